@@ -1,3 +1,23 @@
+; The virtual address the kernel is linked at, the first GiB of physical
+; memory is mapped to this address plus the KASLR slide
+KERNEL_OFFSET equ 0xffffffff80000000
+
+; The number of 2 MiB slots the kernel can be slid to, this needs to be a
+; power of two and the kernel can be moved up to 512 MiB
+KASLR_SLOTS equ 256
+
+; The physical addresses of the boot structures. We can't subtract the
+; KERNEL_OFFSET from a label here because a relocatable (KASLR) build
+; can't have that relocation, instead the linker script gives us the
+; physical address of the start of the bss and rodata as absolute symbols
+; and we add the offset of the label from the start
+%define BSS_PHYSICAL(x) (boot_bss_physical + (x - boot_bss))
+%define RODATA_PHYSICAL(x) (boot_rodata_physical + (x - boot_rodata))
+
+extern boot_bss_physical
+extern boot_rodata_physical
+extern kaslr_relocations_size
+
 section .multiboot_header
 header_start:
     dd 0xe85250d6                ; magic number (multiboot 2)
@@ -7,12 +27,27 @@ header_start:
     ; checksum
     dd 0x100000000 - (0xe85250d6 + 0 + (header_end - header_start))
 
+    ; Ask for a linear framebuffer, the tag is optional so GRUB can still
+    ; boot us in text mode. The console draws on it with a PSF font
+    align 8
+    dw 5    ; type (framebuffer)
+    dw 1    ; flags (optional)
+    dd 20   ; size
+    dd 1024 ; width
+    dd 768  ; height
+    dd 32   ; depth
+
+    ; The tags are 8 byte aligned
+    align 8
     dw 0    ; type
     dw 0    ; flags
     dd 8    ; size
 header_end:
 
-section .text
+; The boot code is linked at the physical address because it runs before
+; paging is enabled, everything else is linked in the higher half so we
+; need the physical address when we access it from here
+section .boot.text progbits alloc exec nowrite align=16
 
 bits 32
 
@@ -21,59 +56,207 @@ extern boot_entry64
 
 boot_entry:
     ; Setup a stack
-    mov esp, stack_top
+    mov esp, BSS_PHYSICAL(stack_top)
 
     ; Save the pointer to the multiboot strucuture
     ; This is passed on to the kernel later when we enter the kernel
     mov edi, ebx
 
-    ; Setup a identity map of physical memory
+    ; Pick where we should put the kernel, the slide is passed on to the
+    ; 64 bit code inside esi
+    call choose_slide
+
+    ; Setup a identity map and the higher half map of physical memory
     call setup_page_tables
     ; Enable paging
     call enable_paging
     ; Load the 64 bit GDT
     call load_gdt
 
-    ; Long jump to boot_entry64 and from that point we can execute 
+    ; Long jump to boot_entry64 and from that point we can execute
     ; 64 bit instructions
     jmp 0x0008:boot_entry64
     hlt
 
+; Pick a random slide for the kernel and return it inside esi, the slide
+; is 0 if the kernel is not relocatable or if "nokaslr" is on the command
+; line
+choose_slide:
+    xor esi, esi
+
+    ; A kernel without relocations can only run where it's linked
+    mov eax, kaslr_relocations_size
+    test eax, eax
+    jz .done
+
+    call kaslr_disabled
+    test eax, eax
+    jnz .done
+
+    ; The slide is a multiple of 2 MiB so the kernel can still be mapped
+    ; with huge pages
+    call random
+    and eax, KASLR_SLOTS - 1
+    shl eax, 21
+    mov esi, eax
+
+.done:
+    ret
+
+; Check if "nokaslr" is on the command line, returns 1 inside eax if it is
+kaslr_disabled:
+    ; The first tag is after the total size and the reserved field
+    lea edx, [edi + 8]
+
+.next_tag:
+    mov eax, [edx]
+
+    ; Tag type 0 is the end tag
+    test eax, eax
+    jz .not_found
+
+    ; Tag type 1 is the command line
+    cmp eax, 1
+    je .command_line
+
+    ; The tags are 8 byte aligned
+    mov eax, [edx + 4]
+    add eax, 7
+    and eax, ~7
+    add edx, eax
+    jmp .next_tag
+
+.command_line:
+    ; The string is after the type and the size
+    add edx, 8
+
+.compare:
+    cmp byte [edx], 0
+    je .not_found
+
+    cmp dword [edx], 'noka'
+    jne .next_char
+    cmp word [edx + 4], 'sl'
+    jne .next_char
+    cmp byte [edx + 6], 'r'
+    jne .next_char
+
+    mov eax, 1
+    ret
+
+.next_char:
+    inc edx
+    jmp .compare
+
+.not_found:
+    xor eax, eax
+    ret
+
+; Get a random number inside eax, we use RDRAND if the CPU has it and mix
+; it with the time stamp counter. The time stamp counter alone is not very
+; random this early but it's better than nothing
+random:
+    push ebx
+
+    rdtsc
+    mov ebp, eax
+
+    ; CPUID.01H:ECX bit 30 tells us if the CPU has RDRAND
+    mov eax, 1
+    cpuid
+    bt ecx, 30
+    jnc .done
+
+    ; RDRAND can fail if the CPU is out of entropy so try a few times
+    mov ecx, 10
+.retry:
+    rdrand eax
+    jc .mix
+    loop .retry
+    jmp .done
+
+.mix:
+    xor ebp, eax
+
+.done:
+    mov eax, ebp
+    pop ebx
+    ret
+
 setup_page_tables:
     ; Set the first entry inside the p4_table to the p3_table
-    mov eax, p3_table
+    mov eax, BSS_PHYSICAL(p3_table)
     ; Set the present and writable bits
-    or eax, 0b11 
+    or eax, 0b11
     ; Add the entry to the first slot in the p4_table
-    mov [p4_table], eax
+    mov [BSS_PHYSICAL(p4_table)], eax
+
+    ; The higher half starts at the last entry inside the p4 table
+    mov eax, BSS_PHYSICAL(p3_high_table)
+    or eax, 0b11
+    mov [BSS_PHYSICAL(p4_table) + 511 * 8], eax
 
-    ; We need to the the 511th entry inside the p4 table to itself
-    ; so we can later recursively map table entries later in the kernel
-    mov eax, p4_table
-    or eax, 0b11 
-    mov [p4_table + 511 * 8], eax
+    ; We need to the the 510th entry inside the p4 table to itself
+    ; so we can later recursively map table entries later in the kernel,
+    ; the 511th entry is used by the higher half
+    mov eax, BSS_PHYSICAL(p4_table)
+    or eax, 0b11
+    mov [BSS_PHYSICAL(p4_table) + 510 * 8], eax
 
     ; We do the same for the p3 table
-    mov eax, p2_table
+    mov eax, BSS_PHYSICAL(p2_table)
+    or eax, 0b11
+    mov [BSS_PHYSICAL(p3_table)], eax
+
+    ; KERNEL_OFFSET is inside the 510th entry of the higher half p3 table,
+    ; with the slide the map can cross into the 511th entry so we use two
+    ; p2 tables after each other
+    mov eax, BSS_PHYSICAL(p2_high_tables)
     or eax, 0b11
-    mov [p3_table], eax
+    mov [BSS_PHYSICAL(p3_high_table) + 510 * 8], eax
+    add eax, 4096
+    mov [BSS_PHYSICAL(p3_high_table) + 511 * 8], eax
+
+    mov ecx, 0
 
 ; Inside the p2 table we need to map all 512 entries to a physical address
 .map_p2_table:
     mov eax, 0x200000
     mul ecx
     or eax, 0b10000011
-    mov [p2_table + ecx * 8], eax
+    mov [BSS_PHYSICAL(p2_table) + ecx * 8], eax
 
     inc ecx
     cmp ecx, 512
     jne .map_p2_table
 
+    mov ecx, 0
+
+; Map the first GiB of physical memory at KERNEL_OFFSET plus the slide
+.map_p2_high_tables:
+    ; The physical address is the offset from KERNEL_OFFSET minus the
+    ; slide, the entries below the slide are left unmapped
+    mov eax, ecx
+    shl eax, 21
+    sub eax, esi
+    jb .next_high_entry
+
+    cmp eax, 0x40000000
+    jae .next_high_entry
+
+    or eax, 0b10000011
+    mov [BSS_PHYSICAL(p2_high_tables) + ecx * 8], eax
+
+.next_high_entry:
+    inc ecx
+    cmp ecx, 1024
+    jne .map_p2_high_tables
+
     ret
 
 ; Function to enable paging
 enable_paging:
-    mov eax, p4_table
+    mov eax, BSS_PHYSICAL(p4_table)
     mov cr3, eax
 
     mov eax, cr4
@@ -92,27 +275,47 @@ enable_paging:
 
 ; Function to load the gdt
 load_gdt:
-    lgdt[gdt64.pointer]
+    lgdt[RODATA_PHYSICAL(gdt64_pointer32)]
     ret
 
 section .rodata
+global boot_rodata
+boot_rodata:
+
 ; The GDT we load for 64 bit
 gdt64:
-    dq 0 
-    dq (1<<43) | (1<<44) | (1<<47) | (1<<53) 
-.pointer:
-    dw $ - gdt64 - 1
+    dq 0
+    dq (1<<43) | (1<<44) | (1<<47) | (1<<53)
+gdt64_end:
+
+; The pointer to the GDT we load when we are inside the higher half
+global gdt64_pointer
+gdt64_pointer:
+    dw gdt64_end - gdt64 - 1
     dq gdt64
 
+; The same pointer but with the physical address of the GDT, this is the
+; pointer we load before we enter the higher half
+gdt64_pointer32:
+    dw gdt64_end - gdt64 - 1
+    dd RODATA_PHYSICAL(gdt64)
+
 section .bss
 ; The paging tables we need for 64 bit transition
 align 4096
+global boot_bss
+boot_bss:
 p4_table:
     resb 4096
 p3_table:
     resb 4096
+p3_high_table:
+    resb 4096
 p2_table:
     resb 4096
+p2_high_tables:
+    resb 4096 * 2
+global stack_top
 stack_bottom:
     resb 4096 * 4
 stack_top:
@@ -1,8 +1,19 @@
-section .text
+section .boot.text progbits alloc exec nowrite align=16
 bits 64
 
+; The virtual address the kernel is linked at, this needs to match boot.asm
+KERNEL_OFFSET equ 0xffffffff80000000
+
+; The only relocation type a relocatable kernel has, the value is the base
+; plus the addend
+R_X86_64_RELATIVE equ 8
+
 global boot_entry64
 extern kernel_entry
+extern gdt64_pointer
+extern stack_top
+extern kaslr_relocations_physical
+extern kaslr_relocations_size
 
 boot_entry64:
     ; Set the segments to the null entry inside the GDT
@@ -13,7 +24,79 @@ boot_entry64:
     mov fs, ax
     mov gs, ax
 
-    ; Call the kernel entry
+    ; The upper half of the registers is undefined after the switch to
+    ; 64 bit, rdi has the multiboot address and rsi the slide
+    mov edi, edi
+    mov esi, esi
+
+    ; Move the kernel to the slide before anything inside the higher half
+    ; uses an absolute address
+    call apply_relocations
+
+    ; We are still running from the identity map so jump to the
+    ; higher half
+    mov rax, higher_half_entry
+    jmp rax
+
+; Add the slide inside rsi to every absolute address inside the kernel,
+; the relocations are written through the identity map. This is done even
+; when the slide is 0 because the linker leaves the places empty
+apply_relocations:
+    mov rbx, kaslr_relocations_physical
+    mov rcx, kaslr_relocations_size
+    add rcx, rbx
+    mov r8, KERNEL_OFFSET
+
+.next:
+    cmp rbx, rcx
+    jae .done
+
+    ; The kernel is linked with every symbol resolved so the relative
+    ; relocations is the only type we should see, skip anything else
+    mov eax, [rbx + 8]
+    cmp eax, R_X86_64_RELATIVE
+    jne .skip
+
+    ; Get the physical address of the place to relocate, the boot code is
+    ; linked at the physical address
+    mov rdx, [rbx]
+    cmp rdx, r8
+    jb .low_place
+    sub rdx, r8
+.low_place:
+
+    ; Only the addresses inside the higher half moves
+    mov rax, [rbx + 16]
+    cmp rax, r8
+    jb .low_value
+    add rax, rsi
+.low_value:
+
+    mov [rdx], rax
+
+.skip:
+    ; Every relocation is an Elf64_Rela of 24 bytes
+    add rbx, 24
+    jmp .next
+
+.done:
+    ret
+
+section .text
+
+higher_half_entry:
+    ; Move the stack to the higher half
+    mov rsp, stack_top
+
+    ; Reload the GDT with the higher half address so we don't
+    ; depend on the identity map
+    mov rax, gdt64_pointer
+    lgdt [rax]
+
+    ; Clear the frame pointer so a backtrace knows where the stack ends
+    xor ebp, ebp
+
+    ; Call the kernel entry with the multiboot address and the slide
     call kernel_entry
 
     hlt
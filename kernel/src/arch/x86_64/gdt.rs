@@ -0,0 +1,104 @@
+//! The Global Descriptor Table and the Task State Segment, the GDT from
+//! boot.asm only has a code segment so we replace it with this table to get
+//! a TSS with the Interrupt Stack Table
+
+/// The code segment selector, this is the same as inside the GDT from
+/// boot.asm so the CS we already have stays valid
+pub const KERNEL_CODE_SELECTOR: u16 = 0x08;
+
+/// The selector for the TSS, the descriptor uses two entries
+pub const TSS_SELECTOR: u16 = 0x10;
+
+/// The number of entries inside the GDT
+const GDT_ENTRIES: usize = 4;
+
+/// A 64 bit code segment
+const CODE_SEGMENT: u64 = (1 << 43) | (1 << 44) | (1 << 47) | (1 << 53);
+
+/// Present and an available 64 bit TSS
+const TSS_DESCRIPTOR: u64 = (0x9 << 40) | (1 << 47);
+
+/// The index inside the Interrupt Stack Table for the double fault stack
+pub const DOUBLE_FAULT_IST_INDEX: usize = 0;
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct TaskStateSegment {
+    reserved0: u32,
+
+    /// The stacks the CPU switches to when the privilege level changes
+    pub privilege_stack_table: [u64; 3],
+    reserved1: u64,
+
+    /// The stacks the CPU switches to for the IDT entries with an IST
+    /// index, the entry uses the index + 1
+    pub interrupt_stack_table: [u64; 7],
+    reserved2: u64,
+    reserved3: u16,
+
+    pub iomap_base: u16,
+}
+
+impl TaskStateSegment {
+    pub const fn new() -> TaskStateSegment {
+        TaskStateSegment {
+            reserved0: 0,
+            privilege_stack_table: [0; 3],
+            reserved1: 0,
+            interrupt_stack_table: [0; 7],
+            reserved2: 0,
+            reserved3: 0,
+
+            // We don't have an IO permission bitmap
+            iomap_base: core::mem::size_of::<TaskStateSegment>() as u16,
+        }
+    }
+}
+
+pub struct Gdt {
+    entries: [u64; GDT_ENTRIES],
+}
+
+impl Gdt {
+    pub const fn new() -> Gdt {
+        Gdt {
+            entries: [0, CODE_SEGMENT, 0, 0],
+        }
+    }
+
+    /// Set the TSS descriptor to point to `tss`, the TSS needs to stay at
+    /// the same address for as long as the table is loaded
+    pub fn set_tss(&mut self, tss: &TaskStateSegment) {
+        let base = tss as *const _ as u64;
+        let limit = (core::mem::size_of::<TaskStateSegment>() - 1) as u64;
+
+        let index = (TSS_SELECTOR / 8) as usize;
+        self.entries[index] = TSS_DESCRIPTOR |
+            (limit & 0xffff) |
+            ((base & 0xffffff) << 16) |
+            (((limit >> 16) & 0xf) << 48) |
+            (((base >> 24) & 0xff) << 56);
+        self.entries[index + 1] = base >> 32;
+    }
+
+    /// Load the table with `lgdt` and the TSS with `ltr`, the table needs
+    /// to stay at the same address for as long as it's loaded
+    pub unsafe fn load(&self) {
+        #[repr(C, packed)]
+        struct GdtPointer {
+            limit: u16,
+            base: u64,
+        }
+
+        let pointer = GdtPointer {
+            limit: (core::mem::size_of::<Gdt>() - 1) as u16,
+            base: self as *const _ as u64,
+        };
+
+        asm!("lgdt [{0}]",
+             in(reg) &pointer);
+
+        asm!("ltr {0:x}",
+             in(reg) TSS_SELECTOR);
+    }
+}
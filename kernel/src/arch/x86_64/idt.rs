@@ -0,0 +1,136 @@
+//! The Interrupt Descriptor Table, the table the CPU uses to find the
+//! handler for an exception or an interrupt
+
+use super::gdt::KERNEL_CODE_SELECTOR;
+
+/// Present, DPL 0 and a 64 bit interrupt gate
+const INTERRUPT_GATE: u8 = 0x8e;
+
+/// The number of entries inside the IDT
+const IDT_ENTRIES: usize = 256;
+
+pub const DEBUG_VECTOR: u8 = 1;
+pub const BREAKPOINT_VECTOR: u8 = 3;
+pub const DOUBLE_FAULT_VECTOR: u8 = 8;
+pub const GENERAL_PROTECTION_VECTOR: u8 = 13;
+pub const PAGE_FAULT_VECTOR: u8 = 14;
+
+/// The stack frame the CPU pushes before it calls the handler
+#[repr(C)]
+#[derive(Debug)]
+pub struct InterruptStackFrame {
+    pub instruction_pointer: u64,
+    pub code_segment: u64,
+    pub cpu_flags: u64,
+    pub stack_pointer: u64,
+    pub stack_segment: u64,
+}
+
+#[allow(dead_code)]
+pub type HandlerFunc = 
+    extern "x86-interrupt" fn(&mut InterruptStackFrame);
+pub type HandlerFuncWithErrorCode = 
+    extern "x86-interrupt" fn(&mut InterruptStackFrame, u64);
+
+#[derive(Copy, Clone)]
+#[repr(C, packed)]
+pub struct IdtEntry {
+    offset_low: u16,
+    selector: u16,
+    ist: u8,
+    options: u8,
+    offset_middle: u16,
+    offset_high: u32,
+    reserved: u32,
+}
+
+impl IdtEntry {
+    /// An entry without a handler, the CPU raises a general protection
+    /// fault if the vector is used
+    const fn missing() -> IdtEntry {
+        IdtEntry {
+            offset_low: 0,
+            selector: 0,
+            ist: 0,
+            options: 0,
+            offset_middle: 0,
+            offset_high: 0,
+            reserved: 0,
+        }
+    }
+
+    fn set_handler_address(&mut self, address: u64) {
+        self.offset_low = address as u16;
+        self.offset_middle = (address >> 16) as u16;
+        self.offset_high = (address >> 32) as u32;
+        self.selector = KERNEL_CODE_SELECTOR;
+        self.options = INTERRUPT_GATE;
+    }
+
+    /// Make the CPU switch to the stack at `index` inside the Interrupt
+    /// Stack Table of the TSS before it calls the handler
+    pub fn set_stack_index(&mut self, index: usize) {
+        // Zero means don't switch stack so the field is the index + 1
+        self.ist = index as u8 + 1;
+    }
+}
+
+pub struct Idt {
+    entries: [IdtEntry; IDT_ENTRIES],
+}
+
+impl Idt {
+    pub const fn new() -> Idt {
+        Idt {
+            entries: [IdtEntry::missing(); IDT_ENTRIES],
+        }
+    }
+
+    /// Set the handler for `vector` for the exceptions and interrupts
+    /// where the CPU doesn't push an error code
+    #[allow(dead_code)]
+    pub fn set_handler(&mut self, vector: u8, handler: HandlerFunc) 
+        -> &mut IdtEntry
+    {
+        let entry = &mut self.entries[vector as usize];
+        entry.set_handler_address(handler as usize as u64);
+        entry
+    }
+
+    /// Set the handler for `vector` for the exceptions where the CPU
+    /// pushes an error code
+    pub fn set_handler_with_error_code(&mut self, vector: u8,
+                                       handler: HandlerFuncWithErrorCode)
+        -> &mut IdtEntry
+    {
+        let entry = &mut self.entries[vector as usize];
+        entry.set_handler_address(handler as usize as u64);
+        entry
+    }
+
+    /// Set the entry for `vector` to the code at `address`, this is for
+    /// the entries written in assembly e.g. the ones inside `trap`
+    pub fn set_entry(&mut self, vector: u8, address: u64) -> &mut IdtEntry {
+        let entry = &mut self.entries[vector as usize];
+        entry.set_handler_address(address);
+        entry
+    }
+
+    /// Load the table with `lidt`, the table needs to stay at the same 
+    /// address for as long as it's loaded
+    pub unsafe fn load(&self) {
+        #[repr(C, packed)]
+        struct IdtPointer {
+            limit: u16,
+            base: u64,
+        }
+
+        let pointer = IdtPointer {
+            limit: (core::mem::size_of::<Idt>() - 1) as u16,
+            base: self as *const _ as u64,
+        };
+
+        asm!("lidt [{0}]",
+             in(reg) &pointer);
+    }
+}
@@ -1,3 +1,8 @@
+pub mod idt;
+pub mod gdt;
+pub mod pic;
+pub mod trap;
+
 #[allow(dead_code)]
 pub fn cr3() -> u64 {
     let result: u64;
@@ -17,3 +22,305 @@ pub fn set_cr3(value: u64) {
              in(reg) value);
     }
 }
+
+/// The model specific register for the Extended Feature Enable Register
+pub const IA32_EFER: u32 = 0xc0000080;
+
+/// EFER bit to enable the No-Execute bit inside the page tables
+pub const EFER_NXE: u64 = 1 << 11;
+
+/// CR0 bit to make the kernel respect the write bit inside the page tables
+pub const CR0_WP: u64 = 1 << 16;
+
+#[allow(dead_code)]
+pub fn cr0() -> u64 {
+    let result: u64;
+
+    unsafe {
+        asm!("mov {0}, cr0",
+             out(reg) result);
+    }
+
+    result
+}
+
+#[allow(dead_code)]
+pub fn set_cr0(value: u64) {
+    unsafe {
+        asm!("mov cr0, {0}",
+             in(reg) value);
+    }
+}
+
+#[allow(dead_code)]
+pub fn rdmsr(msr: u32) -> u64 {
+    let low: u32;
+    let high: u32;
+
+    unsafe {
+        asm!("rdmsr",
+             in("ecx") msr,
+             out("eax") low,
+             out("edx") high);
+    }
+
+    (high as u64) << 32 | (low as u64)
+}
+
+#[allow(dead_code)]
+pub fn wrmsr(msr: u32, value: u64) {
+    let low = value as u32;
+    let high = (value >> 32) as u32;
+
+    unsafe {
+        asm!("wrmsr",
+             in("ecx") msr,
+             in("eax") low,
+             in("edx") high);
+    }
+}
+
+#[allow(dead_code)]
+pub fn invlpg(address: u64) {
+    unsafe {
+        asm!("invlpg [{0}]",
+             in(reg) address);
+    }
+}
+
+/// Flush the whole TLB by reloading CR3
+#[allow(dead_code)]
+pub fn flush_tlb() {
+    set_cr3(cr3());
+}
+
+/// Get the address that caused the last page fault
+#[allow(dead_code)]
+pub fn cr2() -> u64 {
+    let result: u64;
+    
+    unsafe {
+        asm!("mov {0}, cr2",
+             out(reg) result);
+    }
+
+    result
+}
+
+/// The model specific register for the Page Attribute Table
+pub const IA32_PAT: u32 = 0x277;
+
+/// CPUID leaf 1 EDX bit for the Page Attribute Table
+pub const CPUID_EDX_PAT: u32 = 1 << 16;
+
+/// Execute CPUID for `leaf` and return EAX, EBX, ECX and EDX
+#[allow(dead_code)]
+pub fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
+    let eax: u32;
+    let ebx: u64;
+    let ecx: u32;
+    let edx: u32;
+
+    unsafe {
+        // LLVM uses RBX internally so we can't use it as an operand, save
+        // it inside another register while we execute CPUID
+        asm!("mov {0}, rbx",
+             "cpuid",
+             "xchg {0}, rbx",
+             out(reg) ebx,
+             inout("eax") leaf => eax,
+             inout("ecx") 0 => ecx,
+             out("edx") edx);
+    }
+
+    (eax, ebx as u32, ecx, edx)
+}
+
+/// Write back and invalidate all the caches
+#[allow(dead_code)]
+pub fn wbinvd() {
+    unsafe {
+        asm!("wbinvd");
+    }
+}
+
+/// Read a byte from the I/O `port`
+#[allow(dead_code)]
+pub fn inb(port: u16) -> u8 {
+    let value: u8;
+
+    unsafe {
+        asm!("in al, dx",
+             out("al") value,
+             in("dx") port);
+    }
+
+    value
+}
+
+/// Write the byte `value` to the I/O `port`
+#[allow(dead_code)]
+pub fn outb(port: u16, value: u8) {
+    unsafe {
+        asm!("out dx, al",
+             in("dx") port,
+             in("al") value);
+    }
+}
+
+/// Switch the stack pointer to `stack_top` and call `function`, the old
+/// stack is never used again
+pub unsafe fn call_on_stack(stack_top: u64, function: fn() -> !) -> ! {
+    // The frame pointer is cleared so a backtrace stops at `function`
+    asm!("mov rsp, {0}",
+         "xor ebp, ebp",
+         "call {1}",
+         in(reg) stack_top,
+         in(reg) function,
+         options(noreturn));
+}
+
+/// The bit inside RFLAGS that is set when the interrupts are enabled
+const RFLAGS_IF: u64 = 1 << 9;
+
+#[allow(dead_code)]
+pub fn enable_interrupts() {
+    unsafe {
+        asm!("sti");
+    }
+}
+
+#[allow(dead_code)]
+pub fn disable_interrupts() {
+    unsafe {
+        asm!("cli");
+    }
+}
+
+/// Check if the interrupts are enabled
+pub fn interrupts_enabled() -> bool {
+    rflags() & RFLAGS_IF != 0
+}
+
+/// Run `f` with the interrupts disabled, the interrupts are enabled again
+/// afterwards if they were enabled before. Use this around locks an
+/// interrupt handler can take so the handler can't deadlock on them
+pub fn without_interrupts<F, R>(f: F) -> R
+    where F: FnOnce() -> R
+{
+    let enabled = interrupts_enabled();
+
+    if enabled {
+        disable_interrupts();
+    }
+
+    let result = f();
+
+    if enabled {
+        enable_interrupts();
+    }
+
+    result
+}
+
+/// Wait for the next interrupt
+#[allow(dead_code)]
+pub fn halt() {
+    unsafe {
+        asm!("hlt");
+    }
+}
+
+/// Stop the CPU for good, only an NMI wakes the CPU up and then it halts
+/// again
+pub fn halt_forever() -> ! {
+    loop {
+        unsafe {
+            asm!("cli",
+                 "hlt");
+        }
+    }
+}
+
+/// Reset the machine with the keyboard controller, if that doesn't work
+/// we load an empty IDT so the next exception is a triple fault
+pub fn reboot() -> ! {
+    disable_interrupts();
+
+    // Wait until the controller can take the command
+    for _ in 0..0x10000 {
+        if inb(0x64) & 0x02 == 0 {
+            break;
+        }
+    }
+
+    // Pulse the reset line of the CPU
+    outb(0x64, 0xfe);
+
+    let empty_idt = [0u16; 5];
+
+    unsafe {
+        asm!("lidt [{0}]",
+             "int3",
+             in(reg) &empty_idt);
+    }
+
+    halt_forever();
+}
+
+/// Get RFLAGS
+pub fn rflags() -> u64 {
+    let flags: u64;
+
+    unsafe {
+        asm!("pushfq",
+             "pop {0}",
+             out(reg) flags);
+    }
+
+    flags
+}
+
+/// Get the frame pointer, the kernel is built with the frame pointers so
+/// this points to the saved frame pointer of the current function
+#[inline(always)]
+pub fn rbp() -> u64 {
+    let result: u64;
+
+    unsafe {
+        asm!("mov {0}, rbp",
+             out(reg) result);
+    }
+
+    result
+}
+
+#[inline(always)]
+pub fn rsp() -> u64 {
+    let result: u64;
+
+    unsafe {
+        asm!("mov {0}, rsp",
+             out(reg) result);
+    }
+
+    result
+}
+
+pub fn cr4() -> u64 {
+    let result: u64;
+
+    unsafe {
+        asm!("mov {0}, cr4",
+             out(reg) result);
+    }
+
+    result
+}
+
+/// Get the local APIC ID of the CPU we are running on, CPUID leaf 1 has it
+/// inside the highest byte of EBX
+pub fn apic_id() -> u8 {
+    let (_, ebx, _, _) = cpuid(1);
+    (ebx >> 24) as u8
+}
@@ -0,0 +1,108 @@
+//! Driver for the two 8259 Programmable Interrupt Controllers, the PICs
+//! deliver the legacy IRQs of the PC. The firmware leaves the IRQs on the
+//! vectors of the CPU exceptions so we move them after the exceptions
+
+use super::{inb, outb};
+
+// The command and data ports of the master and the slave PIC
+const MASTER_COMMAND: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_COMMAND: u16 = 0xa0;
+const SLAVE_DATA: u16 = 0xa1;
+
+// ICW1 to start the initialization, ICW4 is sent aswell
+const ICW1_INIT: u8 = 0x11;
+// ICW4 for the 8086 mode
+const ICW4_8086: u8 = 0x01;
+
+/// The command to read the In-Service Register
+const OCW3_READ_ISR: u8 = 0x0b;
+
+/// The command that ends the interrupt
+const END_OF_INTERRUPT: u8 = 0x20;
+
+/// The IRQ of the master the slave is connected to
+const CASCADE_IRQ: u8 = 2;
+
+/// The vector of IRQ 0, the IRQs of the slave starts at `IRQ_OFFSET + 8`
+pub const IRQ_OFFSET: u8 = 0x20;
+
+pub const KEYBOARD_IRQ: u8 = 1;
+pub const COM2_IRQ: u8 = 3;
+
+/// The IRQs the PICs raise for a spurious interrupt, e.g. when the IRQ
+/// went away before the CPU acknowledged it
+pub const SPURIOUS_MASTER_IRQ: u8 = 7;
+pub const SPURIOUS_SLAVE_IRQ: u8 = 15;
+
+/// Write to a PIC port and wait for the PIC, the old PICs need some time
+/// between the writes and port 0x80 is unused so a write to it is a delay
+fn write(port: u16, value: u8) {
+    outb(port, value);
+    outb(0x80, 0);
+}
+
+/// Move the IRQs to `IRQ_OFFSET` with every IRQ masked, only the cascade
+/// to the slave is left unmasked
+pub fn init() {
+    write(MASTER_COMMAND, ICW1_INIT);
+    write(SLAVE_COMMAND, ICW1_INIT);
+
+    // ICW2 is the vector offset
+    write(MASTER_DATA, IRQ_OFFSET);
+    write(SLAVE_DATA, IRQ_OFFSET + 8);
+
+    // ICW3 tells the master which IRQ has the slave and tells the slave
+    // its cascade identity
+    write(MASTER_DATA, 1 << CASCADE_IRQ);
+    write(SLAVE_DATA, CASCADE_IRQ);
+
+    write(MASTER_DATA, ICW4_8086);
+    write(SLAVE_DATA, ICW4_8086);
+
+    write(MASTER_DATA, !(1 << CASCADE_IRQ));
+    write(SLAVE_DATA, 0xff);
+}
+
+/// Get the data port and the bit of `irq`
+fn irq_port(irq: u8) -> (u16, u8) {
+    if irq < 8 {
+        (MASTER_DATA, 1 << irq)
+    } else {
+        (SLAVE_DATA, 1 << (irq - 8))
+    }
+}
+
+/// Let the PIC deliver `irq`
+pub fn unmask(irq: u8) {
+    let (port, bit) = irq_port(irq);
+    outb(port, inb(port) & !bit);
+}
+
+/// Stop the PIC from delivering `irq`
+#[allow(dead_code)]
+pub fn mask(irq: u8) {
+    let (port, bit) = irq_port(irq);
+    outb(port, inb(port) | bit);
+}
+
+/// Tell the PICs we are done with `irq`, the IRQs of the slave needs to be
+/// ended on both PICs
+pub fn end_of_interrupt(irq: u8) {
+    if irq >= 8 {
+        outb(SLAVE_COMMAND, END_OF_INTERRUPT);
+    }
+
+    outb(MASTER_COMMAND, END_OF_INTERRUPT);
+}
+
+/// Check if the PIC is handling `irq`, a spurious IRQ is not inside the
+/// In-Service Register
+pub fn in_service(irq: u8) -> bool {
+    let (port, bit) = irq_port(irq);
+
+    // The command port is next to the data port
+    let command = port - 1;
+    outb(command, OCW3_READ_ISR);
+    inb(command) & bit != 0
+}
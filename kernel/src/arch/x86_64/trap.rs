@@ -0,0 +1,126 @@
+//! Entries for the exceptions and the interrupts that need every register,
+//! e.g. the debugger that shows and changes them. The x86 interrupt ABI only
+//! gives the handler the frame the CPU pushed, so these entries push the
+//! general purpose registers aswell and call `handle_trap` with the
+//! `TrapFrame`. The registers are loaded back from the frame afterwards so
+//! the handler can change them
+
+/// The registers of the interrupted code, the entries push the vector, an
+/// error code (0 if the CPU has none) and the general purpose registers
+/// below the frame the CPU pushed
+#[repr(C)]
+#[derive(Debug)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+
+    pub vector: u64,
+    pub error_code: u64,
+
+    // Pushed by the CPU
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+// The CPU aligns the stack to 16 bytes before it pushes its frame, with
+// the 17 registers we push below it the stack is still aligned when we
+// call `handle_trap`. The vector of COM2 is IRQ 3 after the PIC remap
+global_asm!(r#"
+.section .text
+
+.global trap_debug
+trap_debug:
+    push 0
+    push 1
+    jmp trap_common
+
+.global trap_breakpoint
+trap_breakpoint:
+    push 0
+    push 3
+    jmp trap_common
+
+.global trap_com2
+trap_com2:
+    push 0
+    push 0x23
+    jmp trap_common
+
+trap_common:
+    push rax
+    push rbx
+    push rcx
+    push rdx
+    push rsi
+    push rdi
+    push rbp
+    push r8
+    push r9
+    push r10
+    push r11
+    push r12
+    push r13
+    push r14
+    push r15
+
+    mov rdi, rsp
+    cld
+    call handle_trap
+
+    pop r15
+    pop r14
+    pop r13
+    pop r12
+    pop r11
+    pop r10
+    pop r9
+    pop r8
+    pop rbp
+    pop rdi
+    pop rsi
+    pop rdx
+    pop rcx
+    pop rbx
+    pop rax
+
+    add rsp, 16
+    iretq
+"#);
+
+extern "C" {
+    fn trap_debug();
+    fn trap_breakpoint();
+    fn trap_com2();
+}
+
+/// The entry for the debug exception
+pub fn debug_entry() -> u64 {
+    trap_debug as *const u8 as u64
+}
+
+/// The entry for the breakpoint exception (INT3)
+pub fn breakpoint_entry() -> u64 {
+    trap_breakpoint as *const u8 as u64
+}
+
+/// The entry for the IRQ of COM2, the GDB stub stops the kernel from it
+/// when GDB sends Ctrl-C
+pub fn com2_entry() -> u64 {
+    trap_com2 as *const u8 as u64
+}
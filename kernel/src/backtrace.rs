@@ -0,0 +1,91 @@
+//! Walk the kernel stack with the frame pointers, the kernel is built with
+//! the frame pointers forced on so every frame starts with the frame
+//! pointer of the caller followed by the return address. The boot code and
+//! `call_on_stack` clears the frame pointer so the chain ends with 0
+
+use crate::memory::{self, VirtualAddress};
+use crate::symbols;
+
+/// The most frames we print, a broken chain might go on forever
+const MAX_FRAMES: usize = 32;
+
+/// The start of the higher half, the kernel stacks are all above this
+const HIGHER_HALF: u64 = 0xffff_8000_0000_0000;
+
+/// Check that we can read the frame at `frame_pointer` without a page
+/// fault, a fault inside the panic handler would lose the report
+fn frame_is_readable(frame_pointer: u64) -> bool {
+    if frame_pointer < HIGHER_HALF || frame_pointer % 8 != 0 {
+        return false;
+    }
+
+    // The return address might be on the next page
+    let end = match frame_pointer.checked_add(15) {
+        Some(end) => end,
+        None => return false,
+    };
+
+    memory::translate(VirtualAddress(frame_pointer)).is_some() &&
+        memory::translate(VirtualAddress(end)).is_some()
+}
+
+/// Call `f` with the return address of every frame from `frame_pointer`
+/// and up
+pub fn walk<F>(mut frame_pointer: u64, mut f: F)
+    where F: FnMut(u64)
+{
+    for _ in 0..MAX_FRAMES {
+        if !frame_is_readable(frame_pointer) {
+            break;
+        }
+
+        let frame = frame_pointer as *const u64;
+        let (next, return_address) = unsafe {
+            (*frame, *frame.add(1))
+        };
+
+        if return_address == 0 {
+            break;
+        }
+
+        f(return_address);
+
+        // The stack grows down so the frames of the callers are always
+        // higher up, anything else is a broken chain
+        if next <= frame_pointer {
+            break;
+        }
+
+        frame_pointer = next;
+    }
+}
+
+/// Print the backtrace from `frame_pointer` with `print`, the return
+/// addresses are printed with the function they are inside of if we have
+/// the symbol table
+pub fn print<F>(frame_pointer: u64, mut print: F)
+    where F: FnMut(core::fmt::Arguments)
+{
+    let mut index = 0;
+
+    walk(frame_pointer, |return_address| {
+        // The return address is after the call, the address before it is
+        // inside the call instruction and gives the right function for
+        // calls at the end of a function
+        match symbols::lookup(return_address - 1) {
+            Some(symbol) =>
+                print(format_args!("{:>3}: {:#x} {}+{:#x}\n", index,
+                                   return_address, symbol.name,
+                                   symbol.offset + 1)),
+            None =>
+                print(format_args!("{:>3}: {:#x}\n", index,
+                                   return_address)),
+        }
+
+        index += 1;
+    });
+
+    if index == 0 {
+        print(format_args!("  <no frames>\n"));
+    }
+}
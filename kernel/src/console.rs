@@ -0,0 +1,783 @@
+//! The virtual consoles, every console keeps its own grid of cells, runs
+//! the escape sequences and keeps a scrollback history. Only the active
+//! console is drawn to the display, which is the VGA text buffer or a
+//! framebuffer with a PSF font. The kernel log from `println!` goes to the
+//! log console and Alt+F1 to Alt+F4 switches between the consoles
+
+use spin::Mutex;
+use terminal::ansi::{self, Action, Erase};
+use terminal::cp437::{self, Utf8Decoder};
+use crate::serial;
+use crate::vga_buffer::{self, Color, VgaText};
+use crate::fb_console::FramebufferText;
+use crate::keyboard::{Key, KeyEvent};
+use crate::arch::x86_64;
+
+// The largest grid we keep, this is a 1024x768 framebuffer with an 8x16
+// font. A larger framebuffer only uses the top left corner
+const MAX_COLUMNS: usize = 128;
+const MAX_ROWS: usize = 48;
+
+// The distance between the tab stops
+const TAB_WIDTH: u32 = 8;
+
+// The number of lines we keep after they are scrolled off the screen
+const SCROLLBACK_LINES: usize = 500;
+
+// The scanlines of the default underline cursor
+pub const CURSOR_START: u8 = 14;
+pub const CURSOR_END: u8 = 15;
+
+// The number of virtual consoles, console 0 is the log console
+// TODO(patrik): Allocate the buffers of the consoles when we have a heap,
+// every console is about 140 KiB inside the kernel image
+pub const CONSOLE_COUNT: usize = 4;
+pub const LOG_CONSOLE: usize = 0;
+
+pub static CONSOLES: Mutex<Consoles> = Mutex::new(Consoles {
+    consoles: [
+        Console::new(true), Console::new(false),
+        Console::new(false), Console::new(false),
+    ],
+    active: LOG_CONSOLE,
+});
+
+// The display is shared by the consoles, only the active console draws
+// on it. The consoles are always locked before the display
+static DISPLAY: Mutex<Display> = Mutex::new(Display::Vga(VgaText::new()));
+
+// Where the console draws the cells
+pub enum Display {
+    Vga(VgaText),
+    Framebuffer(FramebufferText),
+}
+
+impl Display {
+    // The size of the grid for the display
+    fn size(&self) -> (usize, usize) {
+        match self {
+            Display::Vga(_) =>
+                (vga_buffer::BUFFER_WIDTH, vga_buffer::BUFFER_HEIGHT),
+            Display::Framebuffer(framebuffer) =>
+                (framebuffer.columns().max(1).min(MAX_COLUMNS),
+                 framebuffer.rows().max(1).min(MAX_ROWS)),
+        }
+    }
+
+    fn write_entry(&mut self, column: usize, row: usize, entry: u16) {
+        match self {
+            Display::Vga(vga) => vga.write_entry(column, row, entry),
+            Display::Framebuffer(framebuffer) =>
+                framebuffer.write_entry(column, row, entry),
+        }
+    }
+
+    // Move the first `rows` rows of the grid one row up, the last row is
+    // left as it is. The VGA grid is always the full buffer
+    fn scroll_up(&mut self, rows: usize) {
+        match self {
+            Display::Vga(vga) => vga.scroll_up(),
+            Display::Framebuffer(framebuffer) => framebuffer.scroll_up(rows),
+        }
+    }
+}
+
+// The VGA colors for the ANSI colors, the order of the colors is not the
+// same
+const ANSI_COLORS: [Color; 8] = [
+    Color::Black, Color::Red, Color::Green, Color::Brown,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::LightGray,
+];
+
+const ANSI_BRIGHT_COLORS: [Color; 8] = [
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+    Color::LightBlue, Color::Pink, Color::LightCyan, Color::White,
+];
+
+fn ansi_color(color: ansi::Color, bright: bool) -> Color {
+    if bright {
+        ANSI_BRIGHT_COLORS[color as usize]
+    } else {
+        ANSI_COLORS[color as usize]
+    }
+}
+
+fn encode_color(foreground: Color, background: Color) -> u8 {
+    (background as u8) << 4 | (foreground as u8)
+}
+
+pub struct Consoles {
+    consoles: [Console; CONSOLE_COUNT],
+    active: usize,
+}
+
+impl Consoles {
+    // Move the VGA buffer to the higher half alias of the slid kernel, this
+    // needs to be done before we print anything
+    pub fn update_address(&mut self) {
+        if let Display::Vga(ref mut vga) = *DISPLAY.lock() {
+            vga.update_address();
+        }
+    }
+
+    // Draw the consoles on `display` from now on, the text of the consoles
+    // is kept and the grids are resized to the new display
+    pub fn set_display(&mut self, display: Display) {
+        let (columns, rows) = display.size();
+        *DISPLAY.lock() = display;
+
+        for console in self.consoles.iter_mut() {
+            console.resize(columns, rows);
+            console.view_offset = 0;
+        }
+
+        self.active().redraw();
+    }
+
+    // Change the scanlines the cursor covers, the scanlines are for a 16
+    // scanline high character cell
+    pub fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        match *DISPLAY.lock() {
+            Display::Vga(ref mut vga) => vga.set_cursor_shape(start, end),
+            Display::Framebuffer(ref mut framebuffer) =>
+                framebuffer.set_cursor_shape(start, end),
+        }
+
+        self.active().update_cursor();
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Console> {
+        self.consoles.iter_mut()
+    }
+
+    // The console that is on the display
+    pub fn active(&mut self) -> &mut Console {
+        &mut self.consoles[self.active]
+    }
+
+    // Show console `index` on the display
+    pub fn switch_to(&mut self, index: usize) {
+        if index >= CONSOLE_COUNT || index == self.active {
+            return;
+        }
+
+        self.consoles[self.active].active = false;
+        self.active = index;
+
+        let console = &mut self.consoles[index];
+        console.active = true;
+        console.redraw();
+    }
+}
+
+pub struct Console {
+    // Only the active console draws on the display, the other consoles
+    // only update the copy of the screen
+    active: bool,
+
+    // The size of the grid, the cells are `columns` entries apart inside
+    // the screen
+    columns: usize,
+    rows: usize,
+
+    x: u32,
+    y: u32,
+
+    foreground_color: Color,
+    background_color: Color,
+
+    // The colors we go back to when the attributes are reset
+    default_foreground: Color,
+    default_background: Color,
+
+    // Use the bright version of the foreground color
+    bold: bool,
+    // Swap the foreground and the background color
+    reverse: bool,
+
+    // The cursor saved by the save cursor sequence
+    saved_x: u32,
+    saved_y: u32,
+
+    parser: ansi::Parser,
+
+    // The characters are written as UTF-8 and we print the glyphs of code
+    // page 437, `replacement` is printed for the characters without one
+    utf8: Utf8Decoder,
+    replacement: u8,
+
+    cursor_visible: bool,
+
+    // A copy of the screen, when we are scrolled back the display shows
+    // the history and this is the only copy of the screen
+    screen: [u16; MAX_COLUMNS * MAX_ROWS],
+
+    // The lines scrolled off the screen, this is a ring buffer where
+    // `history_start` is the oldest line
+    history: [[u16; MAX_COLUMNS]; SCROLLBACK_LINES],
+    history_start: usize,
+    history_count: usize,
+
+    // The number of lines we are scrolled back into the history
+    view_offset: usize,
+}
+
+impl Console {
+    const fn new(active: bool) -> Console {
+        Console {
+            active: active,
+            columns: vga_buffer::BUFFER_WIDTH,
+            rows: vga_buffer::BUFFER_HEIGHT,
+            x: 0,
+            y: 0,
+            foreground_color: Color::White,
+            background_color: Color::Black,
+            default_foreground: Color::White,
+            default_background: Color::Black,
+            bold: false,
+            reverse: false,
+            saved_x: 0,
+            saved_y: 0,
+            parser: ansi::Parser::new(),
+            utf8: Utf8Decoder::new(),
+            replacement: cp437::DEFAULT_REPLACEMENT,
+            cursor_visible: true,
+            screen: [0; MAX_COLUMNS * MAX_ROWS],
+            history: [[0; MAX_COLUMNS]; SCROLLBACK_LINES],
+            history_start: 0,
+            history_count: 0,
+            view_offset: 0,
+        }
+    }
+
+    // Change the size of the grid, when there are fewer rows the top lines
+    // go to the history
+    fn resize(&mut self, columns: usize, rows: usize) {
+        let old_columns = self.columns;
+        let old_rows = self.rows;
+
+        let skip = old_rows.saturating_sub(rows);
+        for row in 0..skip {
+            let start = row * old_columns;
+            self.push_history(start, old_columns);
+        }
+
+        self.screen.copy_within(skip * old_columns..old_rows * old_columns,
+                                0);
+
+        // Move the lines to the new line width, we go backwards when the
+        // lines get longer so we don't overwrite a line before it's moved
+        let kept_rows = old_rows - skip;
+        let copy_columns = old_columns.min(columns);
+
+        let mut move_line = |row: usize| {
+            let src = row * old_columns;
+            self.screen.copy_within(src..src + copy_columns, row * columns);
+        };
+
+        if columns > old_columns {
+            (0..kept_rows).rev().for_each(&mut move_line);
+        } else {
+            (0..kept_rows).for_each(&mut move_line);
+        }
+
+        self.columns = columns;
+        self.rows = rows;
+
+        // Clear the new cells at the end of the lines and below the lines
+        let color = encode_color(self.foreground_color,
+                                 self.background_color);
+        let blank = (color as u16) << 8 | b' ' as u16;
+
+        for row in 0..rows {
+            let start = if row < kept_rows { copy_columns } else { 0 };
+
+            for column in start..columns {
+                self.screen[column + row * columns] = blank;
+            }
+        }
+
+        self.y = self.y.saturating_sub(skip as u32);
+        self.move_cursor(self.x as i32, self.y as i32);
+        self.saved_x = self.saved_x.min(columns as u32 - 1);
+        self.saved_y = self.saved_y.min(rows as u32 - 1);
+    }
+
+    // Clear the screen
+    pub fn clear(&mut self, clear_color: Color) {
+        let color = encode_color(Color::White, clear_color);
+
+        for offset in 0..(self.columns * self.rows) {
+            self.write_cell(offset, b' ', color);
+        }
+
+        self.x = 0;
+        self.y = 0;
+        self.update_cursor();
+    }
+
+    // Set the colors and make them the default colors the escape
+    // sequences can reset to
+    pub fn set_color(&mut self, foreground: Color, background: Color) {
+        self.foreground_color = foreground;
+        self.background_color = background;
+        self.default_foreground = foreground;
+        self.default_background = background;
+    }
+
+    // The attribute byte for the current colors
+    fn color(&self) -> u8 {
+        let mut foreground = self.foreground_color as u8;
+        let mut background = self.background_color as u8;
+
+        if self.bold {
+            foreground |= 8;
+        }
+
+        if self.reverse {
+            core::mem::swap(&mut foreground, &mut background);
+        }
+
+        background << 4 | foreground
+    }
+
+    // Write the character and the attribute to the cell at `offset`
+    fn write_cell(&mut self, offset: usize, character: u8, color: u8) {
+        let entry = (color as u16) << 8 | (character as u16);
+        self.screen[offset] = entry;
+
+        // The history or another console is on the display so only update
+        // the copy
+        if self.active && self.view_offset == 0 {
+            DISPLAY.lock().write_entry(offset % self.columns,
+                                       offset / self.columns, entry);
+        }
+    }
+
+    // Draw the rows of the screen, or the history and the top of the
+    // screen if we are scrolled back
+    fn redraw_rows(&mut self, rows: core::ops::Range<usize>) {
+        if !self.active {
+            return;
+        }
+
+        let mut display = DISPLAY.lock();
+
+        for row in rows {
+            for column in 0..self.columns {
+                let entry = if row < self.view_offset {
+                    let line = self.history_count - self.view_offset + row;
+                    let index = (self.history_start + line) % SCROLLBACK_LINES;
+                    self.history[index][column]
+                } else {
+                    let row = row - self.view_offset;
+                    self.screen[column + row * self.columns]
+                };
+
+                display.write_entry(column, row, entry);
+            }
+        }
+    }
+
+    fn redraw(&mut self) {
+        self.redraw_rows(0..self.rows);
+        self.update_cursor();
+    }
+
+    // Scroll the view `lines` lines back into the history
+    pub fn scroll_back(&mut self, lines: usize) {
+        let offset = (self.view_offset + lines).min(self.history_count);
+
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+        }
+    }
+
+    // Scroll the view `lines` lines forward to the screen
+    pub fn scroll_forward(&mut self, lines: usize) {
+        let offset = self.view_offset.saturating_sub(lines);
+
+        if offset != self.view_offset {
+            self.view_offset = offset;
+            self.redraw();
+        }
+    }
+
+    // Scroll back half a screen, this is for Shift+PageUp
+    pub fn page_up(&mut self) {
+        self.scroll_back(self.rows / 2);
+    }
+
+    // Scroll forward half a screen, this is for Shift+PageDown
+    pub fn page_down(&mut self) {
+        self.scroll_forward(self.rows / 2);
+    }
+
+    // Move the cursor on the display to our cursor, the cursor is hidden
+    // while we are scrolled back
+    fn update_cursor(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        let cursor = if self.cursor_visible && self.view_offset == 0 {
+            // The cursor can be one past the last column before it wraps
+            let x = self.x.min(self.columns as u32 - 1) as usize;
+            Some((x, self.y as usize))
+        } else {
+            None
+        };
+
+        let columns = self.columns;
+
+        match *DISPLAY.lock() {
+            Display::Vga(ref mut vga) => vga.set_cursor(cursor),
+
+            // The framebuffer has no hardware cursor so we draw it into
+            // the cell, the cell under the old cursor is drawn again first
+            Display::Framebuffer(ref mut framebuffer) => {
+                if let Some((column, row)) = framebuffer.cursor() {
+                    let entry = self.screen[column + row * columns];
+                    framebuffer.write_entry(column, row, entry);
+                }
+
+                if let Some((column, row)) = cursor {
+                    let entry = self.screen[column + row * columns];
+                    framebuffer.draw_cursor(column, row, entry);
+                }
+            }
+        }
+    }
+
+    // Clear the cells from `start` to `end` (exclusive) with the current
+    // background color
+    fn erase(&mut self, start: usize, end: usize) {
+        let color = encode_color(self.foreground_color,
+                                 self.background_color);
+
+        for offset in start..end {
+            self.write_cell(offset, b' ', color);
+        }
+    }
+
+    // Save `count` cells from `start` inside the screen as the newest line
+    // of the history, when the history is full we replace the oldest line
+    fn push_history(&mut self, start: usize, count: usize) {
+        let index = (self.history_start + self.history_count) %
+            SCROLLBACK_LINES;
+
+        let line = &mut self.history[index];
+        line[..count].copy_from_slice(&self.screen[start..start + count]);
+        line[count..].iter_mut().for_each(|x| *x = 0);
+
+        if self.history_count < SCROLLBACK_LINES {
+            self.history_count += 1;
+        } else {
+            self.history_start = (self.history_start + 1) % SCROLLBACK_LINES;
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        let columns = self.columns;
+        let size = columns * self.rows;
+
+        self.push_history(0, columns);
+        self.screen.copy_within(columns..size, 0);
+
+        // The history is on the display while we are scrolled back, the
+        // display shows the new screen when we scroll forward again
+        if self.active && self.view_offset == 0 {
+            DISPLAY.lock().scroll_up(self.rows);
+        }
+
+        self.erase(size - columns, size);
+    }
+
+    fn newline(&mut self) {
+        self.y += 1;
+        self.x = 0;
+
+        // Check if the y is over the height then we should scroll the
+        // screen
+        if self.y as usize >= self.rows {
+            // Scroll the screen up
+            self.scroll_up();
+            // Reset the y
+            self.y = self.rows as u32 - 1;
+        }
+    }
+
+    // Move the cursor to `x` and `y`, the cursor stops at the edges
+    fn move_cursor(&mut self, x: i32, y: i32) {
+        let max_x = self.columns as i32 - 1;
+        let max_y = self.rows as i32 - 1;
+
+        self.x = x.max(0).min(max_x) as u32;
+        self.y = y.max(0).min(max_y) as u32;
+    }
+
+    // Print the character at the cursor, the cursor wraps to the next line
+    // when we print past the last column
+    fn print_character(&mut self, character: u8) {
+        if self.x as usize >= self.columns {
+            self.newline();
+        }
+
+        let offset = self.x as usize + self.y as usize * self.columns;
+        let color = self.color();
+        self.write_cell(offset, character, color);
+
+        // Increment the x cordinate
+        self.x += 1;
+    }
+
+    // Decode the next byte of a UTF-8 character and print the glyph of the
+    // character when it is complete
+    fn print_utf8(&mut self, byte: u8) {
+        // The decoder is copied out so the closure can borrow the console
+        let mut decoder = self.utf8;
+        decoder.advance(byte, |character| {
+            let glyph = character.and_then(cp437::from_char)
+                .unwrap_or(self.replacement);
+            self.print_character(glyph);
+        });
+        self.utf8 = decoder;
+    }
+
+    // Set the glyph printed for the characters code page 437 doesn't have
+    #[allow(dead_code)]
+    pub fn set_replacement(&mut self, glyph: u8) {
+        self.replacement = glyph;
+    }
+
+    // Run the action from the escape sequence parser
+    fn execute(&mut self, action: Action) {
+        let columns = self.columns;
+        let size = columns * self.rows;
+
+        let x = self.x.min(columns as u32 - 1) as i32;
+        let y = self.y as i32;
+
+        // The offset of the cursor and the start of the line
+        let cursor = x as usize + y as usize * columns;
+        let line = y as usize * columns;
+
+        match action {
+            Action::Print(byte) => self.print_utf8(byte),
+            Action::Newline => self.newline(),
+            Action::CarriageReturn => self.x = 0,
+
+            Action::Tab => {
+                let next = (self.x / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.x = next.min(columns as u32 - 1);
+            }
+
+            Action::Backspace => self.move_cursor(x - 1, y),
+
+            Action::CursorUp(count) => self.move_cursor(x, y - count as i32),
+            Action::CursorDown(count) => self.move_cursor(x, y + count as i32),
+            Action::CursorForward(count) =>
+                self.move_cursor(x + count as i32, y),
+            Action::CursorBack(count) => self.move_cursor(x - count as i32, y),
+            Action::CursorColumn(column) => self.move_cursor(column as i32, y),
+            Action::CursorPosition { row, column } =>
+                self.move_cursor(column as i32, row as i32),
+
+            Action::EraseDisplay(erase) => match erase {
+                Erase::ToEnd => self.erase(cursor, size),
+                Erase::ToStart => self.erase(0, cursor + 1),
+                Erase::All => self.erase(0, size),
+            },
+
+            Action::EraseLine(erase) => match erase {
+                Erase::ToEnd => self.erase(cursor, line + columns),
+                Erase::ToStart => self.erase(line, cursor + 1),
+                Erase::All => self.erase(line, line + columns),
+            },
+
+            Action::SaveCursor => {
+                self.saved_x = self.x;
+                self.saved_y = self.y;
+            }
+
+            Action::RestoreCursor => {
+                self.x = self.saved_x;
+                self.y = self.saved_y;
+            }
+
+            Action::ShowCursor(visible) => self.cursor_visible = visible,
+
+            Action::ResetAttributes => {
+                self.foreground_color = self.default_foreground;
+                self.background_color = self.default_background;
+                self.bold = false;
+                self.reverse = false;
+            }
+
+            Action::Bold(bold) => self.bold = bold,
+            Action::Reverse(reverse) => self.reverse = reverse,
+
+            Action::Foreground(color, bright) =>
+                self.foreground_color = ansi_color(color, bright),
+            Action::Background(color, bright) =>
+                self.background_color = ansi_color(color, bright),
+
+            Action::DefaultForeground =>
+                self.foreground_color = self.default_foreground,
+            Action::DefaultBackground =>
+                self.background_color = self.default_background,
+        }
+    }
+
+    // Write the bytes to the console, the bytes can have escape sequences
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        // New output always shows the screen again
+        if self.view_offset != 0 {
+            self.view_offset = 0;
+            self.redraw();
+        }
+
+        // Loop through all the bytes and write them to the screen
+        for &byte in bytes {
+            self.write_byte(byte)
+        }
+
+        self.update_cursor();
+    }
+
+    // Function to print a single character to the screen, the escape
+    // sequences are handled by the parser
+    fn write_byte(&mut self, character: u8) {
+        // The parser is copied out so the actions can borrow the console
+        let mut parser = self.parser;
+        parser.advance(character, |action| self.execute(action));
+        self.parser = parser;
+    }
+}
+
+// Implement the fmt Write so we can use the write! macro and
+// so we can implement the print macro
+impl core::fmt::Write for Console {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+}
+
+// Print the format arguments to the log console, the output is mirrored to
+// the serial port with the escape sequences untouched so a terminal on the
+// other side shows the same thing as the screen
+pub fn print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    // The keyboard interrupt uses the consoles aswell
+    x86_64::without_interrupts(|| {
+        CONSOLES.lock().consoles[LOG_CONSOLE].write_fmt(args).unwrap();
+        serial::SERIAL.lock().write_fmt(args).unwrap();
+    });
+}
+
+// Print the format arguments from the panic handler and the fault
+// handlers, the code that panicked or faulted might hold the locks of the
+// consoles or of the serial port and it's never going to release them so
+// we take them by force. The log console is switched to so the message is
+// on the screen
+pub fn emergency_print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    // Nothing else can run after this, the keyboard interrupt would
+    // otherwise take the locks from under us
+    x86_64::disable_interrupts();
+
+    // The serial port is first, it has the least that can go wrong
+    unsafe { serial::SERIAL.force_unlock(); }
+    let _ = serial::SERIAL.lock().write_fmt(args);
+
+    unsafe {
+        CONSOLES.force_unlock();
+        DISPLAY.force_unlock();
+    }
+
+    let mut consoles = CONSOLES.lock();
+    consoles.switch_to(LOG_CONSOLE);
+    let _ = consoles.active().write_fmt(args);
+}
+
+// Print the format arguments only to the serial port without the lock,
+// this is for a panic inside the panic handler where the console itself
+// might be what panics
+pub fn serial_emergency_print(args: core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    x86_64::disable_interrupts();
+
+    unsafe { serial::SERIAL.force_unlock(); }
+    let _ = serial::SERIAL.lock().write_fmt(args);
+}
+
+// Handle a key from the keyboard, this is called from the keyboard
+// interrupt
+pub fn handle_key(event: KeyEvent) {
+    let mut consoles = CONSOLES.lock();
+
+    match event.key {
+        // Alt+Fn (and Ctrl+Alt+Fn like Linux) switches to console n
+        Key::Function(number) if event.alt => {
+            consoles.switch_to(number as usize - 1);
+        }
+
+        Key::PageUp if event.shift => consoles.active().page_up(),
+        Key::PageDown if event.shift => consoles.active().page_down(),
+
+        _ => {
+            // The log console is only for the kernel log, the other
+            // consoles echo the keys
+            // TODO(patrik): Give the keys to the shell of the console when
+            // we have one
+            if consoles.active == LOG_CONSOLE {
+                return;
+            }
+
+            let console = consoles.active();
+
+            match event.key {
+                // The control characters are echoed like the terminals do
+                Key::Character(character)
+                    if event.ctrl && character.is_ascii_alphabetic() =>
+                    console.write_bytes(
+                        &[b'^', character.to_ascii_uppercase()]),
+                Key::Character(character) =>
+                    console.write_bytes(&[character]),
+                Key::Enter => console.write_bytes(b"\n"),
+                Key::Backspace => console.write_bytes(b"\x08 \x08"),
+                Key::Tab => console.write_bytes(b"\t"),
+                _ => {}
+            }
+        }
+    }
+}
+
+macro_rules! print {
+    ($($arg:tt)*) => ({
+        $crate::console::print(format_args!($($arg)*));
+    });
+}
+
+macro_rules! println {
+    ($fmt:expr) => (print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+}
+
+// The print macros for the panic handler, these never wait for a lock
+macro_rules! emergency_print {
+    ($($arg:tt)*) => ({
+        $crate::console::emergency_print(format_args!($($arg)*));
+    });
+}
+
+macro_rules! emergency_println {
+    ($fmt:expr) => (emergency_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) =>
+        (emergency_print!(concat!($fmt, "\n"), $($arg)*));
+}
@@ -0,0 +1,261 @@
+//! The text display for the linear framebuffer, the console cells are drawn
+//! with the glyphs of a PSF font. GRUB loads the font for us as a multiboot2
+//! module with the name "font", and a boot logo with the name "logo" that
+//! is shown above the console
+
+use multiboot2::BootInformation;
+use graphics::{Canvas, Bitmap, Color, image};
+use crate::framebuffer::Framebuffer;
+use terminal::psf::Font;
+use crate::memory::{self, VirtualAddress};
+
+/// The name of the module with the font
+const FONT_MODULE: &str = "font";
+
+/// The name of the module with the boot logo, a BMP or a TGA image
+const LOGO_MODULE: &str = "logo";
+
+/// The RGB values of the 16 VGA colors, the console uses the VGA
+/// attributes so the framebuffer looks the same as the text mode
+const PALETTE: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00), (0x00, 0x00, 0xaa),
+    (0x00, 0xaa, 0x00), (0x00, 0xaa, 0xaa),
+    (0xaa, 0x00, 0x00), (0xaa, 0x00, 0xaa),
+    (0xaa, 0x55, 0x00), (0xaa, 0xaa, 0xaa),
+    (0x55, 0x55, 0x55), (0x55, 0x55, 0xff),
+    (0x55, 0xff, 0x55), (0x55, 0xff, 0xff),
+    (0xff, 0x55, 0x55), (0xff, 0x55, 0xff),
+    (0xff, 0xff, 0x55), (0xff, 0xff, 0xff),
+];
+
+/// The cursor shape is given in the scanlines of a VGA character cell, we
+/// scale it to the height of the font
+const VGA_CELL_HEIGHT: usize = 16;
+
+pub struct FramebufferText {
+    framebuffer: Framebuffer,
+    font: Font,
+
+    /// The pixel values of the palette colors
+    colors: [u32; 16],
+
+    /// The cell we have drawn the cursor on
+    cursor: Option<(usize, usize)>,
+
+    /// The rows of the glyph the cursor covers (inclusive)
+    cursor_start: usize,
+    cursor_end: usize,
+
+    /// The first line of pixels we draw on, the lines above has the logo
+    top: usize,
+}
+
+impl FramebufferText {
+    pub fn new(framebuffer: Framebuffer, font: Font, top: usize)
+        -> FramebufferText
+    {
+        let mut colors = [0; 16];
+        for (color, &(red, green, blue)) in colors.iter_mut().zip(&PALETTE) {
+            *color = framebuffer.color(red, green, blue);
+        }
+
+        let height = font.height();
+
+        FramebufferText {
+            framebuffer: framebuffer,
+            font: font,
+            colors: colors,
+            cursor: None,
+            cursor_start: height.saturating_sub(2),
+            cursor_end: height - 1,
+            top: top,
+        }
+    }
+
+    /// The number of cells that fits on a line
+    pub fn columns(&self) -> usize {
+        self.framebuffer.width() / self.font.width()
+    }
+
+    /// The number of lines that fits on the screen
+    pub fn rows(&self) -> usize {
+        self.framebuffer.height().saturating_sub(self.top) /
+            self.font.height()
+    }
+
+    /// Draw the VGA entry (the character and the attribute) at the cell
+    pub fn write_entry(&mut self, column: usize, row: usize, entry: u16) {
+        self.draw_cell(column, row, entry, false);
+
+        // The cursor was drawn over
+        if self.cursor == Some((column, row)) {
+            self.cursor = None;
+        }
+    }
+
+    /// The cell we have drawn the cursor on, the console needs to draw the
+    /// cell again before the cursor moves
+    pub fn cursor(&self) -> Option<(usize, usize)> {
+        self.cursor
+    }
+
+    /// Draw the entry at the cell with the cursor on top
+    pub fn draw_cursor(&mut self, column: usize, row: usize, entry: u16) {
+        self.draw_cell(column, row, entry, true);
+        self.cursor = Some((column, row));
+    }
+
+    /// Move the first `rows` rows of cells one row up by copying the pixels,
+    /// the last row is left as it is. This is much faster than drawing
+    /// every glyph again
+    pub fn scroll_up(&mut self, rows: usize) {
+        let height = self.font.height();
+
+        self.framebuffer.copy_lines(self.top + height, self.top,
+                                    rows.saturating_sub(1) * height);
+
+        // The cursor moved with the pixels, the cursor on the first row is
+        // gone
+        self.cursor = self.cursor
+            .and_then(|(column, row)| Some((column, row.checked_sub(1)?)));
+    }
+
+    /// Change the scanlines the cursor covers, the scanlines are for a 16
+    /// pixel high cell like the VGA cursor
+    pub fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        let height = self.font.height();
+        let scale = |line: u8| {
+            (line as usize * height / VGA_CELL_HEIGHT).min(height - 1)
+        };
+
+        self.cursor_start = scale(start);
+        self.cursor_end = scale(end).max(self.cursor_start);
+    }
+
+    fn draw_cell(&mut self, column: usize, row: usize, entry: u16,
+                 cursor: bool)
+    {
+        let attribute = (entry >> 8) as usize;
+        let foreground = self.colors[attribute & 0xf];
+        let background = self.colors[(attribute >> 4) & 0xf];
+
+        let glyph = self.font.glyph((entry & 0xff) as usize);
+        let width = self.font.width();
+        let height = self.font.height();
+
+        for y in 0..height {
+            let cursor_line = cursor &&
+                y >= self.cursor_start && y <= self.cursor_end;
+
+            for x in 0..width {
+                let color = if cursor_line || self.font.pixel(glyph, x, y) {
+                    foreground
+                } else {
+                    background
+                };
+
+                self.framebuffer.write_pixel(column * width + x,
+                                             self.top + row * height + y,
+                                             color);
+            }
+        }
+    }
+}
+
+/// Setup the framebuffer text display, returns `None` if the bootloader
+/// didn't give us a framebuffer or a font
+pub fn init(boot_info: &BootInformation) -> Option<FramebufferText> {
+    // The text mode also has a framebuffer tag, we only map the font when
+    // we have a framebuffer we can draw it on
+    let mut framebuffer = Framebuffer::from_multiboot(boot_info)?;
+    let font = load_font(boot_info)?;
+
+    println!("Framebuffer: {}x{} {} bpp, font {}x{}",
+             framebuffer.width(), framebuffer.height(),
+             framebuffer.bits_per_pixel(), font.width(), font.height());
+
+    let top = show_logo(boot_info, &mut framebuffer).unwrap_or(0);
+
+    Some(FramebufferText::new(framebuffer, font, top))
+}
+
+/// Map the font module and parse the font inside it
+fn load_font(boot_info: &BootInformation) -> Option<Font> {
+    // The font is never unmapped so the data lives forever
+    let (address, data) = memory::map_module(boot_info, FONT_MODULE)?;
+
+    let font = Font::parse(data);
+    if font.is_none() {
+        println!("The font module is not a PSF font");
+        memory::vfree(address);
+    }
+
+    font
+}
+
+/// Draw the logo centered at the top of the framebuffer, returns the
+/// height of the logo so the console can start below it
+fn show_logo(boot_info: &BootInformation, framebuffer: &mut Framebuffer)
+    -> Option<usize>
+{
+    let (address, data) = memory::map_module(boot_info, LOGO_MODULE)?;
+    let height = draw_logo(data, framebuffer);
+    memory::vfree(address);
+
+    if height.is_none() {
+        println!("Failed to show the boot logo");
+    }
+
+    height
+}
+
+fn draw_logo(data: &[u8], framebuffer: &mut Framebuffer) -> Option<usize> {
+    let (width, height) = image::size(data)?;
+
+    // Leave most of the screen to the console
+    if width > framebuffer.width() || height > framebuffer.height() / 2 {
+        return None;
+    }
+
+    // The image is decoded into one buffer and drawn on a canvas as wide
+    // as the screen, the canvas is flushed to the screen at once
+    let image_pixels = allocate_pixels(width * height)?;
+    let canvas_pixels = match allocate_pixels(framebuffer.width() * height) {
+        Some(pixels) => pixels,
+        None => {
+            memory::vfree(image_pixels.0);
+            return None;
+        }
+    };
+
+    let result = image::decode(data, image_pixels.1).and_then(|_| {
+        let bitmap = Bitmap::new(image_pixels.1, width, height)?;
+        let mut canvas = Canvas::new(canvas_pixels.1, framebuffer.width(),
+                                     height)?;
+
+        canvas.clear(Color::BLACK);
+        canvas.blit(&bitmap, ((framebuffer.width() - width) / 2) as i32, 0);
+        canvas.flush(framebuffer);
+
+        Some(height)
+    });
+
+    memory::vfree(image_pixels.0);
+    memory::vfree(canvas_pixels.0);
+
+    result
+}
+
+/// Allocate a buffer for `count` pixels with `vmalloc`
+fn allocate_pixels(count: usize)
+    -> Option<(VirtualAddress, &'static mut [Color])>
+{
+    let size = count.checked_mul(core::mem::size_of::<Color>())?;
+    let address = memory::vmalloc(size as u64)?;
+
+    let pixels = unsafe {
+        core::slice::from_raw_parts_mut(address.0 as *mut Color, count)
+    };
+
+    Some((address, pixels))
+}
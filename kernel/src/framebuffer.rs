@@ -0,0 +1,172 @@
+//! Driver for the linear framebuffer the bootloader sets up for us, the
+//! mode is requested inside the multiboot2 header and we get the address
+//! and the pixel layout from the framebuffer tag
+
+use multiboot2::{BootInformation, FramebufferType, FramebufferField};
+use graphics::{Color, Target};
+use crate::memory::{self, PhysicalAddress, CacheMode};
+
+/// The position and the size in bits of a color inside a pixel
+#[derive(Copy, Clone, Debug)]
+struct ColorField {
+    position: u8,
+    size: u8,
+}
+
+impl ColorField {
+    fn from_multiboot(field: &FramebufferField) -> ColorField {
+        ColorField {
+            position: field.position,
+            size: field.size,
+        }
+    }
+
+    /// Scale the 8 bit `value` to the size of the field and move it to the
+    /// position inside the pixel
+    fn encode(&self, value: u8) -> u32 {
+        let value = if self.size >= 8 {
+            (value as u32) << (self.size - 8)
+        } else {
+            (value as u32) >> (8 - self.size)
+        };
+
+        value << self.position
+    }
+}
+
+pub struct Framebuffer {
+    /// The virtual address of the framebuffer
+    address: usize,
+
+    /// The number of bytes between the start of two lines
+    pitch: usize,
+
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+
+    red: ColorField,
+    green: ColorField,
+    blue: ColorField,
+}
+
+impl Framebuffer {
+    /// Map the framebuffer the bootloader gives us, only the direct color
+    /// modes with 32, 24 or 16 bits per pixel are supported
+    pub fn from_multiboot(boot_info: &BootInformation) -> Option<Framebuffer> {
+        let tag = boot_info.framebuffer_tag()?;
+
+        let (red, green, blue) = match tag.buffer_type {
+            FramebufferType::RGB { red, green, blue } =>
+                (ColorField::from_multiboot(&red),
+                 ColorField::from_multiboot(&green),
+                 ColorField::from_multiboot(&blue)),
+
+            // The text mode is the VGA buffer and we don't support the
+            // palette modes
+            _ => return None,
+        };
+
+        let bytes_per_pixel = match tag.bpp {
+            32 => 4,
+            24 => 3,
+            15 | 16 => 2,
+            _ => {
+                println!("Unsupported framebuffer depth: {}", tag.bpp);
+                return None;
+            }
+        };
+
+        let size = tag.pitch as u64 * tag.height as u64;
+        let address = memory::ioremap(PhysicalAddress(tag.address), size,
+                                      CacheMode::WriteCombining)?;
+
+        Some(Framebuffer {
+            address: address.0 as usize,
+            pitch: tag.pitch as usize,
+            width: tag.width as usize,
+            height: tag.height as usize,
+            bytes_per_pixel: bytes_per_pixel,
+            red: red,
+            green: green,
+            blue: blue,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.bytes_per_pixel * 8
+    }
+
+    /// Get the pixel value for the color, the value can be given to
+    /// `write_pixel`
+    pub fn color(&self, red: u8, green: u8, blue: u8) -> u32 {
+        self.red.encode(red) | self.green.encode(green) |
+            self.blue.encode(blue)
+    }
+
+    /// Write the pixel value `color` at `x` and `y`, pixels outside of the
+    /// framebuffer are ignored
+    pub fn write_pixel(&mut self, x: usize, y: usize, color: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let address = self.address + y * self.pitch + x * self.bytes_per_pixel;
+
+        unsafe {
+            match self.bytes_per_pixel {
+                4 => core::ptr::write_volatile(address as *mut u32, color),
+
+                3 => {
+                    let pointer = address as *mut u8;
+                    core::ptr::write_volatile(pointer, color as u8);
+                    core::ptr::write_volatile(pointer.add(1),
+                                              (color >> 8) as u8);
+                    core::ptr::write_volatile(pointer.add(2),
+                                              (color >> 16) as u8);
+                }
+
+                _ => core::ptr::write_volatile(address as *mut u16,
+                                               color as u16),
+            }
+        }
+    }
+
+    /// Copy `count` lines of pixels starting at line `source` to line
+    /// `destination`, the lines can overlap. Lines outside of the
+    /// framebuffer are not copied
+    pub fn copy_lines(&mut self, source: usize, destination: usize,
+                      count: usize)
+    {
+        let count = count.min(self.height.saturating_sub(source))
+            .min(self.height.saturating_sub(destination));
+
+        // The lines are next to each other so this is one copy
+        let source = self.address + source * self.pitch;
+        let destination = self.address + destination * self.pitch;
+
+        unsafe {
+            core::ptr::copy(source as *const u8, destination as *mut u8,
+                            count * self.pitch);
+        }
+    }
+}
+
+// Let the graphics library flush a canvas to the screen, the alpha is
+// ignored because there is nothing behind the screen to blend with
+impl Target for Framebuffer {
+    fn write_span(&mut self, x: usize, y: usize, pixels: &[Color]) {
+        for (i, pixel) in pixels.iter().enumerate() {
+            let color = self.color(pixel.red(), pixel.green(), pixel.blue());
+            self.write_pixel(x + i, y, color);
+        }
+    }
+}
@@ -0,0 +1,754 @@
+//! A stub for the GDB Remote Serial Protocol on COM2, GDB connects to it
+//! with "target remote" on the serial port. The stub can read and write the
+//! registers and the memory, set software breakpoints with INT3 and single
+//! step with the trap flag. The kernel stops inside the stub on an INT3, on
+//! a single step and when GDB sends Ctrl-C, and with "gdb" on the command
+//! line the kernel waits for GDB at boot
+
+use core::fmt::Write;
+use spin::Mutex;
+use crate::arch::x86_64;
+use crate::arch::x86_64::idt::{DEBUG_VECTOR, BREAKPOINT_VECTOR};
+use crate::arch::x86_64::trap::TrapFrame;
+use crate::memory::{self, VirtualAddress};
+use crate::serial::{SerialPort, COM2};
+
+/// The largest packet we take from GDB and the largest reply we send
+const BUFFER_SIZE: usize = 1024;
+
+/// The digits of the hex numbers we send
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+/// The number of software breakpoints GDB can set
+const MAX_BREAKPOINTS: usize = 32;
+
+/// The instruction for a software breakpoint
+const INT3: u8 = 0xcc;
+
+/// The ASCII code GDB sends for Ctrl-C
+const INTERRUPT: u8 = 0x03;
+
+/// The trap flag inside RFLAGS, the CPU raises a debug exception after
+/// every instruction when it is set
+const RFLAGS_TF: u64 = 1 << 8;
+
+/// The write protect bit inside CR0, when it is cleared the kernel can
+/// write to the read only pages
+const CR0_WP: u64 = 1 << 16;
+
+// The signals we report to GDB
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// The thread we report, the kernel only has the one thread for now
+const KERNEL_THREAD: u64 = 1;
+
+/// The number of registers inside a 'g' packet, the general purpose
+/// registers, RIP, EFLAGS and the six segment registers
+const REGISTER_COUNT: usize = 24;
+
+const PAGE_SIZE: u64 = 4096;
+
+static GDB: Mutex<Gdb> = Mutex::new(Gdb::new());
+
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    address: u64,
+
+    /// The byte the INT3 replaced
+    original: u8,
+}
+
+/// What the kernel should do when the stub returns
+enum Resume {
+    Continue,
+    Step,
+}
+
+/// A packet we build to send to GDB
+struct Reply {
+    data: [u8; BUFFER_SIZE],
+    length: usize,
+}
+
+impl Reply {
+    const fn new() -> Reply {
+        Reply {
+            data: [0; BUFFER_SIZE],
+            length: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.length = 0;
+    }
+
+    fn push(&mut self, byte: u8) {
+        if self.length < BUFFER_SIZE {
+            self.data[self.length] = byte;
+            self.length += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            self.push(byte);
+        }
+    }
+
+    fn push_hex(&mut self, byte: u8) {
+        self.push(HEX_DIGITS[(byte >> 4) as usize]);
+        self.push(HEX_DIGITS[(byte & 0xf) as usize]);
+    }
+
+    /// Push the `size` lowest bytes of `value` in little endian, the
+    /// registers are sent in the byte order of the target
+    fn push_value(&mut self, value: u64, size: usize) {
+        for index in 0..size {
+            self.push_hex((value >> (index * 8)) as u8);
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        &self.data[..self.length]
+    }
+}
+
+impl core::fmt::Write for Reply {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+pub struct Gdb {
+    port: SerialPort,
+
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+
+    /// GDB has talked to us, the stop replies are only sent when someone
+    /// is on the other side to take them
+    connected: bool,
+
+    /// The signal of the last stop for the '?' packet
+    signal: u8,
+
+    reply: Reply,
+}
+
+fn hex_digit(byte: u8) -> Option<u8> {
+    match byte {
+        b'0'..=b'9' => Some(byte - b'0'),
+        b'a'..=b'f' => Some(byte - b'a' + 10),
+        b'A'..=b'F' => Some(byte - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big endian hex number, the numbers inside the commands are
+/// written like this
+fn parse_hex(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 16 {
+        return None;
+    }
+
+    bytes.iter().try_fold(0, |value, &byte| {
+        Some(value << 4 | hex_digit(byte)? as u64)
+    })
+}
+
+/// Parse a little endian hex value of a register
+fn parse_value(bytes: &[u8]) -> Option<u64> {
+    if bytes.is_empty() || bytes.len() > 16 || bytes.len() % 2 != 0 {
+        return None;
+    }
+
+    let mut value = 0;
+    for (index, pair) in bytes.chunks(2).enumerate() {
+        let byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        value |= (byte as u64) << (index * 8);
+    }
+
+    Some(value)
+}
+
+/// Split `bytes` at the first `separator`
+fn split(bytes: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let index = bytes.iter().position(|&byte| byte == separator)?;
+    Some((&bytes[..index], &bytes[index + 1..]))
+}
+
+/// Get the register with the number GDB uses and its size in bytes
+fn register(frame: &TrapFrame, number: usize) -> Option<(u64, usize)> {
+    let value = match number {
+        0 => frame.rax,
+        1 => frame.rbx,
+        2 => frame.rcx,
+        3 => frame.rdx,
+        4 => frame.rsi,
+        5 => frame.rdi,
+        6 => frame.rbp,
+        7 => frame.rsp,
+        8 => frame.r8,
+        9 => frame.r9,
+        10 => frame.r10,
+        11 => frame.r11,
+        12 => frame.r12,
+        13 => frame.r13,
+        14 => frame.r14,
+        15 => frame.r15,
+        16 => frame.rip,
+        17 => return Some((frame.rflags, 4)),
+        18 => return Some((frame.cs, 4)),
+        19 => return Some((frame.ss, 4)),
+
+        // The data segments are all the null selector
+        20..=23 => return Some((0, 4)),
+
+        _ => return None,
+    };
+
+    Some((value, 8))
+}
+
+/// Set the register with the number GDB uses, the segment registers stay
+/// the same because the kernel only has the one code segment
+fn set_register(frame: &mut TrapFrame, number: usize, value: u64)
+    -> Option<()>
+{
+    let register = match number {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        18..=23 => return Some(()),
+        _ => return None,
+    };
+
+    *register = value;
+    Some(())
+}
+
+/// Check that every page from `address` and `length` bytes on is mapped
+fn is_mapped(address: u64, length: u64) -> bool {
+    let end = match address.checked_add(length) {
+        Some(end) => end,
+        None => return false,
+    };
+
+    // Both ends need to be canonical and on the same side of the hole,
+    // otherwise we would walk every page of the hole
+    let last = end.saturating_sub(1).max(address);
+    if !memory::is_canonical(address) || !memory::is_canonical(last) ||
+        (address ^ last) >> 63 != 0
+    {
+        return false;
+    }
+
+    let mut page = address & !(PAGE_SIZE - 1);
+    while page < end {
+        if memory::translate(VirtualAddress(page)).is_none() {
+            return false;
+        }
+
+        page += PAGE_SIZE;
+    }
+
+    true
+}
+
+/// Write `data` to `address`, the kernel code is mapped read only so the
+/// write protection is turned off while we write e.g. a breakpoint
+fn write_memory(address: u64, data: &[u8]) -> Option<()> {
+    if !is_mapped(address, data.len() as u64) {
+        return None;
+    }
+
+    let cr0 = x86_64::cr0();
+    x86_64::set_cr0(cr0 & !CR0_WP);
+
+    for (offset, &byte) in data.iter().enumerate() {
+        unsafe {
+            core::ptr::write_volatile((address + offset as u64) as *mut u8,
+                                      byte);
+        }
+    }
+
+    x86_64::set_cr0(cr0);
+
+    Some(())
+}
+
+impl Gdb {
+    const fn new() -> Gdb {
+        Gdb {
+            port: SerialPort::new(COM2),
+            breakpoints: [None; MAX_BREAKPOINTS],
+            connected: false,
+            signal: SIGTRAP,
+            reply: Reply::new(),
+        }
+    }
+
+    /// Wait for the next packet from GDB and acknowledge it, returns the
+    /// length of the packet written to `packet`
+    fn receive_packet(&mut self, packet: &mut [u8; BUFFER_SIZE]) -> usize {
+        loop {
+            // Everything before the start of the packet is thrown away,
+            // e.g. the acknowledgements and a Ctrl-C we are already
+            // stopped for
+            while self.port.read_byte() != b'$' {}
+
+            let mut length = 0;
+            let mut checksum = 0u8;
+            let mut overflow = false;
+
+            loop {
+                let byte = self.port.read_byte();
+                if byte == b'#' {
+                    break;
+                }
+
+                checksum = checksum.wrapping_add(byte);
+
+                if length < BUFFER_SIZE {
+                    packet[length] = byte;
+                    length += 1;
+                } else {
+                    overflow = true;
+                }
+            }
+
+            let high = hex_digit(self.port.read_byte());
+            let low = hex_digit(self.port.read_byte());
+            let expected = match (high, low) {
+                (Some(high), Some(low)) => Some(high << 4 | low),
+                _ => None,
+            };
+
+            // Ask GDB to send the packet again if it was broken
+            if expected != Some(checksum) || overflow {
+                self.port.send(b'-');
+                continue;
+            }
+
+            self.port.send(b'+');
+            self.connected = true;
+            return length;
+        }
+    }
+
+    /// Send `reply` to GDB and wait until GDB has it
+    fn send_reply(&mut self) {
+        loop {
+            let checksum = self.reply.bytes().iter()
+                .fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+
+            self.port.send(b'$');
+            for index in 0..self.reply.length {
+                self.port.send(self.reply.data[index]);
+            }
+            self.port.send(b'#');
+            self.port.send(HEX_DIGITS[(checksum >> 4) as usize]);
+            self.port.send(HEX_DIGITS[(checksum & 0xf) as usize]);
+
+            match self.port.read_byte() {
+                b'-' => continue,
+                _ => return,
+            }
+        }
+    }
+
+    fn send_stop_reply(&mut self) {
+        self.reply.clear();
+        let _ = write!(self.reply, "S{:02x}", self.signal);
+        self.send_reply();
+    }
+
+    /// Talk to GDB until it wants the kernel to run again
+    fn run(&mut self, frame: &mut TrapFrame, signal: u8) {
+        self.signal = signal;
+
+        // GDB is waiting for the reply to the continue or the step that
+        // got us here
+        if self.connected {
+            self.send_stop_reply();
+        }
+
+        let mut packet = [0; BUFFER_SIZE];
+
+        loop {
+            let length = self.receive_packet(&mut packet);
+
+            self.reply.clear();
+
+            let resume = self.handle_packet(frame, &packet[..length]);
+
+            match resume {
+                Some(Resume::Continue) => {
+                    frame.rflags &= !RFLAGS_TF;
+                    return;
+                }
+
+                Some(Resume::Step) => {
+                    frame.rflags |= RFLAGS_TF;
+                    return;
+                }
+
+                None => self.send_reply(),
+            }
+        }
+    }
+
+    /// Handle a packet from GDB, the reply is left inside `reply`. Returns
+    /// how to resume the kernel if the packet resumes it
+    fn handle_packet(&mut self, frame: &mut TrapFrame, packet: &[u8])
+        -> Option<Resume>
+    {
+        let (&command, arguments) = match packet.split_first() {
+            Some(split) => split,
+            None => return None,
+        };
+
+        // An empty reply tells GDB we don't support the packet
+        let result = match command {
+            b'?' => {
+                let _ = write!(self.reply, "S{:02x}", self.signal);
+                Some(())
+            }
+
+            b'g' => {
+                for number in 0..REGISTER_COUNT {
+                    if let Some((value, size)) = register(frame, number) {
+                        self.reply.push_value(value, size);
+                    }
+                }
+
+                Some(())
+            }
+
+            b'G' => self.write_registers(frame, arguments),
+
+            b'p' => parse_hex(arguments)
+                .and_then(|number| register(frame, number as usize))
+                .map(|(value, size)| self.reply.push_value(value, size)),
+
+            b'P' => split(arguments, b'=').and_then(|(number, value)| {
+                set_register(frame, parse_hex(number)? as usize,
+                             parse_value(value)?)?;
+                self.reply.push_str("OK");
+                Some(())
+            }),
+
+            b'm' => self.read_memory(arguments),
+            b'M' => self.write_memory(arguments),
+
+            b'Z' | b'z' => return self.breakpoint_packet(command, arguments),
+
+            b'c' | b's' => {
+                // The optional address to resume at
+                if let Some(address) = parse_hex(arguments) {
+                    frame.rip = address;
+                }
+
+                return Some(if command == b'c' {
+                    Resume::Continue
+                } else {
+                    Resume::Step
+                });
+            }
+
+            b'D' | b'k' => {
+                // Leave the kernel running without any of our breakpoints
+                self.remove_breakpoints();
+                self.connected = false;
+
+                if command == b'D' {
+                    self.reply.push_str("OK");
+                    self.send_reply();
+                }
+
+                return Some(Resume::Continue);
+            }
+
+            b'q' => {
+                self.query(arguments);
+                Some(())
+            }
+
+            // There is only one thread to select and it's always alive
+            b'H' | b'T' => {
+                self.reply.push_str("OK");
+                Some(())
+            }
+
+            _ => Some(()),
+        };
+
+        if result.is_none() {
+            self.reply.clear();
+            self.reply.push_str("E01");
+        }
+
+        None
+    }
+
+    fn write_registers(&mut self, frame: &mut TrapFrame, arguments: &[u8])
+        -> Option<()>
+    {
+        let mut offset = 0;
+
+        for number in 0..REGISTER_COUNT {
+            let (_, size) = register(frame, number)?;
+
+            // GDB might send less registers than we have
+            let value = match arguments.get(offset..offset + size * 2) {
+                Some(value) => parse_value(value)?,
+                None => break,
+            };
+
+            set_register(frame, number, value)?;
+            offset += size * 2;
+        }
+
+        self.reply.push_str("OK");
+        Some(())
+    }
+
+    /// Parse the "address,length" of the memory packets
+    fn memory_range(arguments: &[u8]) -> Option<(u64, u64)> {
+        let (address, length) = split(arguments, b',')?;
+        Some((parse_hex(address)?, parse_hex(length)?))
+    }
+
+    fn read_memory(&mut self, arguments: &[u8]) -> Option<()> {
+        let (address, length) = Gdb::memory_range(arguments)?;
+
+        // Every byte is two characters inside the reply
+        let length = length.min(BUFFER_SIZE as u64 / 2);
+
+        // GDB expects EFAULT when the memory can't be accessed
+        if !is_mapped(address, length) {
+            self.reply.push_str("E14");
+            return Some(());
+        }
+
+        for offset in 0..length {
+            let byte = unsafe {
+                core::ptr::read_volatile((address + offset) as *const u8)
+            };
+
+            self.reply.push_hex(byte);
+        }
+
+        Some(())
+    }
+
+    fn write_memory(&mut self, arguments: &[u8]) -> Option<()> {
+        let (range, data) = split(arguments, b':')?;
+        let (address, length) = Gdb::memory_range(range)?;
+
+        // The length comes from GDB so it can be anything, every byte is
+        // two characters and the bytes need to fit inside our buffer
+        if data.len() as u64 != length.checked_mul(2)? ||
+            length > BUFFER_SIZE as u64 / 2
+        {
+            return None;
+        }
+
+        let mut bytes = [0; BUFFER_SIZE / 2];
+        for (byte, pair) in bytes.iter_mut().zip(data.chunks(2)) {
+            *byte = hex_digit(pair[0])? << 4 | hex_digit(pair[1])?;
+        }
+
+        if write_memory(address, &bytes[..length as usize]).is_none() {
+            self.reply.push_str("E14");
+            return Some(());
+        }
+
+        self.reply.push_str("OK");
+        Some(())
+    }
+
+    /// Handle the 'Z' and 'z' packets, we only have the software
+    /// breakpoints so GDB uses those for everything
+    fn breakpoint_packet(&mut self, command: u8, arguments: &[u8])
+        -> Option<Resume>
+    {
+        let (kind, rest) = match split(arguments, b',') {
+            Some(split) => split,
+            None => return None,
+        };
+
+        if kind != b"0" {
+            return None;
+        }
+
+        let address = split(rest, b',')
+            .and_then(|(address, _)| parse_hex(address));
+
+        // A breakpoint can't be written where there is no memory
+        if let Some(address) = address {
+            if command == b'Z' && !is_mapped(address, 1) {
+                self.reply.push_str("E14");
+                return None;
+            }
+        }
+
+        let result = address.and_then(|address| {
+            if command == b'Z' {
+                self.insert_breakpoint(address)
+            } else {
+                self.remove_breakpoint(address)
+            }
+        });
+
+        match result {
+            Some(()) => self.reply.push_str("OK"),
+            None => self.reply.push_str("E01"),
+        }
+
+        None
+    }
+
+    fn insert_breakpoint(&mut self, address: u64) -> Option<()> {
+        let exists = self.breakpoints.iter().flatten()
+            .any(|breakpoint| breakpoint.address == address);
+
+        if exists {
+            return Some(());
+        }
+
+        let slot = self.breakpoints.iter_mut()
+            .find(|breakpoint| breakpoint.is_none())?;
+
+        if !is_mapped(address, 1) {
+            return None;
+        }
+
+        let original = unsafe {
+            core::ptr::read_volatile(address as *const u8)
+        };
+        write_memory(address, &[INT3])?;
+
+        *slot = Some(Breakpoint {
+            address: address,
+            original: original,
+        });
+
+        Some(())
+    }
+
+    fn remove_breakpoint(&mut self, address: u64) -> Option<()> {
+        let slot = self.breakpoints.iter_mut()
+            .find(|breakpoint| match breakpoint {
+                Some(breakpoint) => breakpoint.address == address,
+                None => false,
+            })?;
+
+        let breakpoint = slot.take()?;
+        write_memory(breakpoint.address, &[breakpoint.original])
+    }
+
+    fn remove_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(breakpoint) = slot.take() {
+                let _ = write_memory(breakpoint.address,
+                                     &[breakpoint.original]);
+            }
+        }
+    }
+
+    fn is_breakpoint(&self, address: u64) -> bool {
+        self.breakpoints.iter().flatten()
+            .any(|breakpoint| breakpoint.address == address)
+    }
+
+    /// Handle the general query packets
+    fn query(&mut self, query: &[u8]) {
+        if query.starts_with(b"Supported") {
+            let _ = write!(self.reply, "PacketSize={:x}", BUFFER_SIZE);
+        } else if query == b"Attached" {
+            // We were always there so GDB should detach and not kill
+            self.reply.push_str("1");
+        } else if query == b"C" {
+            let _ = write!(self.reply, "QC{:x}", KERNEL_THREAD);
+        } else if query == b"fThreadInfo" {
+            // TODO(patrik): List the threads of the scheduler when we have
+            // one, the registers of the other threads come from their
+            // saved contexts
+            let _ = write!(self.reply, "m{:x}", KERNEL_THREAD);
+        } else if query == b"sThreadInfo" {
+            self.reply.push_str("l");
+        } else if query == b"Offsets" {
+            // The symbols of kernel.bin are moved by the KASLR slide
+            let slide = memory::kernel_slide();
+            let _ = write!(self.reply, "Text={:x};Data={:x};Bss={:x}",
+                           slide, slide, slide);
+        }
+    }
+}
+
+/// Setup COM2 for the stub, the entries of the debug exceptions and of the
+/// IRQ of COM2 are installed by `interrupts`
+pub fn init() {
+    let mut gdb = GDB.lock();
+    gdb.port.init();
+    gdb.port.enable_receive_interrupt();
+}
+
+/// Stop the kernel and give it to GDB
+pub fn breakpoint() {
+    unsafe { asm!("int3"); }
+}
+
+/// Handle the debug and the breakpoint exception
+pub fn handle_exception(frame: &mut TrapFrame) {
+    let mut gdb = GDB.lock();
+
+    if frame.vector == BREAKPOINT_VECTOR as u64 {
+        // The CPU leaves RIP after the INT3, GDB wants RIP at the address
+        // of its breakpoint
+        let address = frame.rip.wrapping_sub(1);
+        if gdb.is_breakpoint(address) {
+            frame.rip = address;
+        }
+    } else if frame.vector == DEBUG_VECTOR as u64 {
+        // The single step is done, the trap flag is set again if GDB
+        // wants another one
+        frame.rflags &= !RFLAGS_TF;
+    }
+
+    gdb.run(frame, SIGTRAP);
+}
+
+/// Handle the IRQ of COM2, GDB sends Ctrl-C when it wants to stop the
+/// running kernel
+pub fn handle_interrupt(frame: &mut TrapFrame) {
+    let mut gdb = GDB.lock();
+
+    let mut interrupted = false;
+    while let Some(byte) = gdb.port.try_read_byte() {
+        if byte == INTERRUPT {
+            interrupted = true;
+        }
+    }
+
+    if interrupted {
+        gdb.run(frame, SIGINT);
+    }
+}
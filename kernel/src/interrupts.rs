@@ -0,0 +1,199 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::arch::x86_64;
+use crate::arch::x86_64::idt::{Idt, InterruptStackFrame};
+use crate::arch::x86_64::idt::{PAGE_FAULT_VECTOR, DOUBLE_FAULT_VECTOR};
+use crate::arch::x86_64::idt::{DEBUG_VECTOR, BREAKPOINT_VECTOR};
+use crate::arch::x86_64::idt::GENERAL_PROTECTION_VECTOR;
+use crate::arch::x86_64::trap::{self, TrapFrame};
+use crate::arch::x86_64::gdt::{Gdt, TaskStateSegment, DOUBLE_FAULT_IST_INDEX};
+use crate::arch::x86_64::pic::{self, IRQ_OFFSET, KEYBOARD_IRQ, COM2_IRQ};
+use crate::arch::x86_64::pic::{SPURIOUS_MASTER_IRQ, SPURIOUS_SLAVE_IRQ};
+use crate::memory;
+use crate::keyboard;
+use crate::gdb;
+
+/// The number of pages for the stacks inside the Interrupt Stack Table
+const IST_STACK_PAGES: u64 = 4;
+
+static IDT: Mutex<Idt> = Mutex::new(Idt::new());
+static GDT: Mutex<Gdt> = Mutex::new(Gdt::new());
+static TSS: Mutex<TaskStateSegment> = Mutex::new(TaskStateSegment::new());
+
+/// The number of IRQs of the two PICs
+pub const IRQ_COUNT: usize = 16;
+
+/// The number of times every IRQ was raised, for the monitor
+static IRQ_COUNTS: [AtomicU64; IRQ_COUNT] = {
+    const ZERO: AtomicU64 = AtomicU64::new(0);
+    [ZERO; IRQ_COUNT]
+};
+
+fn count_irq(irq: u8) {
+    IRQ_COUNTS[irq as usize].fetch_add(1, Ordering::Relaxed);
+}
+
+/// Get the number of times `irq` was raised, the spurious IRQs are
+/// counted aswell
+pub fn irq_count(irq: usize) -> u64 {
+    IRQ_COUNTS.get(irq)
+        .map(|count| count.load(Ordering::Relaxed))
+        .unwrap_or(0)
+}
+
+pub fn init() {
+    let mut idt = IDT.lock();
+
+    idt.set_handler_with_error_code(PAGE_FAULT_VECTOR, page_fault_handler);
+    idt.set_handler_with_error_code(DOUBLE_FAULT_VECTOR,
+                                    double_fault_handler);
+    idt.set_handler_with_error_code(GENERAL_PROTECTION_VECTOR,
+                                    general_protection_handler);
+
+    // The debugger needs all the registers so these go through `trap`
+    idt.set_entry(DEBUG_VECTOR, trap::debug_entry());
+    idt.set_entry(BREAKPOINT_VECTOR, trap::breakpoint_entry());
+
+    // The IDT is inside a static so it will never move
+    unsafe { idt.load(); }
+}
+
+/// Load our GDT with a TSS and give the double fault handler its own
+/// stack, this needs the memory manager so it's done after `memory::init`.
+/// A kernel stack overflow faults while the CPU pushes the page fault 
+/// frame, so the double fault handler needs a stack that is known good
+pub fn init_stacks() {
+    let stack = memory::stack::allocate_stack(IST_STACK_PAGES)
+        .expect("Failed to allocate the double fault stack");
+
+    let mut tss = TSS.lock();
+    tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX] = stack.top();
+
+    let mut gdt = GDT.lock();
+    gdt.set_tss(&tss);
+
+    // The GDT and the TSS is inside statics so they will never move
+    unsafe { gdt.load(); }
+
+    IDT.lock().set_handler_with_error_code(DOUBLE_FAULT_VECTOR,
+                                           double_fault_handler)
+        .set_stack_index(DOUBLE_FAULT_IST_INDEX);
+
+    println!("Double fault stack: {:#x} - {:#x}",
+             stack.bottom(), stack.top());
+}
+
+/// Move the IRQs of the PICs after the exceptions, install the IRQ
+/// handlers and start taking interrupts
+pub fn init_irqs() {
+    pic::init();
+
+    {
+        let mut idt = IDT.lock();
+        idt.set_handler(IRQ_OFFSET + KEYBOARD_IRQ, keyboard_handler);
+        idt.set_handler(IRQ_OFFSET + SPURIOUS_MASTER_IRQ,
+                        spurious_master_handler);
+        idt.set_handler(IRQ_OFFSET + SPURIOUS_SLAVE_IRQ,
+                        spurious_slave_handler);
+        idt.set_entry(IRQ_OFFSET + COM2_IRQ, trap::com2_entry());
+    }
+
+    pic::unmask(KEYBOARD_IRQ);
+    pic::unmask(COM2_IRQ);
+    x86_64::enable_interrupts();
+}
+
+/// Called by the entries inside `trap` with the registers of the
+/// interrupted code
+#[no_mangle]
+extern "C" fn handle_trap(frame: &mut TrapFrame) {
+    let vector = frame.vector as u8;
+
+    match vector {
+        DEBUG_VECTOR | BREAKPOINT_VECTOR => gdb::handle_exception(frame),
+
+        _ if vector == IRQ_OFFSET + COM2_IRQ => {
+            count_irq(COM2_IRQ);
+            gdb::handle_interrupt(frame);
+            pic::end_of_interrupt(COM2_IRQ);
+        }
+
+        _ => panic!("Unexpected trap {}\n{:#x?}", vector, frame),
+    }
+}
+
+extern "x86-interrupt" fn keyboard_handler(
+    _stack_frame: &mut InterruptStackFrame)
+{
+    count_irq(KEYBOARD_IRQ);
+    keyboard::handle_interrupt();
+    pic::end_of_interrupt(KEYBOARD_IRQ);
+}
+
+extern "x86-interrupt" fn spurious_master_handler(
+    _stack_frame: &mut InterruptStackFrame)
+{
+    count_irq(SPURIOUS_MASTER_IRQ);
+
+    // A spurious IRQ is not ended, but IRQ 7 can be a real IRQ aswell
+    if pic::in_service(SPURIOUS_MASTER_IRQ) {
+        pic::end_of_interrupt(SPURIOUS_MASTER_IRQ);
+    }
+}
+
+extern "x86-interrupt" fn spurious_slave_handler(
+    _stack_frame: &mut InterruptStackFrame)
+{
+    count_irq(SPURIOUS_SLAVE_IRQ);
+
+    // The master doesn't know the IRQ of the slave was spurious so it's
+    // only ended on the master
+    if pic::in_service(SPURIOUS_SLAVE_IRQ) {
+        pic::end_of_interrupt(SPURIOUS_SLAVE_IRQ);
+    } else {
+        pic::end_of_interrupt(0);
+    }
+}
+
+extern "x86-interrupt" fn page_fault_handler(
+    stack_frame: &mut InterruptStackFrame, error_code: u64)
+{
+    // CR2 contains the address the CPU tried to access
+    let address = x86_64::cr2();
+
+    if memory::handle_page_fault(address, error_code,
+                                 stack_frame.instruction_pointer)
+    {
+        return;
+    }
+
+    panic!("Unhandled page fault at {:#x}", address);
+}
+
+extern "x86-interrupt" fn general_protection_handler(
+    stack_frame: &mut InterruptStackFrame, error_code: u64)
+{
+    // The error code is the segment selector that caused the fault, it's
+    // zero for the other causes e.g. an access to a non canonical address.
+    // The fault could happen while the consoles are locked
+    emergency_println!("General protection fault at {:#x}, error code {:#x}",
+                       stack_frame.instruction_pointer, error_code);
+
+    panic!("General protection fault\n{:#x?}", stack_frame);
+}
+
+extern "x86-interrupt" fn double_fault_handler(
+    stack_frame: &mut InterruptStackFrame, _error_code: u64)
+{
+    // When the stack overflows the page fault can't be delivered, CR2 has
+    // the address inside the guard page the CPU tried to push to
+    let address = x86_64::cr2();
+
+    let instruction_pointer = stack_frame.instruction_pointer;
+    if memory::stack::check_stack_overflow(address, instruction_pointer)
+    {
+        panic!("Kernel stack overflow");
+    }
+
+    panic!("Double fault\n{:#x?}", stack_frame);
+}
@@ -0,0 +1,270 @@
+//! Driver for the PS/2 keyboard, the controller translates the scancodes
+//! to scancode set 1 for us. The scancodes are turned into key events with
+//! the state of the modifier keys and given to the console
+
+use spin::Mutex;
+use crate::arch::x86_64;
+use crate::console;
+use crate::monitor;
+
+/// The data port of the PS/2 controller
+const DATA_PORT: u16 = 0x60;
+
+/// The status port of the PS/2 controller
+const STATUS_PORT: u16 = 0x64;
+
+/// Status bit that is set when there is a byte to read from the data port
+const STATUS_OUTPUT_FULL: u8 = 1 << 0;
+
+/// Status bit that is set when the byte is from the mouse
+const STATUS_MOUSE_DATA: u8 = 1 << 5;
+
+/// The prefix of the scancodes for the extended keys
+const EXTENDED_PREFIX: u8 = 0xe0;
+
+/// The bit of the scancode that is set when the key is released
+const RELEASED: u8 = 0x80;
+
+// The scancodes of the keys that are not characters
+const SCANCODE_ESCAPE: u8 = 0x01;
+const SCANCODE_BACKSPACE: u8 = 0x0e;
+const SCANCODE_TAB: u8 = 0x0f;
+const SCANCODE_ENTER: u8 = 0x1c;
+const SCANCODE_CTRL: u8 = 0x1d;
+const SCANCODE_LEFT_SHIFT: u8 = 0x2a;
+const SCANCODE_RIGHT_SHIFT: u8 = 0x36;
+const SCANCODE_ALT: u8 = 0x38;
+const SCANCODE_CAPS_LOCK: u8 = 0x3a;
+const SCANCODE_F1: u8 = 0x3b;
+const SCANCODE_F10: u8 = 0x44;
+const SCANCODE_F11: u8 = 0x57;
+const SCANCODE_F12: u8 = 0x58;
+const SCANCODE_HOME: u8 = 0x47;
+const SCANCODE_UP: u8 = 0x48;
+const SCANCODE_PAGE_UP: u8 = 0x49;
+const SCANCODE_LEFT: u8 = 0x4b;
+const SCANCODE_RIGHT: u8 = 0x4d;
+const SCANCODE_END: u8 = 0x4f;
+const SCANCODE_DOWN: u8 = 0x50;
+const SCANCODE_PAGE_DOWN: u8 = 0x51;
+const SCANCODE_DELETE: u8 = 0x53;
+
+/// The scancode of Print Screen when Alt is held, without Alt the key has
+/// the extended scancode 0x37
+const SCANCODE_SYSRQ: u8 = 0x54;
+const SCANCODE_PRINT_SCREEN: u8 = 0x37;
+
+/// The characters of scancode set 1 for the US layout, 0 is a key that is
+/// not a character
+const CHARACTERS: [u8; 58] = *b"\
+    \0\x1b1234567890-=\x08\t\
+    qwertyuiop[]\n\0\
+    asdfghjkl;'`\0\\\
+    zxcvbnm,./\0*\0 ";
+
+const SHIFT_CHARACTERS: [u8; 58] = *b"\
+    \0\x1b!@#$%^&*()_+\x08\t\
+    QWERTYUIOP{}\n\0\
+    ASDFGHJKL:\"~\0|\
+    ZXCVBNM<>?\0*\0 ";
+
+pub static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new());
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Key {
+    /// A key with a character, the character has the shift applied
+    Character(u8),
+
+    /// The function keys, F1 is 1
+    Function(u8),
+
+    Enter,
+    Backspace,
+    Tab,
+    Escape,
+    Delete,
+
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+
+    /// Print Screen, Alt+SysRq drops into the monitor
+    SysRq,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct KeyEvent {
+    pub key: Key,
+
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+}
+
+pub struct Keyboard {
+    /// The last scancode was the extended prefix
+    extended: bool,
+
+    left_shift: bool,
+    right_shift: bool,
+    ctrl: bool,
+    alt: bool,
+    caps_lock: bool,
+}
+
+impl Keyboard {
+    const fn new() -> Keyboard {
+        Keyboard {
+            extended: false,
+            left_shift: false,
+            right_shift: false,
+            ctrl: false,
+            alt: false,
+            caps_lock: false,
+        }
+    }
+
+    /// Feed the next scancode to the keyboard, returns the event if the
+    /// scancode pressed a key
+    pub fn decode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == EXTENDED_PREFIX {
+            self.extended = true;
+            return None;
+        }
+
+        let extended = self.extended;
+        self.extended = false;
+
+        let pressed = scancode & RELEASED == 0;
+        let code = scancode & !RELEASED;
+
+        // The right control and alt are the extended versions of the left
+        // keys so we don't care about the prefix for them
+        match code {
+            SCANCODE_CTRL => self.ctrl = pressed,
+            SCANCODE_ALT => self.alt = pressed,
+
+            // The fake shifts some keyboards sends around the extended
+            // keys have the prefix and we ignore them
+            SCANCODE_LEFT_SHIFT if !extended => self.left_shift = pressed,
+            SCANCODE_RIGHT_SHIFT if !extended => self.right_shift = pressed,
+
+            SCANCODE_CAPS_LOCK if pressed => self.caps_lock = !self.caps_lock,
+            _ => {}
+        }
+
+        if !pressed {
+            return None;
+        }
+
+        let shift = self.left_shift || self.right_shift;
+
+        Some(KeyEvent {
+            key: self.key(code, extended, shift)?,
+            shift: shift,
+            ctrl: self.ctrl,
+            alt: self.alt,
+        })
+    }
+
+    fn key(&self, code: u8, extended: bool, shift: bool) -> Option<Key> {
+        // The navigation keys are on the keypad aswell, without num lock
+        // they are the same keys
+        let key = match code {
+            SCANCODE_ESCAPE => Key::Escape,
+            SCANCODE_BACKSPACE => Key::Backspace,
+            SCANCODE_TAB => Key::Tab,
+            SCANCODE_ENTER => Key::Enter,
+
+            SCANCODE_F1..=SCANCODE_F10 =>
+                Key::Function(code - SCANCODE_F1 + 1),
+            SCANCODE_F11 => Key::Function(11),
+            SCANCODE_F12 => Key::Function(12),
+
+            SCANCODE_HOME => Key::Home,
+            SCANCODE_UP => Key::Up,
+            SCANCODE_PAGE_UP => Key::PageUp,
+            SCANCODE_LEFT => Key::Left,
+            SCANCODE_RIGHT => Key::Right,
+            SCANCODE_END => Key::End,
+            SCANCODE_DOWN => Key::Down,
+            SCANCODE_PAGE_DOWN => Key::PageDown,
+            SCANCODE_DELETE => Key::Delete,
+
+            SCANCODE_SYSRQ => Key::SysRq,
+            SCANCODE_PRINT_SCREEN if extended => Key::SysRq,
+
+            // The keypad slash is the only extended character key
+            0x35 if extended => Key::Character(b'/'),
+
+            _ if extended => return None,
+
+            _ => {
+                let index = code as usize;
+                let character = *CHARACTERS.get(index)?;
+
+                if character == 0 {
+                    return None;
+                }
+
+                // Caps lock only changes the letters
+                let shift = if character.is_ascii_alphabetic() {
+                    shift != self.caps_lock
+                } else {
+                    shift
+                };
+
+                if shift {
+                    Key::Character(SHIFT_CHARACTERS[index])
+                } else {
+                    Key::Character(character)
+                }
+            }
+        };
+
+        Some(key)
+    }
+}
+
+/// Read the scancode from the controller and handle it, this is called by
+/// the keyboard interrupt handler
+pub fn handle_interrupt() {
+    let scancode = x86_64::inb(DATA_PORT);
+
+    // The event is handled after the keyboard is unlocked
+    let event = KEYBOARD.lock().decode(scancode);
+
+    if let Some(event) = event {
+        // The monitor is entered before the console gets the key so the
+        // console is not locked while we are inside the monitor
+        if event.key == Key::SysRq && event.alt {
+            monitor::enter(monitor::Reason::SysRq);
+            return;
+        }
+
+        console::handle_key(event);
+    }
+}
+
+/// Read a scancode from the controller without the interrupt and decode
+/// it, this is for the monitor that runs with the interrupts disabled
+pub fn poll() -> Option<KeyEvent> {
+    let status = x86_64::inb(STATUS_PORT);
+    if status & STATUS_OUTPUT_FULL == 0 {
+        return None;
+    }
+
+    let scancode = x86_64::inb(DATA_PORT);
+
+    // The byte is read anyway so the keyboard can send the next one
+    if status & STATUS_MOUSE_DATA != 0 {
+        return None;
+    }
+
+    KEYBOARD.lock().decode(scancode)
+}
@@ -1,57 +1,116 @@
-#![feature(asm, ptr_internals, panic_info_message)]
+#![feature(asm, global_asm, ptr_internals, panic_info_message,
+           abi_x86_interrupt)]
 #![no_std]
 
 extern crate rlibc;
 extern crate multiboot2;
 extern crate rangeset;
-
-use rangeset::{Range, RangeSet};
-
-#[macro_use] mod vga_buffer;
+extern crate page_table;
+extern crate graphics;
+extern crate terminal;
+
+use rangeset::Range;
+use vga_buffer::Color;
+
+#[macro_use] mod console;
+mod vga_buffer;
+mod framebuffer;
+mod fb_console;
+mod keyboard;
+mod serial;
 mod panic;
+mod backtrace;
+mod symbols;
 mod arch;
 mod memory;
+mod interrupts;
+mod gdb;
+mod monitor;
+
+/// The number of pages for the kernel stack we switch to after the boot
+const KERNEL_STACK_PAGES: u64 = 8;
 
 #[no_mangle]
-fn kernel_entry(multiboot_address: usize) -> ! {
+fn kernel_entry(multiboot_address: usize, kernel_slide: u64) -> ! {
+    // The boot code might have moved the kernel, everything that uses the
+    // higher half addresses needs the slide
+    memory::set_kernel_slide(kernel_slide);
+
+    // Setup the serial port so the output is mirrored to it
+    serial::SERIAL.lock().init();
+
     {
-        // Get the lock for the vga buffer and the lock with unlock 
+        // Get the lock for the consoles and the lock with unlock 
         // when the variable goes out of this scope
-        let mut writer = vga_buffer::WRITER.lock();
+        let mut consoles = console::CONSOLES.lock();
+        consoles.update_address();
+
+        // Clear the screens and set the color to use
+        for console in consoles.iter_mut() {
+            console.clear(Color::Magenta);
+            console.set_color(Color::White, Color::Magenta);
+        }
 
-        // Clear the buffer 
-        writer.clear(vga_buffer::Color::Magenta);
-        // Set the color to use
-        writer.set_color(vga_buffer::Color::White, vga_buffer::Color::Magenta);
+        // Use an underline cursor, the firmware might have left a block
+        consoles.set_cursor_shape(console::CURSOR_START, console::CURSOR_END);
     }
 
-    println!("Welcome to NanoOS v0.01");
+    println!("\x1b[1mWelcome to NanoOS v0.01\x1b[0m");
+
+    // Setup the exception handlers as early as possible
+    interrupts::init();
+
+    // The GDB stub talks on COM2, the kernel only stops for it on a
+    // breakpoint or when GDB sends Ctrl-C
+    gdb::init();
+
+    // The boot code gives us the physical address of the multiboot 
+    // structure, but we run inside the higher half so translate it to 
+    // the higher half alias
+    let multiboot_physical = multiboot_address as u64;
+    let multiboot_address = 
+        multiboot_address + memory::kernel_offset() as usize;
 
     // Load the multiboot infomation
     let boot_info = unsafe { multiboot2::load(multiboot_address) };
 
+    let mut memory_test = false;
+
     if let Some(tag) = boot_info.command_line_tag() {
         let cmd_line = tag.command_line();
         println!("Command Line: {}", cmd_line);
+
+        if cmd_line.contains("nokaslr") {
+            println!("KASLR disabled on the command line");
+        }
+
+        // Wait for GDB before we do anything else so the rest of the boot
+        // can be debugged
+        if cmd_line.split(' ').any(|x| x == "gdb") {
+            println!("Waiting for GDB on COM2");
+            gdb::breakpoint();
+        }
+
+        // The memory tests are only run when they are asked for
+        memory_test = cmd_line.split(' ').any(|x| x == "memtest");
+
+        // Drop into the monitor when the boot is done
+        if cmd_line.split(' ').any(|x| x == "debug") {
+            monitor::request_at_boot();
+        }
     }
 
-    // Get the memory map from the boot info
-    let memory_map = boot_info.memory_map_tag()
+    println!("Kernel slide: {:#x} (kernel at {:#x})",
+             memory::kernel_slide(), memory::kernel_offset());
+
+    // Get the memory map with the type of every area from the boot info
+    let memory_map = memory::MemoryMap::from_multiboot(&boot_info)
         .expect("Failed to retrive the memory map");
+    memory_map.print();
 
-    // Construct a rangeset of the physical memory 
+    // Construct a rangeset of the usable physical memory 
     // from the memory map the bootloader gives us
-    let mut physical_memory = RangeSet::new();
-
-    // Loop through all the memory areas and and them 
-    // to the total physical memory range
-    for area in memory_map.memory_areas() {
-        // Insert the range
-        physical_memory.insert(Range {
-            start: area.start_address(),
-            end: area.end_address().checked_sub(1).unwrap(),
-        });
-    }
+    let mut physical_memory = memory_map.ranges(memory::MemoryKind::Usable);
 
     // Remove the first 1 MiB becuase there is stuff there we use 
     // or we might use, so to be sure we just remove the first MiB 
@@ -63,20 +122,32 @@ fn kernel_entry(multiboot_address: usize) -> ! {
     let elf_sections = boot_info.elf_sections_tag()
         .expect("Failed to retive the elf sections tag");
 
+    // The sections linked in the higher half has their virtual address 
+    // inside the tag, so convert them back to the physical address
+    let to_physical = |address: u64| {
+        if address >= memory::KERNEL_OFFSET {
+            address - memory::KERNEL_OFFSET
+        } else {
+            address
+        }
+    };
+
     let kernel_start =
         elf_sections.sections()
-            .map(|x| x.start_address())
+            .filter(|x| x.is_allocated())
+            .map(|x| to_physical(x.start_address()))
             .min()
             .unwrap();
 
     let kernel_end =
         elf_sections.sections()
-            .map(|x| x.end_address())
+            .filter(|x| x.is_allocated())
+            .map(|x| to_physical(x.end_address()))
             .max()
             .unwrap();
 
-    let multiboot_start = multiboot_address as u64;
-    let multiboot_end = multiboot_address as u64 + 
+    let multiboot_start = multiboot_physical;
+    let multiboot_end = multiboot_physical + 
         boot_info.total_size() as u64;
 
     println!("Kernel Start: {:#x}", kernel_start);
@@ -98,7 +169,56 @@ fn kernel_entry(multiboot_address: usize) -> ! {
         end: multiboot_end.checked_sub(1).unwrap()
     });
 
-    memory::init(&mut physical_memory);
+    // Remove the modules GRUB loaded for us, e.g. the console font
+    for module in boot_info.module_tags() {
+        let start = module.start_address() as u64;
+        let end = module.end_address() as u64;
+        println!("Module '{}': {:#x} - {:#x}", module.name(), start, end);
+
+        if end > start {
+            physical_memory.remove(Range {
+                start: start,
+                end: end - 1
+            });
+        }
+    }
+
+    memory::init(&boot_info, memory_map, physical_memory);
+
+    if memory_test {
+        memory::self_test();
+    }
+
+    // The panic handler uses the symbol table for the backtraces
+    symbols::init(&boot_info);
+
+    // Move the console to the framebuffer if the bootloader gave us one and
+    // a font to draw with, otherwise we stay inside the VGA text mode
+    if let Some(display) = fb_console::init(&boot_info) {
+        console::CONSOLES.lock()
+            .set_display(console::Display::Framebuffer(display));
+    }
 
-    loop {}
+    // Now that we can allocate stacks we can handle kernel stack overflows
+    interrupts::init_stacks();
+
+    // Leave the boot stack, it has nothing below it to catch an overflow
+    let stack = memory::stack::allocate_stack(KERNEL_STACK_PAGES)
+        .expect("Failed to allocate the kernel stack");
+    println!("Kernel stack: {:#x} - {:#x}", stack.bottom(), stack.top());
+
+    unsafe { arch::x86_64::call_on_stack(stack.top(), kernel_main); }
+}
+
+/// The rest of the kernel after we switched to the kernel stack
+fn kernel_main() -> ! {
+    // Start taking the keyboard interrupts
+    interrupts::init_irqs();
+
+    monitor::enter_if_requested();
+
+    // Everything happens inside the interrupts for now
+    loop {
+        arch::x86_64::halt();
+    }
 }
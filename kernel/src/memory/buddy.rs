@@ -0,0 +1,313 @@
+//! A buddy allocator for physical frames, memory is split into blocks of
+//! `2^order` frames and a freed block is merged with its buddy when the
+//! buddy is free aswell. The free lists are stored inside the free blocks
+//! themselves through the direct map, and a bitmap for every order tells
+//! us if a block is free so we can find the buddy without walking the lists
+
+use rangeset::{Range, RangeSet};
+use super::{PAGE_SIZE, PhysicalAddress, PhysicalFrame};
+use super::{physmap, zone};
+
+/// The number of orders, the largest block is `2^(MAX_ORDER - 1)` frames
+/// which is 4 MiB
+pub const MAX_ORDER: usize = 11;
+
+/// Marks the end of a free list
+const NO_BLOCK: u64 = !0;
+
+/// The node we store at the start of every free block
+#[repr(C)]
+struct FreeBlock {
+    next: u64,
+    prev: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BuddyStats {
+    /// The number of frames the allocator manages
+    pub total_frames: u64,
+
+    /// The number of frames that is free
+    pub free_frames: u64,
+
+    /// The number of free blocks for every order
+    pub free_blocks: [u64; MAX_ORDER],
+}
+
+impl BuddyStats {
+    /// The order of the largest free block, `None` if there is no free
+    /// memory
+    pub fn largest_free_order(&self) -> Option<usize> {
+        (0..MAX_ORDER).rev().find(|&order| self.free_blocks[order] > 0)
+    }
+}
+
+pub struct BuddyAllocator {
+    /// The first frame of the first free block for every order
+    free_lists: [u64; MAX_ORDER],
+
+    /// The virtual address of the bitmap for every order, the bit for a
+    /// block is set if the block is on the free list
+    bitmaps: [u64; MAX_ORDER],
+
+    /// The first frame the allocator covers
+    base_frame: u64,
+
+    /// The number of frames the bitmaps covers starting at `base_frame`
+    frame_count: u64,
+
+    stats: BuddyStats,
+}
+
+impl BuddyAllocator {
+    pub const fn new() -> BuddyAllocator {
+        BuddyAllocator {
+            free_lists: [NO_BLOCK; MAX_ORDER],
+            bitmaps: [0; MAX_ORDER],
+            base_frame: 0,
+            frame_count: 0,
+
+            stats: BuddyStats {
+                total_frames: 0,
+                free_frames: 0,
+                free_blocks: [0; MAX_ORDER],
+            },
+        }
+    }
+
+    /// Setup the bitmaps for the frames from `start` to `end` (exclusive),
+    /// the bitmaps are allocated from `boot_memory` and this needs to be
+    /// called after the direct map is setup. The allocator has no free
+    /// memory until memory is added with `add_range`
+    pub(super) fn init(&mut self, start: PhysicalFrame, end: PhysicalFrame,
+                       boot_memory: &mut RangeSet)
+    {
+        assert!(start.0 % (1 << (MAX_ORDER - 1)) == 0,
+                "The start of the buddy allocator needs to be aligned to \
+                 the largest block");
+
+        let frame_count = end.0 - start.0;
+        self.base_frame = start.0;
+        self.frame_count = frame_count;
+
+        // Calculate the size for all the bitmaps rounded up to full u64s
+        let bitmap_size = |order: usize| {
+            let blocks = (frame_count + (1 << order) - 1) >> order;
+            ((blocks + 63) / 64) * 8
+        };
+
+        let total_size: u64 = (0..MAX_ORDER).map(bitmap_size).sum();
+
+        let preferred = zone::boot_preferred_memory();
+        let address = boot_memory.allocate_prefer(total_size, PAGE_SIZE,
+                                                  Some(&preferred))
+            .expect("Failed to allocate the buddy allocator bitmaps");
+        let address = physmap::phys_to_virt(PhysicalAddress(address as u64))
+            .expect("The buddy bitmaps are not inside the direct map");
+
+        unsafe {
+            core::ptr::write_bytes(address.0 as *mut u8, 0,
+                                   total_size as usize);
+        }
+
+        let mut offset = 0;
+        for order in 0..MAX_ORDER {
+            self.bitmaps[order] = address.0 + offset;
+            offset += bitmap_size(order);
+        }
+    }
+
+    /// Give the frames from `start` to `end` (exclusive) to the allocator
+    pub(super) fn add_range(&mut self, start: PhysicalFrame,
+                            end: PhysicalFrame)
+    {
+        assert!(start.0 >= self.base_frame &&
+                end.0 <= self.base_frame + self.frame_count,
+                "Range is outside the buddy allocator");
+
+        let mut frame = start.0;
+        let end = end.0;
+
+        while frame < end {
+            // Find the largest block that is aligned and fits
+            let mut order = MAX_ORDER - 1;
+            while frame % (1 << order) != 0 ||
+                frame + (1 << order) > end
+            {
+                order -= 1;
+            }
+
+            self.stats.total_frames += 1 << order;
+            self.free(PhysicalFrame(frame), order);
+
+            frame += 1 << order;
+        }
+    }
+
+    /// Get the bitmap word and the bit for the block at `frame`
+    fn bit(&self, frame: u64, order: usize) -> (*mut u64, u64) {
+        let index = (frame - self.base_frame) >> order;
+        let word = (self.bitmaps[order] + (index / 64) * 8) as *mut u64;
+
+        (word, 1 << (index % 64))
+    }
+
+    fn is_free(&self, frame: u64, order: usize) -> bool {
+        let (word, bit) = self.bit(frame, order);
+        unsafe { *word & bit != 0 }
+    }
+
+    fn set_free(&mut self, frame: u64, order: usize, free: bool) {
+        let (word, bit) = self.bit(frame, order);
+
+        unsafe {
+            if free {
+                *word |= bit;
+            } else {
+                *word &= !bit;
+            }
+        }
+    }
+
+    /// Get the free list node stored inside the block at `frame`
+    fn node<'a>(frame: u64) -> &'a mut FreeBlock {
+        let address = physmap::frame_to_virt(PhysicalFrame(frame));
+        unsafe { &mut *(address.0 as *mut FreeBlock) }
+    }
+
+    fn push(&mut self, frame: u64, order: usize) {
+        let head = self.free_lists[order];
+
+        let node = Self::node(frame);
+        node.next = head;
+        node.prev = NO_BLOCK;
+
+        if head != NO_BLOCK {
+            Self::node(head).prev = frame;
+        }
+
+        self.free_lists[order] = frame;
+        self.set_free(frame, order, true);
+
+        self.stats.free_blocks[order] += 1;
+        self.stats.free_frames += 1 << order;
+    }
+
+    /// Remove the block at `frame` from the free list, this is O(1) because
+    /// the list is doubly linked
+    fn remove(&mut self, frame: u64, order: usize) {
+        let node = Self::node(frame);
+        let (next, prev) = (node.next, node.prev);
+
+        if prev != NO_BLOCK {
+            Self::node(prev).next = next;
+        } else {
+            self.free_lists[order] = next;
+        }
+
+        if next != NO_BLOCK {
+            Self::node(next).prev = prev;
+        }
+
+        self.set_free(frame, order, false);
+
+        self.stats.free_blocks[order] -= 1;
+        self.stats.free_frames -= 1 << order;
+    }
+
+    /// Allocate a block of `2^order` contiguous frames aligned to the size
+    /// of the block
+    pub(super) fn allocate(&mut self, order: usize) -> Option<PhysicalFrame> {
+        assert!(order < MAX_ORDER, "Invalid order {}", order);
+
+        // Find the smallest order that has a free block
+        let mut current = (order..MAX_ORDER)
+            .find(|&x| self.free_lists[x] != NO_BLOCK)?;
+
+        let frame = self.free_lists[current];
+        self.remove(frame, current);
+
+        // Split the block until we have the size we want, the upper half
+        // goes back on the free list
+        while current > order {
+            current -= 1;
+            self.push(frame + (1 << current), current);
+        }
+
+        Some(PhysicalFrame(frame))
+    }
+
+    /// Free the block of `2^order` frames at `frame` and merge it with
+    /// its buddy as long as the buddy is free
+    pub(super) fn free(&mut self, frame: PhysicalFrame, mut order: usize) {
+        assert!(order < MAX_ORDER, "Invalid order {}", order);
+        assert!(frame.0 % (1 << order) == 0,
+                "Block {:#x} is not aligned to the order {}",
+                frame.0 * PAGE_SIZE, order);
+        assert!(!self.is_free(frame.0, order),
+                "Double free of the block {:#x}", frame.0 * PAGE_SIZE);
+
+        let mut frame = frame.0;
+
+        while order < MAX_ORDER - 1 {
+            let buddy = frame ^ (1 << order);
+            if buddy < self.base_frame ||
+                buddy + (1 << order) > self.base_frame + self.frame_count ||
+                !self.is_free(buddy, order)
+            {
+                break;
+            }
+
+            self.remove(buddy, order);
+
+            frame = core::cmp::min(frame, buddy);
+            order += 1;
+        }
+
+        self.push(frame, order);
+    }
+
+    pub fn stats(&self) -> BuddyStats {
+        self.stats
+    }
+
+    /// Add the free blocks to `memory`, returns `false` if `memory` ran
+    /// out of entries before we added all the blocks
+    pub(super) fn free_memory(&self, memory: &mut RangeSet) -> bool {
+        for order in 0..MAX_ORDER {
+            let mut frame = self.free_lists[order];
+
+            while frame != NO_BLOCK {
+                if memory.entries().len() == memory.capacity() {
+                    return false;
+                }
+
+                let start = frame * PAGE_SIZE;
+                memory.insert(Range {
+                    start: start,
+                    end: start + (PAGE_SIZE << order) - 1,
+                });
+
+                frame = Self::node(frame).next;
+            }
+        }
+
+        true
+    }
+
+    /// Check if `frame` is managed by this allocator
+    pub(super) fn contains(&self, frame: PhysicalFrame) -> bool {
+        frame.0 >= self.base_frame &&
+            frame.0 < self.base_frame + self.frame_count
+    }
+}
+
+/// Get the smallest order that fits `count` frames
+pub fn order_for(count: u64) -> usize {
+    let mut order = 0;
+    while (1 << order) < count {
+        order += 1;
+    }
+
+    order
+}
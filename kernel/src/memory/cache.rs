@@ -0,0 +1,154 @@
+//! The cache modes for mappings, the Page Attribute Table is programmed so
+//! the PAT, PCD and PWT bits of a page table entry can select every memory
+//! type including write-combining which the power-on table doesn't have
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::arch::x86_64;
+use super::{PAGE_WRITE_THROUGH, PAGE_NO_CACHE};
+
+/// The PAT bit of a P1 entry, this is the same bit as the huge bit inside
+/// the P2 and P3 entries so this can only be used for 4 KiB pages
+pub(super) const PAGE_PAT: u64 = 1 << 7;
+
+/// The PAT bit inside a P2 or P3 entry that maps a huge page, bit 7 is the
+/// huge page bit there
+const PAGE_PAT_HUGE: u64 = 1 << 12;
+
+// The memory types for the entries inside the PAT MSR
+const PAT_UC:       u64 = 0x00;
+const PAT_WC:       u64 = 0x01;
+const PAT_WT:       u64 = 0x04;
+const PAT_WB:       u64 = 0x06;
+const PAT_UC_MINUS: u64 = 0x07;
+
+/// The table we program, this is the power-on table except entry 4 which
+/// is write-combining instead of write-back. Entry N is selected by
+/// PAT << 2 | PCD << 1 | PWT
+const PAT_VALUE: u64 = PAT_WB       <<  0 |
+                       PAT_WT       <<  8 |
+                       PAT_UC_MINUS << 16 |
+                       PAT_UC       << 24 |
+                       PAT_WC       << 32 |
+                       PAT_WT       << 40 |
+                       PAT_UC_MINUS << 48 |
+                       PAT_UC       << 56;
+
+/// Set when the PAT has been programmed with `PAT_VALUE`
+static PAT_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// How the CPU caches the memory of a mapping
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CacheMode {
+    /// Normal RAM, reads and writes are cached
+    WriteBack,
+
+    /// Reads are cached and writes goes to memory right away
+    WriteThrough,
+
+    /// Nothing is cached but writes are combined into bursts, used for
+    /// framebuffers
+    WriteCombining,
+
+    /// Nothing is cached, but the MTRRs can override this to
+    /// write-combining
+    UncachedMinus,
+
+    /// Nothing is cached, used for device registers
+    Uncached,
+}
+
+impl CacheMode {
+    /// The entry inside the PAT for the cache mode
+    fn pat_index(&self) -> u64 {
+        match self {
+            CacheMode::WriteBack      => 0,
+            CacheMode::WriteThrough   => 1,
+            CacheMode::UncachedMinus  => 2,
+            CacheMode::Uncached       => 3,
+
+            // Without our table write-combining is not available, so we
+            // use the closest mode that is still correct
+            CacheMode::WriteCombining => {
+                if PAT_ENABLED.load(Ordering::Relaxed) { 4 } else { 2 }
+            }
+        }
+    }
+
+    /// The flags that selects the cache mode inside a P1 entry
+    pub(super) fn page_flags(&self) -> u64 {
+        let index = self.pat_index();
+        let mut flags = 0;
+
+        if index & 1 != 0 { flags |= PAGE_WRITE_THROUGH; }
+        if index & 2 != 0 { flags |= PAGE_NO_CACHE; }
+        if index & 4 != 0 { flags |= PAGE_PAT; }
+
+        flags
+    }
+
+    /// Replace the cache bits inside the flags of a 4 KiB page with the
+    /// bits for this mode
+    pub(super) fn apply(&self, flags: u64) -> u64 {
+        let flags = flags & !(PAGE_WRITE_THROUGH | PAGE_NO_CACHE | PAGE_PAT);
+        flags | self.page_flags()
+    }
+
+    /// Get the cache mode a page is mapped with from the flags of the
+    /// entry, the PAT bit is bit 12 inside the entry of a huge page
+    pub(super) fn from_page_flags(flags: u64, huge: bool) -> CacheMode {
+        let pat = if huge { PAGE_PAT_HUGE } else { PAGE_PAT };
+
+        let mut index = 0;
+        if flags & PAGE_WRITE_THROUGH != 0 { index |= 1; }
+        if flags & PAGE_NO_CACHE      != 0 { index |= 2; }
+        if flags & pat                != 0 { index |= 4; }
+
+        match index {
+            0 => CacheMode::WriteBack,
+            1 | 5 => CacheMode::WriteThrough,
+            2 | 6 => CacheMode::UncachedMinus,
+            3 | 7 => CacheMode::Uncached,
+
+            // Entry 4 is only write-combining if we programmed the PAT,
+            // in the power-on table it's write-back
+            _ => {
+                if PAT_ENABLED.load(Ordering::Relaxed) {
+                    CacheMode::WriteCombining
+                } else {
+                    CacheMode::WriteBack
+                }
+            }
+        }
+    }
+
+    /// The short name of the mode for the page table dump
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            CacheMode::WriteBack      => "WB",
+            CacheMode::WriteThrough   => "WT",
+            CacheMode::WriteCombining => "WC",
+            CacheMode::UncachedMinus  => "UC-",
+            CacheMode::Uncached       => "UC",
+        }
+    }
+}
+
+/// Program the PAT with our table, this needs to be done before any
+/// mapping uses the write-combining mode
+pub(super) fn init() {
+    let (_, _, _, edx) = x86_64::cpuid(1);
+    if edx & x86_64::CPUID_EDX_PAT == 0 {
+        println!("The CPU has no PAT, write-combining is not available");
+        return;
+    }
+
+    // The caches and the TLB can have lines with the old memory types
+    x86_64::wbinvd();
+    x86_64::wrmsr(x86_64::IA32_PAT, PAT_VALUE);
+    x86_64::wbinvd();
+    x86_64::flush_tlb();
+
+    PAT_ENABLED.store(true, Ordering::Relaxed);
+
+    println!("PAT: {:#018x}", PAT_VALUE);
+}
@@ -0,0 +1,251 @@
+//! The page fault handling, faults inside anonymous regions are handled by
+//! mapping a zeroed frame and writes to copy-on-write pages are handled by
+//! making a private copy, every other fault is reported as a bug
+
+use super::{PAGE_SIZE, PAGE_WRITE, PAGE_COW};
+use super::{FRAME_ALLOCATOR, FrameAllocator};
+use super::{VirtualAddress, PhysicalFrame, Page};
+use super::ActivePageTable;
+use super::{physmap, frame_info, stack};
+use super::vma::{KERNEL_ADDRESS_SPACE, Region, RegionKind};
+
+/// The fault was caused by a page-level protection violation, if this bit
+/// is not set the page was not present
+pub const PF_PRESENT:           u64 = 1 << 0;
+/// The access that caused the fault was a write
+pub const PF_WRITE:             u64 = 1 << 1;
+/// The access was done while the CPU was in user mode
+pub const PF_USER:              u64 = 1 << 2;
+/// A reserved bit was set inside one of the page table entries
+pub const PF_RESERVED:          u64 = 1 << 3;
+/// The access that caused the fault was an instruction fetch
+pub const PF_INSTRUCTION_FETCH: u64 = 1 << 4;
+
+/// Check if the access described by `error_code` is allowed by the
+/// permissions of `region`
+fn access_allowed(region: &Region, error_code: u64) -> bool {
+    if error_code & PF_WRITE != 0 && !region.is_writable() {
+        return false;
+    }
+
+    if error_code & PF_INSTRUCTION_FETCH != 0 && !region.is_executable() {
+        return false;
+    }
+
+    if error_code & PF_USER != 0 && !region.is_user() {
+        return false;
+    }
+
+    true
+}
+
+/// Map a zeroed frame at the page containing `address`
+fn zero_fill(address: u64, region: &Region) -> bool {
+    // If the page fault happend while someone was holding the frame
+    // allocator we can't allocate a frame without deadlocking
+    let mut allocator = match FRAME_ALLOCATOR.try_lock() {
+        Some(allocator) => allocator,
+        None => {
+            emergency_println!("The frame allocator is locked, can't handle \
+                                the fault");
+            return false;
+        }
+    };
+
+    let frame = match allocator.allocate_frame() {
+        Some(frame) => frame,
+        None => {
+            emergency_println!("Out of physical memory, can't handle the \
+                                fault");
+            return false;
+        }
+    };
+
+    // Zero the frame through the direct map before it's visible at the
+    // faulting address
+    physmap::zero_frame(frame);
+
+    let page = Page::containing_address(VirtualAddress(address));
+
+    let mut page_table = unsafe { ActivePageTable::new() };
+    page_table.map_to(page, frame, region.flags, &mut *allocator);
+
+    true
+}
+
+/// Handle a write to the copy-on-write page containing `address`, returns
+/// `false` if the page is not a copy-on-write page
+fn copy_on_write(address: u64) -> bool {
+    let page = Page::containing_address(VirtualAddress(address));
+
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let mapping = match page_table.mapping(page) {
+        Some(mapping) if mapping.flags & PAGE_COW != 0 => mapping,
+        _ => return false,
+    };
+
+    let frame = PhysicalFrame(mapping.physical_address / PAGE_SIZE);
+    let flags = (mapping.flags & !PAGE_COW) | PAGE_WRITE;
+
+    let refcount = match frame_info::get(frame) {
+        Some(info) => info.refcount,
+        None => {
+            emergency_println!("The frame has no metadata, can't handle the \
+                                fault");
+            return false;
+        }
+    };
+
+    if refcount <= 1 {
+        // We are the last mapping of the frame so we can just take it
+        page_table.update_flags(page, |_| flags);
+        frame_info::with_frame(frame, |info| {
+            info.flags &= !frame_info::FRAME_COW;
+        });
+
+        return true;
+    }
+
+    // If the page fault happend while someone was holding the frame
+    // allocator we can't allocate a frame without deadlocking
+    let mut allocator = match FRAME_ALLOCATOR.try_lock() {
+        Some(allocator) => allocator,
+        None => {
+            emergency_println!("The frame allocator is locked, can't handle \
+                                the fault");
+            return false;
+        }
+    };
+
+    let new_frame = match allocator.allocate_frame() {
+        Some(frame) => frame,
+        None => {
+            emergency_println!("Failed to allocate a frame for the \
+                                copy-on-write");
+            return false;
+        }
+    };
+
+    // Copy the shared frame to our private frame through the direct map
+    let source = physmap::frame_to_virt(frame);
+    let destination = physmap::frame_to_virt(new_frame);
+
+    unsafe {
+        core::ptr::copy_nonoverlapping(source.0 as *const u8,
+                                       destination.0 as *mut u8,
+                                       PAGE_SIZE as usize);
+    }
+
+    // Point the page to our private copy
+    page_table.unmap(page);
+    page_table.map_to(page, new_frame, flags, &mut *allocator);
+
+    // Drop our reference to the shared frame, the frame is freed if that
+    // was the last one
+    if frame_info::put_ref(frame) == 0 {
+        allocator.deallocate_frame(frame);
+    }
+
+    true
+}
+
+/// Print all the infomation we have about the page fault
+fn report(address: u64, error_code: u64, instruction_pointer: u64,
+          region: Option<&Region>)
+{
+    let access = if error_code & PF_INSTRUCTION_FETCH != 0 {
+        "instruction fetch"
+    } else if error_code & PF_WRITE != 0 {
+        "write"
+    } else {
+        "read"
+    };
+
+    let mode = if error_code & PF_USER != 0 { "user" } else { "kernel" };
+
+    let reason = if error_code & PF_RESERVED != 0 {
+        "reserved bit set inside a page table entry"
+    } else if error_code & PF_PRESENT != 0 {
+        "protection violation"
+    } else {
+        "page not present"
+    };
+
+    emergency_println!("---------- PAGE FAULT ----------");
+    emergency_println!("Address: {:#x}", address);
+    emergency_println!("Instruction: {:#x}", instruction_pointer);
+    emergency_println!("Access: {} from {} mode ({:#x})", access, mode,
+                       error_code);
+    emergency_println!("Reason: {}", reason);
+
+    match region {
+        Some(region) => {
+            emergency_println!("Region: '{}' {:#x} - {:#x} ({:?}, W: {}, \
+                                X: {}, U: {})",
+                               region.name, region.start, region.end,
+                               region.kind, region.is_writable(),
+                               region.is_executable(), region.is_user());
+
+            if !access_allowed(region, error_code) {
+                emergency_println!("The access is not allowed inside the \
+                                    region");
+            }
+        }
+
+        None => emergency_println!("Region: the address is not inside any \
+                                    region"),
+    }
+
+    emergency_println!("--------------------------------");
+}
+
+/// Try to handle the page fault at `address`, returns `false` if the fault
+/// could not be handled and in that case a diagnostic is printed
+pub fn handle_page_fault(address: u64, error_code: u64,
+                         instruction_pointer: u64) -> bool
+{
+    // Most overflows ends up inside the double fault handler because the
+    // CPU can't push the frame, but a large stack frame can skip over the
+    // stack pointer and fault here
+    if stack::check_stack_overflow(address, instruction_pointer) {
+        return false;
+    }
+
+    let address_space = match KERNEL_ADDRESS_SPACE.try_lock() {
+        Some(address_space) => address_space,
+        None => {
+            emergency_println!("The address space is locked, can't handle \
+                                the fault");
+            report(address, error_code, instruction_pointer, None);
+            return false;
+        }
+    };
+
+    let region = address_space.find_region(address);
+
+    if let Some(region) = region {
+        let not_present = error_code & (PF_PRESENT | PF_RESERVED) == 0;
+
+        // A write to a present page can be a write to a copy-on-write page
+        if error_code & PF_PRESENT != 0 && error_code & PF_WRITE != 0 &&
+            error_code & PF_RESERVED == 0 &&
+            access_allowed(region, error_code)
+        {
+            if copy_on_write(address) {
+                return true;
+            }
+        }
+
+        if region.kind == RegionKind::Anonymous && not_present &&
+            access_allowed(region, error_code)
+        {
+            if zero_fill(address & !(PAGE_SIZE - 1), region) {
+                return true;
+            }
+        }
+    }
+
+    report(address, error_code, instruction_pointer, region);
+
+    false
+}
@@ -0,0 +1,172 @@
+//! Metadata for every physical frame, this keeps track of how many mappings
+//! reference a frame so frames can be shared copy-on-write and only freed
+//! when the last mapping is gone
+
+use spin::Mutex;
+use rangeset::RangeSet;
+use super::{PAGE_SIZE, PhysicalAddress, PhysicalFrame};
+use super::{physmap, zone};
+
+/// The frame is allocated
+pub const FRAME_ALLOCATED:  u16 = 1 << 0;
+/// The frame is used for a page table
+pub const FRAME_PAGE_TABLE: u16 = 1 << 1;
+/// The frame is shared copy-on-write between multiple mappings
+pub const FRAME_COW:        u16 = 1 << 2;
+/// The frame is not usable memory, e.g. the kernel image or memory that
+/// the firmware uses
+pub const FRAME_RESERVED:   u16 = 1 << 3;
+
+/// The owner id of the frames that belongs to the kernel
+pub const KERNEL_OWNER: u16 = 0;
+
+static FRAME_INFO: Mutex<Option<FrameInfoTable>> = Mutex::new(None);
+
+#[derive(Copy, Clone, Debug)]
+#[repr(C)]
+pub struct FrameInfo {
+    /// The number of mappings that reference the frame
+    pub refcount: u32,
+
+    /// The `FRAME_*` flags
+    pub flags: u16,
+
+    /// The id of the address space that owns the frame
+    pub owner: u16,
+}
+
+impl FrameInfo {
+    const fn empty() -> FrameInfo {
+        FrameInfo {
+            refcount: 0,
+            flags: 0,
+            owner: KERNEL_OWNER,
+        }
+    }
+}
+
+struct FrameInfoTable {
+    entries: &'static mut [FrameInfo],
+}
+
+/// Allocate the metadata array for all the frames up to `memory_end`, the
+/// frames outside of `usable_memory` are marked as reserved. The array is
+/// accessed through the direct map so this needs to be called after the
+/// direct map is setup
+pub(super) fn init(usable_memory: &RangeSet, memory_end: u64,
+                   allocator: &mut RangeSet)
+{
+    let frame_count = (memory_end / PAGE_SIZE) as usize;
+    let size = (frame_count * core::mem::size_of::<FrameInfo>()) as u64;
+
+    let preferred = zone::boot_preferred_memory();
+    let table_address = allocator.allocate_prefer(size, PAGE_SIZE,
+                                                  Some(&preferred))
+        .expect("Failed to allocate the frame metadata array");
+    let table_frame = PhysicalFrame::containing_address(
+        PhysicalAddress(table_address as u64));
+
+    let address = physmap::frame_to_virt(table_frame);
+    let entries = unsafe {
+        core::slice::from_raw_parts_mut(address.0 as *mut FrameInfo,
+                                        frame_count)
+    };
+
+    // Everything that is not usable memory starts out as reserved
+    for entry in entries.iter_mut() {
+        *entry = FrameInfo::empty();
+        entry.flags = FRAME_RESERVED;
+    }
+
+    // The usable memory starts out as allocated, the boot code has
+    // allocated the page tables and this array from it. The frame
+    // allocator marks the memory it's given as free
+    for range in usable_memory.entries() {
+        let start = (range.start + PAGE_SIZE - 1) / PAGE_SIZE;
+        let end = range.end.saturating_add(1) / PAGE_SIZE;
+
+        for frame in start..end {
+            let entry = &mut entries[frame as usize];
+            entry.refcount = 1;
+            entry.flags = FRAME_ALLOCATED;
+        }
+    }
+
+    println!("Frame metadata: {} frames ({} KiB)",
+             frame_count, size / 1024);
+
+    *FRAME_INFO.lock() = Some(FrameInfoTable {
+        entries: entries,
+    });
+}
+
+/// Call `f` with the metadata for `frame`, returns `None` if the metadata
+/// array is not setup or if the frame is outside the array. Nothing inside
+/// the lock can fault so the page fault handler can wait for it aswell
+pub(super) fn with_frame<F, R>(frame: PhysicalFrame, f: F) -> Option<R>
+    where F: FnOnce(&mut FrameInfo) -> R
+{
+    let mut table = FRAME_INFO.lock();
+    let table = table.as_mut()?;
+    let entry = table.entries.get_mut(frame.0 as usize)?;
+
+    Some(f(entry))
+}
+
+/// Get a copy of the metadata for `frame`
+pub(super) fn get(frame: PhysicalFrame) -> Option<FrameInfo> {
+    with_frame(frame, |info| *info)
+}
+
+/// Mark the frames from `start` to `end` (exclusive) as free usable
+/// memory, this is used when memory is given to the frame allocator
+pub(super) fn set_usable(start: PhysicalFrame, end: PhysicalFrame) {
+    for frame in start.0..end.0 {
+        with_frame(PhysicalFrame(frame), |info| {
+            *info = FrameInfo::empty();
+        }).expect("Failed to update the frame metadata");
+    }
+}
+
+/// Mark `frame` as allocated by `owner` with one reference
+pub(super) fn set_allocated(frame: PhysicalFrame, owner: u16, flags: u16) {
+    with_frame(frame, |info| {
+        assert!(info.flags & FRAME_RESERVED == 0,
+                "Frame {:#x} is reserved", frame.0 * PAGE_SIZE);
+
+        info.refcount = 1;
+        info.flags = FRAME_ALLOCATED | flags;
+        info.owner = owner;
+    }).expect("Failed to update the frame metadata");
+}
+
+/// Add a reference to `frame` and return the new reference count
+pub(super) fn get_ref(frame: PhysicalFrame) -> u32 {
+    with_frame(frame, |info| {
+        assert!(info.flags & FRAME_ALLOCATED != 0,
+                "Frame {:#x} is not allocated", frame.0 * PAGE_SIZE);
+
+        info.refcount += 1;
+        info.refcount
+    }).expect("Failed to update the frame metadata")
+}
+
+/// Remove a reference to `frame` and return the new reference count, when
+/// the count reaches zero the frame should be freed by the caller
+pub(super) fn put_ref(frame: PhysicalFrame) -> u32 {
+    with_frame(frame, |info| {
+        assert!(info.refcount > 0,
+                "Frame {:#x} has no references", frame.0 * PAGE_SIZE);
+
+        info.refcount -= 1;
+        if info.refcount <= 1 {
+            info.flags &= !FRAME_COW;
+        }
+
+        if info.refcount == 0 {
+            info.flags = 0;
+        }
+
+        info.refcount
+    }).expect("Failed to update the frame metadata")
+}
@@ -0,0 +1,212 @@
+//! The physical memory map from the bootloader with the type of every area,
+//! the `memory_areas` iterator from the multiboot2 crate only gives us the
+//! available areas so we read the memory map tag ourselves
+
+use spin::Mutex;
+use multiboot2::BootInformation;
+use rangeset::{Range, RangeSet};
+
+/// The maximum number of areas we keep from the memory map
+const MAX_AREAS: usize = 128;
+
+/// The type of the multiboot2 tag that ends the tag list
+const TAG_END: u32 = 0;
+/// The type of the multiboot2 memory map tag
+const TAG_MEMORY_MAP: u32 = 6;
+
+/// The memory map of the machine, this is saved inside `memory::init` so
+/// the ACPI memory can be reclaimed later
+pub static MEMORY_MAP: Mutex<MemoryMap> = Mutex::new(MemoryMap::new());
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MemoryKind {
+    /// RAM we can use
+    Usable,
+
+    /// Memory we can't use, e.g. memory the firmware uses or MMIO
+    Reserved,
+
+    /// Memory with the ACPI tables, this memory is usable after the ACPI
+    /// tables has been parsed
+    AcpiReclaimable,
+
+    /// Memory the firmware needs to be preserved across sleep states
+    AcpiNvs,
+
+    /// RAM that has been detected as broken
+    Defective,
+}
+
+impl MemoryKind {
+    /// Get the kind from the type inside the multiboot2 memory map, unknown
+    /// types are treated as reserved
+    fn from_multiboot(typ: u32) -> MemoryKind {
+        match typ {
+            1 => MemoryKind::Usable,
+            3 => MemoryKind::AcpiReclaimable,
+            4 => MemoryKind::AcpiNvs,
+            5 => MemoryKind::Defective,
+            _ => MemoryKind::Reserved,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MemoryKind::Usable          => "usable",
+            MemoryKind::Reserved        => "reserved",
+            MemoryKind::AcpiReclaimable => "ACPI reclaimable",
+            MemoryKind::AcpiNvs         => "ACPI NVS",
+            MemoryKind::Defective       => "defective",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryArea {
+    /// The start of the area (inclusive)
+    pub start: u64,
+    /// The end of the area (exclusive)
+    pub end: u64,
+
+    pub kind: MemoryKind,
+}
+
+/// An entry inside the multiboot2 memory map tag
+#[repr(C, packed)]
+struct RawMemoryArea {
+    base_address: u64,
+    length: u64,
+    typ: u32,
+    reserved: u32,
+}
+
+pub struct MemoryMap {
+    areas: [MemoryArea; MAX_AREAS],
+    count: usize,
+}
+
+impl MemoryMap {
+    pub const fn new() -> MemoryMap {
+        MemoryMap {
+            areas: [MemoryArea {
+                start: 0,
+                end: 0,
+                kind: MemoryKind::Reserved,
+            }; MAX_AREAS],
+            count: 0,
+        }
+    }
+
+    /// Read the memory map tag from the multiboot structure
+    pub fn from_multiboot(boot_info: &BootInformation) -> Option<MemoryMap> {
+        let mut map = MemoryMap::new();
+
+        unsafe {
+            // Skip the total size and the reserved field
+            let mut tag = boot_info.start_address() + 8;
+
+            loop {
+                let typ = *(tag as *const u32);
+                let size = *((tag + 4) as *const u32) as usize;
+
+                if typ == TAG_END {
+                    return None;
+                }
+
+                if typ == TAG_MEMORY_MAP {
+                    let entry_size = *((tag + 8) as *const u32) as usize;
+
+                    // The entries starts after the entry size and version
+                    let mut entry = tag + 16;
+                    while entry + entry_size <= tag + size {
+                        let area = &*(entry as *const RawMemoryArea);
+                        let kind = MemoryKind::from_multiboot(area.typ);
+
+                        // A broken firmware can report an area past the
+                        // end of the address space, we can't use that
+                        match area.base_address.checked_add(area.length) {
+                            Some(end) if area.length > 0 => {
+                                map.add(area.base_address, end, kind);
+                            }
+                            Some(_) => {}
+                            None => {
+                                println!("Ignoring invalid memory area \
+                                          {:#x} ({:#x} bytes)",
+                                         area.base_address, area.length);
+                            }
+                        }
+
+                        entry += entry_size;
+                    }
+
+                    return Some(map);
+                }
+
+                // The tags are aligned to 8 bytes
+                tag += (size + 7) & !7;
+            }
+        }
+    }
+
+    fn add(&mut self, start: u64, end: u64, kind: MemoryKind) {
+        if self.count >= MAX_AREAS {
+            println!("Memory map is full, ignoring {:#x} - {:#x} ({})",
+                     start, end, kind.name());
+            return;
+        }
+
+        self.areas[self.count] = MemoryArea {
+            start: start,
+            end: end,
+            kind: kind,
+        };
+        self.count += 1;
+    }
+
+    pub fn areas(&self) -> impl Iterator<Item = &MemoryArea> {
+        self.areas[..self.count].iter()
+    }
+
+    /// Get all the memory of `kind` as a `RangeSet`
+    pub fn ranges(&self, kind: MemoryKind) -> RangeSet {
+        let mut ranges = RangeSet::new();
+
+        for area in self.areas().filter(|x| x.kind == kind) {
+            ranges.insert(Range {
+                start: area.start,
+                end: area.end - 1,
+            });
+        }
+
+        // Some firmware reports overlapping areas, the usable memory can
+        // never overlap memory we are told not to touch
+        if kind == MemoryKind::Usable {
+            for area in self.areas().filter(|x| x.kind != kind) {
+                ranges.remove(Range {
+                    start: area.start,
+                    end: area.end - 1,
+                });
+            }
+        }
+
+        ranges
+    }
+
+    /// Change the kind of all the areas of `from` to `to`
+    pub fn convert(&mut self, from: MemoryKind, to: MemoryKind) {
+        for area in self.areas[..self.count].iter_mut() {
+            if area.kind == from {
+                area.kind = to;
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        println!("Memory map:");
+
+        for area in self.areas() {
+            println!("  {:#018x} - {:#018x} {}",
+                     area.start, area.end, area.kind.name());
+        }
+    }
+}
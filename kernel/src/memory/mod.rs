@@ -1,8 +1,63 @@
 // This is for all the warnings for unused flags
 #![allow(dead_code)]
 
-use core::ptr::Unique;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use multiboot2::BootInformation;
 use rangeset::{Range, RangeSet};
+use crate::arch::x86_64;
+use vma::{KERNEL_ADDRESS_SPACE, Region, RegionKind};
+
+mod remap;
+mod recursive;
+mod fault;
+mod frame_info;
+mod buddy;
+mod zone;
+mod memory_map;
+mod vmalloc;
+mod cache;
+pub mod physmap;
+pub mod stack;
+pub mod stats;
+pub mod vma;
+
+pub use fault::handle_page_fault;
+pub use zone::Zone;
+pub use memory_map::{MemoryMap, MemoryKind};
+pub use vmalloc::{vmalloc, vmalloc_cached, vfree, ioremap};
+pub use cache::CacheMode;
+
+const PAGE_SIZE: u64 = 4096;
+
+/// The size of a huge page mapped by the P2 table
+const HUGE_PAGE_SIZE: u64 = PAGE_SIZE * 512;
+
+/// The virtual address the kernel is linked at, the boot code maps the
+/// first GiB of physical memory here plus the KASLR slide, this needs to
+/// match the linker script
+pub const KERNEL_OFFSET: u64 = 0xffff_ffff_8000_0000;
+
+/// How far the boot code moved the kernel from `KERNEL_OFFSET`, this is 0
+/// if the kernel is not relocatable or KASLR is disabled
+static KERNEL_SLIDE: AtomicU64 = AtomicU64::new(0);
+
+/// Save the slide the boot code picked, this needs to be done before
+/// anything uses `kernel_offset`
+pub fn set_kernel_slide(slide: u64) {
+    KERNEL_SLIDE.store(slide, Ordering::Relaxed);
+}
+
+pub fn kernel_slide() -> u64 {
+    KERNEL_SLIDE.load(Ordering::Relaxed)
+}
+
+/// The virtual address the kernel and the first GiB of physical memory is
+/// mapped at, the addresses inside the ELF sections tag are still the
+/// addresses the kernel is linked at
+pub fn kernel_offset() -> u64 {
+    KERNEL_OFFSET + kernel_slide()
+}
 
 const PAGE_PRESENT:       u64 = 1 <<  0;
 const PAGE_WRITE:         u64 = 1 <<  1;
@@ -15,11 +70,17 @@ const PAGE_HUGE:          u64 = 1 <<  7;
 const PAGE_GLOBAL:        u64 = 1 <<  8;
 const PAGE_NXE:           u64 = 1 << 63;
 
+// Bit 9 to 11 is ignored by the CPU so we can use them for our own flags
+
+/// The page is shared copy-on-write, the page is mapped read only and the
+/// page fault handler makes a private copy on the first write
+const PAGE_COW:           u64 = 1 <<  9;
+
 #[derive(Copy, Clone, Debug)]
-struct VirtualAddress(u64);
+pub struct VirtualAddress(pub u64);
 
 #[derive(Copy, Clone, Debug)]
-struct PhysicalAddress(u64);
+pub struct PhysicalAddress(pub u64);
 
 #[derive(Copy, Clone, Debug)]
 struct PhysicalFrame(u64);
@@ -34,10 +95,15 @@ impl PhysicalFrame {
 #[derive(Copy, Clone, Debug)]
 struct Page(u64);
 
+/// Check that bits 48 to 63 of `address` are copies of bit 47, any other
+/// address is inside the hole in the middle and can't be used
+pub fn is_canonical(address: u64) -> bool {
+    address < 0x0000_8000_0000_0000 || address >= 0xffff_8000_0000_0000
+}
+
 impl Page {
     fn containing_address(address: VirtualAddress) -> Self {
-        assert!(address.0 < 0x0000_8000_0000_0000 ||
-                address.0 >= 0xffff_8000_0000_0000,
+        assert!(is_canonical(address.0),
                 "Invalid Virtual Address: {:#x}", address.0);
 
         // TODO(patrik): Change the 4096 to a constant "PAGE SIZE"
@@ -61,6 +127,108 @@ impl Page {
     }
 }
 
+/// The allocator for all the physical memory, this is setup inside `init`
+/// and until then we allocate directly from the `RangeSet` of the boot
+/// memory
+static FRAME_ALLOCATOR: Mutex<zone::ZoneAllocator> =
+    Mutex::new(zone::ZoneAllocator::new());
+
+/// Allocate `count` physically contiguous frames from `zone` or from a
+/// lower zone, this is for devices that need a DMA buffer below some
+/// physical address. Returns the physical address of the first frame
+pub fn allocate_contiguous(count: u64, zone: Zone)
+    -> Option<PhysicalAddress>
+{
+    let frame = FRAME_ALLOCATOR.lock().allocate(count, zone)?;
+    Some(PhysicalAddress(frame.0 * PAGE_SIZE))
+}
+
+/// Give the ACPI reclaimable memory to the frame allocator, the RSDT, the
+/// XSDT and the other tables are inside this memory so this can only be
+/// called when the ACPI tables are parsed and nothing reads them anymore
+// TODO(patrik): Call this from the ACPI init when we parse the tables
+pub fn reclaim_acpi_memory() {
+    let mut memory_map = memory_map::MEMORY_MAP.lock();
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    let mut reclaimed = 0;
+    for range in memory_map.ranges(MemoryKind::AcpiReclaimable).entries() {
+        // We never use the first MiB
+        let start = core::cmp::max(range.start, 1 * 1024 * 1024);
+        let start = (start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let end = range.end.saturating_add(1) & !(PAGE_SIZE - 1);
+        if start >= end {
+            continue;
+        }
+
+        allocator.add_memory(start, end);
+
+        reclaimed += end - start;
+    }
+
+    memory_map.convert(MemoryKind::AcpiReclaimable, MemoryKind::Usable);
+
+    println!("Reclaimed {} KiB of ACPI memory", reclaimed / 1024);
+}
+
+/// Get the free physical memory as a `RangeSet`, the bool is `false` if
+/// the memory is too fragmented to fit inside it. Returns `None` if the
+/// frame allocator is locked, this is used by the monitor that might run
+/// after a panic with the lock held
+pub fn free_memory() -> Option<(RangeSet, bool)> {
+    FRAME_ALLOCATOR.try_lock().map(|allocator| allocator.free_memory())
+}
+
+/// Map the multiboot module with `name`, the module stays mapped until the
+/// address is given to `vfree`
+pub fn map_module(boot_info: &BootInformation, name: &str)
+    -> Option<(VirtualAddress, &'static [u8])>
+{
+    let module = boot_info.module_tags()
+        .find(|x| x.name() == name)?;
+
+    let start = module.start_address() as u64;
+    let size = (module.end_address() as u64).checked_sub(start)?;
+
+    let address = ioremap(PhysicalAddress(start), size,
+                          CacheMode::WriteBack)?;
+    let data = unsafe {
+        core::slice::from_raw_parts(address.0 as *const u8, size as usize)
+    };
+
+    Some((address, data))
+}
+
+/// Get the physical address `address` is mapped to inside the active page
+/// table, this takes no locks so the panic handler can use it. A non
+/// canonical address is never mapped
+pub fn translate(address: VirtualAddress) -> Option<PhysicalAddress> {
+    // The page table walk only looks at bits 12 to 47, so the address
+    // would alias a canonical one
+    if !is_canonical(address.0) {
+        return None;
+    }
+
+    if physmap::enabled() {
+        unsafe { ActivePageTable::new() }.translate(address)
+    } else {
+        unsafe { recursive::RecursivePageTable::new() }.translate(address)
+    }
+}
+
+/// Free `count` frames at `address` allocated with `allocate_contiguous`
+pub fn free_contiguous(address: PhysicalAddress, count: u64) {
+    let frame = PhysicalFrame::containing_address(address);
+
+    for frame in frame.0..frame.0 + count {
+        let refcount = frame_info::put_ref(PhysicalFrame(frame));
+        assert!(refcount == 0, "Freed frame {:#x} is still referenced",
+                frame * PAGE_SIZE);
+    }
+
+    FRAME_ALLOCATOR.lock().free(frame, count);
+}
+
 trait FrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysicalFrame>;
     fn deallocate_frame(&mut self, frame: PhysicalFrame) -> Option<()>;
@@ -68,7 +236,9 @@ trait FrameAllocator {
 
 impl FrameAllocator for RangeSet {
     fn allocate_frame(&mut self) -> Option<PhysicalFrame> {
-        let address = self.allocate(4096, 4096)?;
+        // Keep the boot allocations out of the DMA zones if we can
+        let preferred = zone::boot_preferred_memory();
+        let address = self.allocate_prefer(4096, 4096, Some(&preferred))?;
         let address = PhysicalAddress(address as u64);
         Some(PhysicalFrame::containing_address(address))
     }
@@ -86,195 +256,286 @@ impl FrameAllocator for RangeSet {
     }
 }
 
-const PAGE_TABLE_ENTRIES: usize = 512;
+/// The page table inside CR3, the tables are walked through the direct map
+/// with the walker from the `page_table` crate. This can only be used after
+/// the direct map is setup, before that the boot code uses the recursive
+/// mapping through `RecursivePageTable`
+struct ActivePageTable;
 
-#[derive(Copy, Clone, Debug)]
-struct PageTableEntry(u64);
-
-impl PageTableEntry {
-    fn pointed_frame(&self) -> Option<PhysicalFrame> {
-        if self.0 & PAGE_PRESENT != 0 {
-            Some(PhysicalFrame::containing_address(
-                PhysicalAddress(self.0 & 0x000fffff_fffff000)
-            ))
-        } else {
-            None
-        }
+impl ActivePageTable {
+    unsafe fn new() -> ActivePageTable {
+        assert!(physmap::enabled(), "The direct map is not setup yet");
+
+        ActivePageTable
     }
-}
 
-#[repr(C, packed)]
-struct PageTable {
-    entries: [PageTableEntry; PAGE_TABLE_ENTRIES]
-}
+    /// Execute `f` with the walker for the tables inside CR3
+    fn walk<R, F>(&self, f: F) -> R
+        where F: FnOnce(&mut page_table::PageTable<physmap::PhysmapMemory>)
+                        -> R
+    {
+        let mut memory = physmap::PhysmapMemory;
+        f(&mut physmap::active_table(&mut memory))
+    }
 
-impl PageTable {
-    fn next_table_address(&self, index: usize) -> Option<usize> {
-        let entry = self.entries[index];
-        if entry.0 & PAGE_PRESENT != 0 {
-            let table_address = self as *const _ as usize;
-            Some((table_address << 9) | (index << 12))
-        } else {
-            None
-        }
+    fn translate(&self, virtual_address: VirtualAddress)
+        -> Option<PhysicalAddress>
+    {
+        self.walk(|table| table.translate(virtual_address.0))
+            .map(PhysicalAddress)
     }
 
-    fn zero(&mut self) {
-        // TODO(patrik): Should we use unsafe here?!
-        unsafe {
-            for entry in self.entries.iter_mut() {
-                (*entry) = PageTableEntry(0);
-            }
-        }
+    fn translate_page(&self, page: Page) -> Option<PhysicalFrame> {
+        self.walk(|table| table.translate(page.0 * PAGE_SIZE))
+            .map(|address| PhysicalFrame(address / PAGE_SIZE))
     }
 
-    fn next_table<'a>(&'a self, index: usize) -> Option<&'a PageTable> {
-        self.next_table_address(index)
-            .map(|x| unsafe { &*(x as *const _) })
+    /// Get the mapping of `page`, the mapping can be a huge page
+    fn mapping(&self, page: Page) -> Option<page_table::Mapping> {
+        self.walk(|table| table.mapping(page.0 * PAGE_SIZE))
     }
 
-    fn next_table_mut<'a>(&'a mut self, index: usize) 
-        -> Option<&'a mut PageTable>
+    fn map_to<A>(&mut self, page: Page, frame: PhysicalFrame,
+                 flags: u64, allocator: &mut A)
+        where A: FrameAllocator
     {
-        self.next_table_address(index)
-            .map(|x| unsafe { &mut *(x as *mut _) })
+        self.walk(|table| {
+            table.map_to(page.0 * PAGE_SIZE, frame.0 * PAGE_SIZE, flags,
+                         &mut physmap::TableAllocator(allocator))
+        }).expect("Failed to map the page");
     }
 
-    fn next_table_create<A>(&mut self, index: usize,
-                            allocator: &mut A) -> &mut PageTable 
+    /// Map `page` to `frame` with `flags` and the memory type of
+    /// `cache_mode`, the cache bits inside `flags` are replaced by the bits
+    /// for the mode
+    fn map_to_cached<A>(&mut self, page: Page, frame: PhysicalFrame,
+                        flags: u64, cache_mode: CacheMode, allocator: &mut A)
         where A: FrameAllocator
     {
-        if self.next_table(index).is_none() {
-            assert!(self.entries[index].0 & PAGE_HUGE == 0,
-                    "mapping code does not support huge pages");
-
-            let frame = allocator.allocate_frame()
-                .expect("Failed to allocate frame");
+        self.map_to(page, frame, cache_mode.apply(flags), allocator);
+    }
 
-            self.entries[index] = 
-                PageTableEntry((frame.0 * 4096) | PAGE_PRESENT | PAGE_WRITE);
+    /// Unmap `page` and return the frame it was mapped to, the frame is not
+    /// freed so that is up to the caller
+    fn unmap(&mut self, page: Page) -> PhysicalFrame {
+        let mapping = self.walk(|table| table.unmap(page.0 * PAGE_SIZE))
+            .expect("Trying to unmap a page that is not mapped");
+        assert!(mapping.size == PAGE_SIZE,
+                "mapping code does not support huge pages");
 
-            self.next_table_mut(index).unwrap().zero();
-        }
+        x86_64::invlpg(page.0 * PAGE_SIZE);
 
-        self.next_table_mut(index).unwrap()
+        PhysicalFrame(mapping.physical_address / PAGE_SIZE)
     }
-}
 
-const P4: *mut PageTable = 0xffffffff_fffff000 as *mut _;
-
-
-struct ActivePageTable {
-    top: Unique<PageTable>
-}
+    /// Change the flags of `page` with `f`, returns `None` if the page is
+    /// not mapped
+    fn update_flags<F>(&mut self, page: Page, f: F) -> Option<()>
+        where F: FnOnce(u64) -> u64
+    {
+        self.walk(|table| table.update_flags(page.0 * PAGE_SIZE, f))?;
+        x86_64::invlpg(page.0 * PAGE_SIZE);
 
-impl ActivePageTable {
-    unsafe fn new() -> ActivePageTable {
-        ActivePageTable {
-            top: Unique::new_unchecked(P4),
-        }
+        Some(())
     }
 
-    fn p4(&self) -> &PageTable {
-        unsafe { self.top.as_ref() }
-    }
-    
-    fn p4_mut(&mut self) -> &mut PageTable {
-        unsafe { self.top.as_mut() }
-    }
+    /// Turn the writable mapping of `page` into a copy-on-write mapping and
+    /// return the frame, the frame can then be mapped somewhere else with 
+    /// `map_cow`
+    fn make_cow(&mut self, page: Page) -> PhysicalFrame {
+        let mapping = self.mapping(page)
+            .expect("Trying to share a page that is not mapped");
+        assert!(mapping.size == PAGE_SIZE,
+                "mapping code does not support huge pages");
 
-    fn translate(&self, virtual_address: VirtualAddress) 
-        -> Option<PhysicalAddress> 
-    {
-        // TODO(patrik): Change 4096 to a constant
-        let offset = virtual_address.0 % 4096;
-        self.translate_page(Page::containing_address(virtual_address))
-            .map(|frame| PhysicalAddress(frame.0 * 4096 + offset))
-    }
+        let frame = PhysicalFrame(mapping.physical_address / PAGE_SIZE);
 
-    fn translate_page(&self, page: Page) -> Option<PhysicalFrame> {
-        let p3 = self.p4().next_table(page.p4_index());
-
-        let huge_page = || {
-            p3.and_then(|p3| {
-                if let Some(p2) = p3.next_table(page.p3_index()) {
-                    let p2_entry = unsafe { &p2.entries[page.p2_index()] };
-                    if let Some(start_frame) = p2_entry.pointed_frame() {
-                        if p2_entry.0 & PAGE_HUGE != 0 {
-                            assert!(start_frame.0 % 512 == 0);
-
-                            let frame = 
-                                PhysicalFrame(start_frame.0 + 
-                                              page.p1_index() as u64);
-
-                            return Some(frame);
-                        }
-                    }
-                }
-
-                None
-            })
-        };
+        if mapping.flags & PAGE_WRITE != 0 {
+            self.update_flags(page, |flags| (flags & !PAGE_WRITE) | PAGE_COW);
+        }
 
-        let frame = 
-            unsafe {
-                p3.and_then(|p3| p3.next_table(page.p3_index()))
-                    .and_then(|p2| p2.next_table(page.p2_index()))
-                    .and_then(|p1| p1.entries[page.p1_index()].pointed_frame())
-                    .or_else(huge_page)
-            };
+        frame_info::with_frame(frame, |info| {
+            info.flags |= frame_info::FRAME_COW;
+        }).expect("Failed to update the frame metadata");
 
         frame
     }
 
-    fn map_to<A>(&mut self, page: Page, frame: PhysicalFrame, 
-                 flags: u64, allocator: &mut A)
+    /// Map `page` to the shared `frame` as copy-on-write, `flags` is the
+    /// flags the page gets when the private copy is made
+    fn map_cow<A>(&mut self, page: Page, frame: PhysicalFrame, flags: u64,
+                  allocator: &mut A)
         where A: FrameAllocator
     {
-        let p4 = self.p4_mut(); 
-        let p3 = p4.next_table_create(page.p4_index(), allocator);
-        let p2 = p3.next_table_create(page.p3_index(), allocator);
-        let p1 = p2.next_table_create(page.p2_index(), allocator);
-
-        assert!(p1.entries[page.p1_index()].0 == 0);
-        p1.entries[page.p1_index()] = 
-            PageTableEntry(frame.0 * 4096 | PAGE_PRESENT | flags);
+        frame_info::get_ref(frame);
+
+        let flags = if flags & PAGE_WRITE != 0 {
+            (flags & !PAGE_WRITE) | PAGE_COW
+        } else {
+            flags
+        };
+
+        self.map_to(page, frame, flags, allocator);
     }
 }
 
 // TODO(patrik):
-//   - Page Tables (unmapping)
 //   - Kernel Heap 
 //   - Global Allocator (so we can use the core::alloc stuff)
 
-fn print_table_entries(table: &PageTable) {
-    unsafe {
-        for (i, entry) in table.entries.iter().enumerate() {
-            if entry.0 != 0 {
-                println!("Entry {} = {:#x}", i, entry.0); 
-            }
-        }
-    }
-}
-
-pub fn init(physical_memory: &mut RangeSet) {
+pub fn init(boot_info: &BootInformation, memory_map: MemoryMap,
+            physical_memory: RangeSet)
+{
     println!("Total Detected Memory: {}MiB", 
              physical_memory.sum().unwrap() as f32 / 1024.0 / 1024.0);
 
-    let mut page_table = unsafe { ActivePageTable::new() };
+    // Save the usable memory before we start to allocate from it so the
+    // direct map covers the frames we use for the page tables aswell
+    let usable_memory = physical_memory;
+
+    // Until the buddy allocator is setup we allocate from the boot memory
+    let mut boot_memory = physical_memory;
+
+    // Program the PAT before any mapping uses the cache modes
+    cache::init();
+
+    // Remap the kernel with the correct permissions for every section
+    let mut page_table = remap::remap_kernel(boot_info, &memory_map,
+                                             &usable_memory,
+                                             &mut boot_memory);
+
+    // The end of the memory we can allocate from, the ACPI reclaimable
+    // memory is included so it can be given to the allocator later
+    let memory_end = memory_map.areas()
+        .filter(|x| x.kind == MemoryKind::Usable ||
+                    x.kind == MemoryKind::AcpiReclaimable)
+        .map(|x| x.end)
+        .max()
+        .unwrap();
+
+    // Setup the metadata for all the frames, this uses the direct map
+    frame_info::init(&usable_memory, memory_end, &mut boot_memory);
+
+    // Give the rest of the boot memory to the zone allocators
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    allocator.init(&mut boot_memory, memory_end);
 
     let address = VirtualAddress(42 * 512 * 512 * 4096);
     let page = Page::containing_address(address);
-    let frame = physical_memory.allocate_frame()
+    let frame = allocator.allocate_frame()
         .expect("Failed to allocate frame");
 
     println!("Mapping virtual address: {:#x}", address.0);
     println!("'{:#x?}' maps to {:#x}", page_table.translate(address), frame.0);
 
-    page_table.map_to(page, frame, 0, physical_memory);
+    page_table.map_to(page, frame, 0, &mut *allocator);
     println!("Some = {:#x?}", page_table.translate(address));
-    println!("next free frame: {:?}", physical_memory.allocate_frame());
+    println!("next free frame: {:?}", allocator.allocate_frame());
 
-    let address = page_table.translate(VirtualAddress(0xb8000));
+    let address = 
+        page_table.translate(VirtualAddress(kernel_offset() + 0xb8000));
     println!("Address: {:#x?}", address);
+
+    // The page fault handler needs the allocator
+    drop(allocator);
+
+    // Save the memory map so the ACPI memory can be reclaimed later
+    *memory_map::MEMORY_MAP.lock() = memory_map;
+
+    stats::print_stats();
+}
+
+/// Run the memory tests, this is for "memtest" on the command line. Every
+/// test gives back what it allocated so the tests can run on every boot
+/// without taking memory or address space from the kernel
+pub fn self_test() {
+    // Test a contiguous allocation for a device limited to ISA DMA
+    let dma = allocate_contiguous(3, Zone::Dma)
+        .expect("Failed to allocate from the DMA zone");
+    println!("DMA buffer: {:#x} - {:#x}", dma.0, dma.0 + 3 * PAGE_SIZE);
+    free_contiguous(dma, 3);
+
+    // Test the vmalloc by writing to both ends of a buffer that is larger
+    // than the largest buddy block
+    let size = 5 * 1024 * 1024;
+    let buffer = vmalloc(size).expect("Failed to vmalloc the test buffer");
+
+    unsafe {
+        core::ptr::write_volatile(buffer.0 as *mut u64, 0xdead);
+        core::ptr::write_volatile((buffer.0 + size - 8) as *mut u64, 0xbeef);
+    }
+
+    println!("vmalloc buffer: {:#x} ({} KiB)", buffer.0, size / 1024);
+    vfree(buffer);
+
+    // Test a vmalloc with another cache mode, e.g. for a buffer a device
+    // reads from
+    let buffer = vmalloc_cached(PAGE_SIZE, CacheMode::Uncached)
+        .expect("Failed to vmalloc the uncached test buffer");
+    unsafe { core::ptr::write_volatile(buffer.0 as *mut u64, 0xcafe); }
+    println!("Uncached vmalloc buffer: {:#x}", buffer.0);
+    vfree(buffer);
+
+    // Test the ioremap by reading the first character of the VGA buffer,
+    // the cache mode needs to match the other mapping of the buffer
+    let vga = ioremap(PhysicalAddress(0xb8000), 80 * 25 * 2,
+                      CacheMode::WriteCombining)
+        .expect("Failed to ioremap the VGA buffer");
+    println!("ioremap VGA buffer: {:#x} first character {:#x}", vga.0,
+             unsafe { core::ptr::read_volatile(vga.0 as *const u16) });
+    vfree(vga);
+
+    // Test the demand paging by touching a page inside an anonymous region
+    let address = 43 * 512 * 512 * 4096;
+    let pages = 4;
+    KERNEL_ADDRESS_SPACE.lock().add_region(Region {
+        name: "demand paging test",
+        start: address,
+        end: address + pages * PAGE_SIZE,
+        flags: PAGE_WRITE | PAGE_NXE,
+        kind: RegionKind::Anonymous,
+    }).expect("Failed to add the demand paging test region");
+
+    unsafe {
+        let pointer = (address + PAGE_SIZE) as *mut u64;
+        core::ptr::write_volatile(pointer, 0x1337);
+        println!("Demand paged value: {:#x}", 
+                 core::ptr::read_volatile(pointer));
+    }
+
+    // Test the copy-on-write by sharing the demand paged page with the 
+    // next page inside the region and write to the shared page
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let page = Page::containing_address(VirtualAddress(address + PAGE_SIZE));
+    let shared_page = 
+        Page::containing_address(VirtualAddress(address + 2 * PAGE_SIZE));
+
+    let frame = page_table.make_cow(page);
+    page_table.map_cow(shared_page, frame, PAGE_WRITE | PAGE_NXE,
+                       &mut *FRAME_ALLOCATOR.lock());
+
+    unsafe {
+        let pointer = (address + 2 * PAGE_SIZE) as *mut u64;
+        core::ptr::write_volatile(pointer, 0xc0ffee);
+
+        println!("Copy-on-write values: {:#x} {:#x}",
+                 core::ptr::read_volatile((address + PAGE_SIZE) as *mut u64),
+                 core::ptr::read_volatile(pointer));
+    }
+
+    // Remove the test region and free the frames the faults mapped
+    KERNEL_ADDRESS_SPACE.lock().remove_region(address);
+
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    for index in 0..pages {
+        let page = Page::containing_address(
+            VirtualAddress(address + index * PAGE_SIZE));
+
+        if page_table.translate_page(page).is_some() {
+            let frame = page_table.unmap(page);
+            if frame_info::put_ref(frame) == 0 {
+                allocator.deallocate_frame(frame);
+            }
+        }
+    }
 }
@@ -0,0 +1,201 @@
+//! The direct map of physical memory, all usable physical memory is mapped
+//! linearly starting at `PHYSMAP_OFFSET` so the kernel can access any frame
+//! without setting up a temporary mapping
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use rangeset::{Range, RangeSet};
+use page_table::PhysicalMemory;
+use crate::arch::x86_64;
+use super::{PAGE_SIZE, HUGE_PAGE_SIZE};
+use super::vmalloc::VMALLOC_START;
+use super::{PhysicalAddress, VirtualAddress, PhysicalFrame, Page};
+use super::FrameAllocator;
+use super::recursive::{RecursivePageTable, InactivePageTable};
+
+/// The virtual address where the direct map of physical memory starts,
+/// this is the start of the higher half
+pub const PHYSMAP_OFFSET: u64 = 0xffff_8000_0000_0000;
+
+/// The physical memory the direct map covers, the kernel image, the first
+/// MiB, the modules and the MMIO are holes inside it. This is written once
+/// before `PHYSMAP_ENABLED` is set and never changed after that, so it is
+/// read without a lock like the symbol table
+static mut PHYSMAP_MEMORY: RangeSet = RangeSet::new();
+static PHYSMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Check if the physical `address` is mapped by the direct map
+fn is_mapped(address: u64) -> bool {
+    if !enabled() {
+        return false;
+    }
+
+    let memory = unsafe { &*core::ptr::addr_of!(PHYSMAP_MEMORY) };
+    memory.entries().iter()
+        .any(|range| address >= range.start && address <= range.end)
+}
+
+/// Get the virtual address inside the direct map for `address`, returns
+/// `None` if the address is not mapped by the direct map
+pub fn phys_to_virt(address: PhysicalAddress) -> Option<VirtualAddress> {
+    if !is_mapped(address.0) {
+        return None;
+    }
+
+    Some(VirtualAddress(address.0 + PHYSMAP_OFFSET))
+}
+
+/// Get the virtual address inside the direct map for a frame we got from
+/// one of the frame allocators, the allocators only give out frames the
+/// direct map covers
+pub(super) fn frame_to_virt(frame: PhysicalFrame) -> VirtualAddress {
+    phys_to_virt(PhysicalAddress(frame.0 * PAGE_SIZE))
+        .expect("Allocated frame is not inside the direct map")
+}
+
+/// Get the physical address for `address`, an address inside the direct
+/// map is converted without a walk of the page tables. Every other address
+/// is translated with the active page table so we only return addresses
+/// that are mapped
+pub fn virt_to_phys(address: VirtualAddress) -> Option<PhysicalAddress> {
+    // The direct map ends before the vmalloc window
+    if address.0 >= PHYSMAP_OFFSET && address.0 < VMALLOC_START &&
+        is_mapped(address.0 - PHYSMAP_OFFSET)
+    {
+        return Some(PhysicalAddress(address.0 - PHYSMAP_OFFSET));
+    }
+
+    super::translate(address)
+}
+
+/// Zero the frame through the direct map
+pub(super) fn zero_frame(frame: PhysicalFrame) {
+    let address = frame_to_virt(frame);
+
+    unsafe {
+        core::ptr::write_bytes(address.0 as *mut u8, 0, PAGE_SIZE as usize);
+    }
+}
+
+/// Map all the physical memory inside `memory` to the direct map with
+/// `mapper` and `flags`, we use huge pages when the range covers a full
+/// 2 MiB region. The memory we mapped is added to `mapped`
+pub(super) fn map_physical_memory<A>(mapper: &mut RecursivePageTable,
+                                     memory: &RangeSet, flags: u64,
+                                     mapped: &mut RangeSet,
+                                     allocator: &mut A)
+    where A: FrameAllocator
+{
+    for range in memory.entries() {
+        // Only map the full pages inside the range
+        let start = (range.start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        let end = range.end.saturating_add(1) & !(PAGE_SIZE - 1);
+
+        let mut address = start;
+        while address < end {
+            let page = Page::containing_address(
+                VirtualAddress(address + PHYSMAP_OFFSET));
+            let frame =
+                PhysicalFrame::containing_address(PhysicalAddress(address));
+
+            if address % HUGE_PAGE_SIZE == 0 &&
+                address + HUGE_PAGE_SIZE <= end
+            {
+                mapper.map_to_huge(page, frame, flags, allocator);
+                address += HUGE_PAGE_SIZE;
+            } else {
+                mapper.map_to(page, frame, flags, allocator);
+                address += PAGE_SIZE;
+            }
+        }
+
+        if start < end {
+            mapped.insert(Range {
+                start: start,
+                end: end - 1,
+            });
+        }
+    }
+}
+
+/// Enable `phys_to_virt` for the physical memory inside `mapped`, this
+/// should only be called when the table with the direct map is active
+pub(super) unsafe fn enable(mapped: RangeSet) {
+    assert!(!enabled(), "The direct map is already enabled");
+
+    PHYSMAP_MEMORY = mapped;
+    PHYSMAP_ENABLED.store(true, Ordering::Release);
+}
+
+/// Check if the direct map is setup
+pub(super) fn enabled() -> bool {
+    PHYSMAP_ENABLED.load(Ordering::Acquire)
+}
+
+/// The physical memory backend for the page table walker, every access
+/// goes through the direct map
+pub(super) struct PhysmapMemory;
+
+impl PhysicalMemory for PhysmapMemory {
+    fn read_u64(&self, address: u64) -> u64 {
+        let address = phys_to_virt(PhysicalAddress(address))
+            .expect("Page table is not inside the direct map");
+        unsafe { core::ptr::read_volatile(address.0 as *const u64) }
+    }
+
+    fn write_u64(&mut self, address: u64, value: u64) {
+        let address = phys_to_virt(PhysicalAddress(address))
+            .expect("Page table is not inside the direct map");
+        unsafe { core::ptr::write_volatile(address.0 as *mut u64, value); }
+    }
+}
+
+/// Lets the page table walker allocate the frames for new tables from one
+/// of our frame allocators
+pub(super) struct TableAllocator<'a, A: FrameAllocator>(pub &'a mut A);
+
+impl<'a, A: FrameAllocator> page_table::FrameAllocator
+    for TableAllocator<'a, A>
+{
+    fn allocate_frame(&mut self) -> Option<u64> {
+        self.0.allocate_frame().map(|frame| frame.0 * PAGE_SIZE)
+    }
+}
+
+/// Get the walker for the page table inside CR3
+pub(super) fn active_table(memory: &mut PhysmapMemory)
+    -> page_table::PageTable<'_, PhysmapMemory>
+{
+    let p4 = x86_64::cr3() & page_table::ADDRESS_MASK;
+    page_table::PageTable::new(memory, p4)
+}
+
+impl InactivePageTable {
+    /// Get the walker for this table, so we can edit the table without
+    /// redirecting the recursive mapping
+    fn table<'a>(&self, memory: &'a mut PhysmapMemory)
+        -> page_table::PageTable<'a, PhysmapMemory>
+    {
+        page_table::PageTable::new(memory, self.p4_frame.0 * PAGE_SIZE)
+    }
+
+    /// Translate `page` with the page table walked through the direct map
+    fn translate_page(&self, page: Page) -> Option<PhysicalFrame> {
+        let mut memory = PhysmapMemory;
+
+        self.table(&mut memory).translate(page.0 * PAGE_SIZE)
+            .map(|address| PhysicalFrame(address / PAGE_SIZE))
+    }
+
+    /// Map `page` to `frame` inside this table through the direct map
+    fn map_to<A>(&mut self, page: Page, frame: PhysicalFrame,
+                 flags: u64, allocator: &mut A)
+        where A: FrameAllocator
+    {
+        let mut memory = PhysmapMemory;
+
+        self.table(&mut memory)
+            .map_to(page.0 * PAGE_SIZE, frame.0 * PAGE_SIZE, flags,
+                    &mut TableAllocator(allocator))
+            .expect("Failed to map the page");
+    }
+}
@@ -0,0 +1,358 @@
+//! The page table access through the recursive mapping, this is only used
+//! at boot to build the table with the direct map. When the direct map is
+//! setup every table is walked through it with the `page_table` crate
+
+use core::ptr::Unique;
+use crate::arch::x86_64;
+use super::{PAGE_SIZE, PAGE_PRESENT, PAGE_WRITE, PAGE_HUGE, PAGE_NXE};
+use super::{PhysicalAddress, PhysicalFrame, VirtualAddress, Page};
+use super::{FrameAllocator, physmap};
+
+const PAGE_TABLE_ENTRIES: usize = 512;
+
+/// The slot inside the P4 table we use for the recursive mapping, the last
+/// slot is used by the higher half kernel
+const RECURSIVE_INDEX: usize = 510;
+
+const P4: *mut PageTable = 0xffffff7f_bfdfe000 as *mut _;
+
+/// The virtual page we use to temporary map frames, e.g. when we need to
+/// edit a page table that is not the active one
+pub(super) const TEMPORARY_PAGE: u64 = 0xffff_fe80_0000_0000;
+
+#[derive(Copy, Clone, Debug)]
+pub(super) struct PageTableEntry(pub u64);
+
+impl PageTableEntry {
+    fn pointed_frame(&self) -> Option<PhysicalFrame> {
+        if self.0 & PAGE_PRESENT != 0 {
+            Some(PhysicalFrame::containing_address(
+                PhysicalAddress(self.0 & 0x000fffff_fffff000)
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+#[repr(C, packed)]
+pub(super) struct PageTable {
+    entries: [PageTableEntry; PAGE_TABLE_ENTRIES]
+}
+
+impl PageTable {
+    fn next_table_address(&self, index: usize) -> Option<usize> {
+        let entry = self.entries[index];
+
+        // Huge pages don't point to a table so we can't follow them
+        if entry.0 & PAGE_PRESENT != 0 && entry.0 & PAGE_HUGE == 0 {
+            let table_address = self as *const _ as usize;
+            let address = (table_address << 9) | (index << 12);
+
+            // The recursive index is not 511 so the shift above can
+            // make the address non canonical, so sign extend bit 47
+            Some((((address << 16) as isize) >> 16) as usize)
+        } else {
+            None
+        }
+    }
+
+    fn zero(&mut self) {
+        // TODO(patrik): Should we use unsafe here?!
+        unsafe {
+            for entry in self.entries.iter_mut() {
+                (*entry) = PageTableEntry(0);
+            }
+        }
+    }
+
+    fn next_table<'a>(&'a self, index: usize) -> Option<&'a PageTable> {
+        self.next_table_address(index)
+            .map(|x| unsafe { &*(x as *const _) })
+    }
+
+    fn next_table_mut<'a>(&'a mut self, index: usize)
+        -> Option<&'a mut PageTable>
+    {
+        self.next_table_address(index)
+            .map(|x| unsafe { &mut *(x as *mut _) })
+    }
+
+    fn next_table_create<A>(&mut self, index: usize,
+                            allocator: &mut A) -> &mut PageTable
+        where A: FrameAllocator
+    {
+        if self.next_table(index).is_none() {
+            assert!(self.entries[index].0 & PAGE_HUGE == 0,
+                    "mapping code does not support huge pages");
+
+            let frame = allocator.allocate_frame()
+                .expect("Failed to allocate frame");
+
+            self.entries[index] =
+                PageTableEntry((frame.0 * 4096) | PAGE_PRESENT | PAGE_WRITE);
+
+            self.next_table_mut(index).unwrap().zero();
+        }
+
+        self.next_table_mut(index).unwrap()
+    }
+}
+
+/// The page table inside CR3 accessed through the recursive mapping, this
+/// can't be used after the direct map is setup
+pub(super) struct RecursivePageTable {
+    top: Unique<PageTable>
+}
+
+impl RecursivePageTable {
+    pub unsafe fn new() -> RecursivePageTable {
+        assert!(!physmap::enabled(),
+                "The recursive mapping is only used before the direct map \
+                 is setup");
+
+        RecursivePageTable {
+            top: Unique::new_unchecked(P4),
+        }
+    }
+
+    fn p4(&self) -> &PageTable {
+        unsafe { self.top.as_ref() }
+    }
+
+    fn p4_mut(&mut self) -> &mut PageTable {
+        unsafe { self.top.as_mut() }
+    }
+
+    pub fn translate(&self, virtual_address: VirtualAddress)
+        -> Option<PhysicalAddress>
+    {
+        let offset = virtual_address.0 % PAGE_SIZE;
+        self.translate_page(Page::containing_address(virtual_address))
+            .map(|frame| PhysicalAddress(frame.0 * PAGE_SIZE + offset))
+    }
+
+    pub fn translate_page(&self, page: Page) -> Option<PhysicalFrame> {
+        let p3 = self.p4().next_table(page.p4_index());
+
+        let huge_page = || {
+            p3.and_then(|p3| {
+                if let Some(p2) = p3.next_table(page.p3_index()) {
+                    let p2_entry = unsafe { &p2.entries[page.p2_index()] };
+                    if let Some(start_frame) = p2_entry.pointed_frame() {
+                        if p2_entry.0 & PAGE_HUGE != 0 {
+                            assert!(start_frame.0 % 512 == 0);
+
+                            let frame =
+                                PhysicalFrame(start_frame.0 +
+                                              page.p1_index() as u64);
+
+                            return Some(frame);
+                        }
+                    }
+                }
+
+                None
+            })
+        };
+
+        let frame =
+            unsafe {
+                p3.and_then(|p3| p3.next_table(page.p3_index()))
+                    .and_then(|p2| p2.next_table(page.p2_index()))
+                    .and_then(|p1| p1.entries[page.p1_index()].pointed_frame())
+                    .or_else(huge_page)
+            };
+
+        frame
+    }
+
+    pub fn map_to<A>(&mut self, page: Page, frame: PhysicalFrame,
+                     flags: u64, allocator: &mut A)
+        where A: FrameAllocator
+    {
+        let p4 = self.p4_mut();
+        let p3 = p4.next_table_create(page.p4_index(), allocator);
+        let p2 = p3.next_table_create(page.p3_index(), allocator);
+        let p1 = p2.next_table_create(page.p2_index(), allocator);
+
+        assert!(p1.entries[page.p1_index()].0 == 0);
+        p1.entries[page.p1_index()] =
+            PageTableEntry(frame.0 * 4096 | PAGE_PRESENT | flags);
+    }
+
+    /// Map the 2 MiB region starting at `page` to the 2 MiB region starting
+    /// at `frame` with a huge page
+    pub fn map_to_huge<A>(&mut self, page: Page, frame: PhysicalFrame,
+                          flags: u64, allocator: &mut A)
+        where A: FrameAllocator
+    {
+        assert!(page.0 % 512 == 0 && frame.0 % 512 == 0,
+                "Huge pages needs to be aligned to 2 MiB");
+
+        let p4 = self.p4_mut();
+        let p3 = p4.next_table_create(page.p4_index(), allocator);
+        let p2 = p3.next_table_create(page.p3_index(), allocator);
+
+        assert!(p2.entries[page.p2_index()].0 == 0);
+        p2.entries[page.p2_index()] =
+            PageTableEntry(frame.0 * PAGE_SIZE |
+                           PAGE_PRESENT | PAGE_HUGE | flags);
+    }
+
+    /// Unmap `page` and return the frame it was mapped to, the frame is not
+    /// freed so that is up to the caller
+    pub fn unmap(&mut self, page: Page) -> PhysicalFrame {
+        let p1 = self.p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))
+            .expect("mapping code does not support huge pages");
+
+        let frame = p1.entries[page.p1_index()].pointed_frame()
+            .expect("Trying to unmap a page that is not mapped");
+        p1.entries[page.p1_index()] = PageTableEntry(0);
+
+        x86_64::invlpg(page.0 * PAGE_SIZE);
+
+        frame
+    }
+
+    /// Get the P1 entry for `page`, returns `None` if one of the tables
+    /// is missing or if the page is part of a huge page
+    pub fn entry_mut(&mut self, page: Page) -> Option<&mut PageTableEntry> {
+        let p1 = self.p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))?;
+
+        Some(&mut p1.entries[page.p1_index()])
+    }
+
+    /// Execute `f` with the recursive mapping redirected to `table`, so all
+    /// the mapping functions modifies `table` instead of the active table
+    pub fn with<A, F>(&mut self, table: &mut InactivePageTable,
+                      temporary_page: &mut TemporaryPage,
+                      allocator: &mut A, f: F)
+        where A: FrameAllocator,
+              F: FnOnce(&mut RecursivePageTable, &mut A)
+    {
+        let active_frame = PhysicalFrame::containing_address(
+            PhysicalAddress(x86_64::cr3() & 0x000fffff_fffff000));
+
+        {
+            // Map the active P4 table so we can restore the recursive
+            // mapping after we are done
+            let p4 = temporary_page.map_table_frame(active_frame,
+                                                    self, allocator);
+
+            // Point the recursive entry to the inactive table
+            self.p4_mut().entries[RECURSIVE_INDEX] =
+                PageTableEntry(table.p4_frame.0 * PAGE_SIZE |
+                               PAGE_PRESENT | PAGE_WRITE | PAGE_NXE);
+            x86_64::flush_tlb();
+
+            f(self, allocator);
+
+            // Restore the recursive mapping to the active table
+            p4.entries[RECURSIVE_INDEX] =
+                PageTableEntry(active_frame.0 * PAGE_SIZE |
+                               PAGE_PRESENT | PAGE_WRITE | PAGE_NXE);
+            x86_64::flush_tlb();
+        }
+
+        temporary_page.unmap(self);
+    }
+
+    /// Switch to `new_table` and return the table that was active before
+    pub fn switch(&mut self, new_table: InactivePageTable)
+        -> InactivePageTable
+    {
+        let old_table = InactivePageTable {
+            p4_frame: PhysicalFrame::containing_address(
+                PhysicalAddress(x86_64::cr3() & 0x000fffff_fffff000)),
+        };
+
+        x86_64::set_cr3(new_table.p4_frame.0 * PAGE_SIZE);
+
+        old_table
+    }
+}
+
+/// A page we can use to map a frame for a short time
+pub(super) struct TemporaryPage {
+    page: Page,
+}
+
+impl TemporaryPage {
+    pub fn new(page: Page) -> TemporaryPage {
+        TemporaryPage {
+            page: page,
+        }
+    }
+
+    /// Map the temporary page to `frame` and return the virtual address
+    fn map<A>(&mut self, frame: PhysicalFrame,
+              active_table: &mut RecursivePageTable,
+              allocator: &mut A) -> VirtualAddress
+        where A: FrameAllocator
+    {
+        assert!(active_table.translate_page(self.page).is_none(),
+                "Temporary page is already mapped");
+
+        active_table.map_to(self.page, frame,
+                            PAGE_WRITE | PAGE_NXE, allocator);
+
+        VirtualAddress(self.page.0 * PAGE_SIZE)
+    }
+
+    /// Map the temporary page to the page table inside `frame`
+    fn map_table_frame<'a, A>(&mut self, frame: PhysicalFrame,
+                              active_table: &mut RecursivePageTable,
+                              allocator: &mut A) -> &'a mut PageTable
+        where A: FrameAllocator
+    {
+        let address = self.map(frame, active_table, allocator);
+        unsafe { &mut *(address.0 as *mut PageTable) }
+    }
+
+    fn unmap(&mut self, active_table: &mut RecursivePageTable) {
+        active_table.unmap(self.page);
+    }
+}
+
+/// A page table that is not loaded inside CR3, it can be modified with
+/// `RecursivePageTable::with` and loaded with `RecursivePageTable::switch`
+pub(super) struct InactivePageTable {
+    pub p4_frame: PhysicalFrame,
+}
+
+impl InactivePageTable {
+    /// Create a new page table inside `frame`, the table is empty except
+    /// for the recursive mapping
+    pub fn new<A>(frame: PhysicalFrame,
+                  active_table: &mut RecursivePageTable,
+                  temporary_page: &mut TemporaryPage,
+                  allocator: &mut A) -> InactivePageTable
+        where A: FrameAllocator
+    {
+        {
+            let table = temporary_page.map_table_frame(frame, active_table,
+                                                       allocator);
+            table.zero();
+
+            // Map the last entry to the table itself so the recursive
+            // mapping works when the table is active or redirected to,
+            // nothing should execute the page tables so mark it no execute
+            table.entries[RECURSIVE_INDEX] =
+                PageTableEntry(frame.0 * PAGE_SIZE |
+                               PAGE_PRESENT | PAGE_WRITE | PAGE_NXE);
+        }
+
+        temporary_page.unmap(active_table);
+
+        InactivePageTable {
+            p4_frame: frame,
+        }
+    }
+}
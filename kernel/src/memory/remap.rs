@@ -0,0 +1,253 @@
+use multiboot2::{BootInformation, ElfSection, ElfSectionFlags};
+use rangeset::RangeSet;
+use crate::arch::x86_64;
+use super::physmap;
+use super::memory_map::{MemoryMap, MemoryKind};
+use super::{PAGE_SIZE, PAGE_WRITE, PAGE_NXE, KERNEL_OFFSET};
+use super::{kernel_offset, kernel_slide};
+use super::{PhysicalAddress, PhysicalFrame, VirtualAddress, Page};
+use super::CacheMode;
+use super::{FrameAllocator, ActivePageTable};
+use super::recursive::{RecursivePageTable, InactivePageTable, TemporaryPage};
+use super::recursive::TEMPORARY_PAGE;
+use super::vma::{KERNEL_ADDRESS_SPACE, Region, RegionKind};
+
+/// The physical address of the VGA text buffer
+const VGA_BUFFER_ADDRESS: u64 = 0xb8000;
+
+/// Convert the flags of an ELF section to the page flags we should map
+/// the section with
+fn section_flags(section: &ElfSection) -> u64 {
+    let mut flags = 0;
+
+    if section.flags().contains(ElfSectionFlags::WRITABLE) {
+        flags |= PAGE_WRITE;
+    }
+
+    if !section.flags().contains(ElfSectionFlags::EXECUTABLE) {
+        flags |= PAGE_NXE;
+    }
+
+    flags
+}
+
+/// Add a region to the kernel address space for memory we map up front,
+/// so the page fault handler can report what a faulting address belongs to
+fn add_fixed_region(name: &'static str, start: u64, end: u64, flags: u64) {
+    KERNEL_ADDRESS_SPACE.lock().add_region(Region {
+        name: name,
+        start: start,
+        end: end,
+        flags: flags,
+        kind: RegionKind::Fixed,
+    }).expect("Failed to add the region to the kernel address space");
+}
+
+/// Check that a kernel page is not both writable and executable, the
+/// linker script keeps the code on its own pages so this never happens
+fn assert_w_xor_x(page: Page, flags: u64) {
+    assert!(flags & PAGE_WRITE == 0 || flags & PAGE_NXE != 0,
+            "Kernel page {:#x} is both writable and executable",
+            page.0 * PAGE_SIZE);
+}
+
+/// Map the physical range `start` to `end` (exclusive) to the higher half
+/// alias at `kernel_offset()` with `flags` and `cache_mode`, pages that are
+/// already mapped keep their frame but get the write and execute
+/// permissions of `flags` aswell
+fn map_kernel_range<A>(active_table: &mut RecursivePageTable,
+                       start: u64, end: u64, flags: u64,
+                       cache_mode: CacheMode, allocator: &mut A)
+    where A: FrameAllocator
+{
+    let start = PhysicalFrame::containing_address(PhysicalAddress(start));
+    let end = PhysicalFrame::containing_address(PhysicalAddress(end - 1));
+
+    for frame in start.0..=end.0 {
+        let frame = PhysicalFrame(frame);
+        let page = Page::containing_address(
+            VirtualAddress(frame.0 * PAGE_SIZE + kernel_offset()));
+
+        if active_table.translate_page(page).is_some() {
+            // A page shared by two ranges needs to allow what both of
+            // them needs, otherwise one of them faults
+            if let Some(entry) = active_table.entry_mut(page) {
+                entry.0 |= flags & PAGE_WRITE;
+
+                if flags & PAGE_NXE == 0 {
+                    entry.0 &= !PAGE_NXE;
+                }
+
+                assert_w_xor_x(page, entry.0);
+            }
+
+            continue;
+        }
+
+        assert_w_xor_x(page, flags);
+
+        active_table.map_to(page, frame, cache_mode.apply(flags),
+                            allocator);
+    }
+}
+
+/// Build a new page table where every kernel section is mapped with
+/// the permissions from the ELF sections tag, then switch to that table
+/// and leave the identity map and the higher half map from the boot 
+/// code behind. All the memory inside `usable_memory` and the ACPI memory
+/// from `memory_map` is mapped to the direct map
+pub(super) fn remap_kernel<A>(boot_info: &BootInformation,
+                              memory_map: &MemoryMap,
+                              usable_memory: &RangeSet,
+                              allocator: &mut A) -> ActivePageTable
+    where A: FrameAllocator
+{
+    // We need to enable the no execute bit before we use it inside any of
+    // the tables otherwise the NX bit is a reserved bit and we would
+    // page fault
+    let efer = x86_64::rdmsr(x86_64::IA32_EFER);
+    x86_64::wrmsr(x86_64::IA32_EFER, efer | x86_64::EFER_NXE);
+
+    let mut active_table = unsafe { RecursivePageTable::new() };
+    let mut temporary_page = TemporaryPage::new(
+        Page::containing_address(VirtualAddress(TEMPORARY_PAGE)));
+
+    let frame = allocator.allocate_frame()
+        .expect("Failed to allocate frame for the new page table");
+    let mut new_table = InactivePageTable::new(frame, &mut active_table,
+                                               &mut temporary_page,
+                                               allocator);
+
+    let mut physmap_memory = RangeSet::new();
+    let mut physmap_end = 0;
+
+    active_table.with(&mut new_table, &mut temporary_page, allocator,
+                      |mapper, allocator| {
+        let elf_sections = boot_info.elf_sections_tag()
+            .expect("Failed to retrive the elf sections tag");
+
+        for section in elf_sections.sections() {
+            // Skip the sections that is not loaded into memory
+            if !section.is_allocated() || section.size() == 0 {
+                continue;
+            }
+
+            // The sections below the higher half is the boot code and we
+            // don't need that after we entered the kernel
+            if section.start_address() < KERNEL_OFFSET {
+                continue;
+            }
+
+            // The linker script aligns every section it knows about, a
+            // section it doesn't know can share its first page with the
+            // section before it, that page gets the permissions of both.
+            // The code is padded to a full page so the shared page is
+            // never writable and executable
+            if section.start_address() % PAGE_SIZE != 0 {
+                println!("Warning: section '{}' is not page aligned, the \
+                          first page is shared", section.name());
+            }
+
+            let flags = section_flags(&section);
+
+            // The tag has the addresses the kernel is linked at, the
+            // kernel runs at those addresses plus the slide
+            let start = section.start_address() + kernel_slide();
+            let end = section.end_address() + kernel_slide();
+
+            println!("Mapping section '{}' {:#x} - {:#x} (W: {}, X: {})",
+                     section.name(), start, end,
+                     flags & PAGE_WRITE != 0, flags & PAGE_NXE == 0);
+
+            map_kernel_range(mapper,
+                             section.start_address() - KERNEL_OFFSET,
+                             section.end_address() - KERNEL_OFFSET,
+                             flags, CacheMode::WriteBack, allocator);
+
+            let name = if flags & PAGE_NXE == 0 {
+                "kernel code"
+            } else if flags & PAGE_WRITE != 0 {
+                "kernel data"
+            } else {
+                "kernel read only data"
+            };
+
+            add_fixed_region(name, start, end, flags);
+        }
+
+        // Map the VGA buffer so we can still print to the screen, the
+        // buffer is video memory so the writes are combined
+        map_kernel_range(mapper,
+                         VGA_BUFFER_ADDRESS,
+                         VGA_BUFFER_ADDRESS + PAGE_SIZE,
+                         PAGE_WRITE | PAGE_NXE, CacheMode::WriteCombining,
+                         allocator);
+        add_fixed_region("vga buffer",
+                         VGA_BUFFER_ADDRESS + kernel_offset(),
+                         VGA_BUFFER_ADDRESS + kernel_offset() + PAGE_SIZE,
+                         PAGE_WRITE | PAGE_NXE |
+                         CacheMode::WriteCombining.page_flags());
+
+        // Map the multiboot structure as read only so we can still read
+        // the boot infomation
+        map_kernel_range(mapper,
+                         boot_info.start_address() as u64 - kernel_offset(),
+                         boot_info.end_address() as u64 - kernel_offset(),
+                         PAGE_NXE, CacheMode::WriteBack, allocator);
+        add_fixed_region("multiboot infomation",
+                         boot_info.start_address() as u64,
+                         boot_info.end_address() as u64,
+                         PAGE_NXE);
+
+        // Map all the usable physical memory to the direct map, the ACPI
+        // reclaimable memory is mapped writable aswell because it becomes
+        // usable memory when the ACPI tables has been parsed
+        let mut writable = *usable_memory;
+        for range in memory_map.ranges(MemoryKind::AcpiReclaimable)
+            .entries()
+        {
+            writable.insert(*range);
+        }
+
+        physmap::map_physical_memory(mapper, &writable,
+                                     PAGE_WRITE | PAGE_NXE,
+                                     &mut physmap_memory, allocator);
+
+        // The firmware owns the ACPI NVS memory so we only need to read
+        // it, the reserved and defective memory is never mapped
+        let acpi_nvs = memory_map.ranges(MemoryKind::AcpiNvs);
+        physmap::map_physical_memory(mapper, &acpi_nvs, PAGE_NXE,
+                                     &mut physmap_memory, allocator);
+
+        physmap_end = physmap_memory.entries().iter()
+            .map(|range| range.end + 1)
+            .max()
+            .unwrap_or(0);
+
+        println!("Direct map of physical memory: {:#x} - {:#x}",
+                 physmap::PHYSMAP_OFFSET,
+                 physmap::PHYSMAP_OFFSET + physmap_end);
+
+        add_fixed_region("direct map",
+                         physmap::PHYSMAP_OFFSET,
+                         physmap::PHYSMAP_OFFSET + physmap_end,
+                         PAGE_WRITE | PAGE_NXE);
+    });
+
+    // Switch to the new table, the old table is the boot page table 
+    // which we don't need anymore
+    let old_table = active_table.switch(new_table);
+
+    // The direct map is active now so we can use it
+    unsafe { physmap::enable(physmap_memory); }
+
+    // Make the kernel respect the write bit so writes to
+    // read only pages fault
+    x86_64::set_cr0(x86_64::cr0() | x86_64::CR0_WP);
+
+    println!("Switched to the new page table at {:#x} (old table {:#x})",
+             frame.0 * PAGE_SIZE, old_table.p4_frame.0 * PAGE_SIZE);
+
+    // From now on the tables are walked through the direct map
+    unsafe { ActivePageTable::new() }
+}
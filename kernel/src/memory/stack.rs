@@ -0,0 +1,135 @@
+//! Kernel stacks, every stack gets a slot inside the stack window and is
+//! mapped at the top of the slot so the rest of the slot below the stack is
+//! unmapped. A stack overflow hits those guard pages and faults instead of
+//! silently corrupting the memory below the stack
+
+use spin::Mutex;
+use super::{PAGE_SIZE, PAGE_WRITE, PAGE_NXE};
+use super::{VirtualAddress, Page};
+use super::{FRAME_ALLOCATOR, FrameAllocator, ActivePageTable};
+use super::frame_info;
+
+/// The start of the kernel stack window, this is right after the vmalloc
+/// window
+pub const STACKS_START: u64 = 0xffff_c100_0000_0000;
+
+/// The number of pages inside a slot, a stack can use all but one page so
+/// there is always at least one guard page
+const SLOT_PAGES: u64 = 32;
+const SLOT_SIZE: u64 = SLOT_PAGES * PAGE_SIZE;
+
+/// The maximum number of stacks
+const MAX_STACKS: usize = 256;
+
+/// The end of the kernel stack window (exclusive)
+pub const STACKS_END: u64 = STACKS_START + MAX_STACKS as u64 * SLOT_SIZE;
+
+/// The number of pages of every allocated stack, zero for a free slot
+static STACKS: Mutex<[u8; MAX_STACKS]> = Mutex::new([0; MAX_STACKS]);
+
+#[derive(Copy, Clone, Debug)]
+pub struct KernelStack {
+    slot: usize,
+    pages: u64,
+}
+
+impl KernelStack {
+    /// The lowest address of the stack (inclusive)
+    pub fn bottom(&self) -> u64 {
+        self.top() - self.pages * PAGE_SIZE
+    }
+
+    /// The address the stack pointer starts at, the stack grows down from
+    /// here
+    pub fn top(&self) -> u64 {
+        STACKS_START + (self.slot as u64 + 1) * SLOT_SIZE
+    }
+}
+
+/// Allocate and map a kernel stack of `pages` pages
+pub fn allocate_stack(pages: u64) -> Option<KernelStack> {
+    assert!(pages > 0 && pages < SLOT_PAGES,
+            "Kernel stack of {} pages is too large", pages);
+
+    let slot = {
+        let mut stacks = STACKS.lock();
+        let slot = stacks.iter().position(|&x| x == 0)?;
+        stacks[slot] = pages as u8;
+        slot
+    };
+
+    let stack = KernelStack {
+        slot: slot,
+        pages: pages,
+    };
+
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    for index in 0..pages {
+        let frame = allocator.allocate_frame()
+            .expect("Out of memory for the kernel stack");
+
+        let page = Page::containing_address(
+            VirtualAddress(stack.bottom() + index * PAGE_SIZE));
+        page_table.map_to(page, frame, PAGE_WRITE | PAGE_NXE,
+                          &mut *allocator);
+    }
+
+    Some(stack)
+}
+
+/// Unmap the stack and free the frames, the stack can't be in use
+pub fn free_stack(stack: KernelStack) {
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    for index in 0..stack.pages {
+        let page = Page::containing_address(
+            VirtualAddress(stack.bottom() + index * PAGE_SIZE));
+        let frame = page_table.unmap(page);
+
+        if frame_info::put_ref(frame) == 0 {
+            allocator.deallocate_frame(frame);
+        }
+    }
+
+    STACKS.lock()[stack.slot] = 0;
+}
+
+/// Check if `address` is inside the guard pages of an allocated stack
+pub fn is_guard_page(address: u64) -> bool {
+    if address < STACKS_START || address >= STACKS_END {
+        return false;
+    }
+
+    let slot = ((address - STACKS_START) / SLOT_SIZE) as usize;
+    let offset = (address - STACKS_START) % SLOT_SIZE;
+
+    // This is used inside the fault handlers so we can't wait for the
+    // lock, if someone is holding it we still know the address is inside
+    // the stack window where every unmapped page is a guard page
+    let pages = match STACKS.try_lock() {
+        Some(stacks) => stacks[slot] as u64,
+        None => return true,
+    };
+
+    pages != 0 && offset < SLOT_SIZE - pages * PAGE_SIZE
+}
+
+/// Report a kernel stack overflow if `address` is inside a guard page,
+/// returns `true` if it was a stack overflow
+pub fn check_stack_overflow(address: u64, instruction_pointer: u64) -> bool {
+    if !is_guard_page(address) {
+        return false;
+    }
+
+    emergency_println!("---------- KERNEL STACK OVERFLOW ----------");
+    emergency_println!("Address: {:#x}", address);
+    emergency_println!("Instruction: {:#x}", instruction_pointer);
+    emergency_println!("The guard page below the stack in slot {} was hit",
+                       (address - STACKS_START) / SLOT_SIZE);
+    emergency_println!("-------------------------------------------");
+
+    true
+}
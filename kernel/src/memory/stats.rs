@@ -0,0 +1,297 @@
+//! Statistics about the physical memory and a dump of the page table, so
+//! the state of the memory manager can be inspected at any time and not
+//! only from the messages printed during boot
+
+use super::{PAGE_SIZE, PAGE_PRESENT, PAGE_WRITE, PAGE_USER, PAGE_NXE};
+use super::{PAGE_GLOBAL, PAGE_COW, PAGE_HUGE};
+use super::CacheMode;
+use super::FRAME_ALLOCATOR;
+use super::physmap;
+use super::zone::{Zone, ZoneAllocator, ZONE_COUNT};
+use super::vma::{AddressSpace, KERNEL_ADDRESS_SPACE};
+
+#[derive(Copy, Clone, Debug)]
+pub struct ZoneStats {
+    pub zone: Zone,
+
+    /// The number of frames the zone manages
+    pub total_frames: u64,
+
+    /// The number of free frames inside the zone
+    pub free_frames: u64,
+
+    /// The size in frames of the largest free block inside the zone
+    pub largest_free_block: u64,
+}
+
+impl ZoneStats {
+    pub fn used_frames(&self) -> u64 {
+        self.total_frames - self.free_frames
+    }
+}
+
+// TODO(patrik): Add the usage of every slab cache when we have a heap
+#[derive(Copy, Clone, Debug)]
+pub struct MemoryStats {
+    pub zones: [ZoneStats; ZONE_COUNT],
+
+    /// The number of frames used for the active page table
+    pub page_table_frames: u64,
+
+    /// The number of regions inside the kernel address space
+    pub kernel_regions: usize,
+
+    /// The number of bytes the regions inside the kernel address space
+    /// covers
+    pub kernel_region_bytes: u64,
+}
+
+impl MemoryStats {
+    pub fn total_frames(&self) -> u64 {
+        self.zones.iter().map(|x| x.total_frames).sum()
+    }
+
+    pub fn free_frames(&self) -> u64 {
+        self.zones.iter().map(|x| x.free_frames).sum()
+    }
+
+    pub fn used_frames(&self) -> u64 {
+        self.total_frames() - self.free_frames()
+    }
+
+    /// The size in frames of the largest physically contiguous block we
+    /// can allocate
+    pub fn largest_free_block(&self) -> u64 {
+        self.zones.iter().map(|x| x.largest_free_block).max().unwrap_or(0)
+    }
+}
+
+/// Collect the statistics about the memory
+pub fn stats() -> MemoryStats {
+    let zones = {
+        let allocator = FRAME_ALLOCATOR.lock();
+        zone_stats(&allocator)
+    };
+
+    collect(zones, &KERNEL_ADDRESS_SPACE.lock())
+}
+
+/// Collect the statistics like `stats` but give up if one of the locks is
+/// taken, so this can be used when the code holding it is interrupted
+pub fn try_stats() -> Option<MemoryStats> {
+    let zones = {
+        let allocator = FRAME_ALLOCATOR.try_lock()?;
+        zone_stats(&allocator)
+    };
+
+    let address_space = KERNEL_ADDRESS_SPACE.try_lock()?;
+    Some(collect(zones, &address_space))
+}
+
+fn zone_stats(allocator: &ZoneAllocator) -> [ZoneStats; ZONE_COUNT] {
+    let mut zones = [ZoneStats {
+        zone: Zone::Dma,
+        total_frames: 0,
+        free_frames: 0,
+        largest_free_block: 0,
+    }; ZONE_COUNT];
+
+    for &zone in Zone::ALL.iter() {
+        let stats = allocator.stats(zone);
+
+        zones[zone as usize] = ZoneStats {
+            zone: zone,
+            total_frames: stats.total_frames,
+            free_frames: stats.free_frames,
+            largest_free_block: stats.largest_free_order()
+                .map(|order| 1 << order)
+                .unwrap_or(0),
+        };
+    }
+
+    zones
+}
+
+fn collect(zones: [ZoneStats; ZONE_COUNT], address_space: &AddressSpace)
+    -> MemoryStats
+{
+    let page_table_frames = if physmap::enabled() {
+        let mut memory = physmap::PhysmapMemory;
+        physmap::active_table(&mut memory).table_count()
+    } else {
+        0
+    };
+
+    MemoryStats {
+        zones: zones,
+        page_table_frames: page_table_frames,
+        kernel_regions: address_space.regions().count(),
+        kernel_region_bytes: address_space.regions()
+            .map(|x| x.end - x.start)
+            .sum(),
+    }
+}
+
+/// Print the statistics and the regions of the kernel address space
+pub fn print_stats() {
+    print(&stats(), &KERNEL_ADDRESS_SPACE.lock());
+}
+
+/// Print the statistics like `print_stats` but only if none of the locks
+/// are taken, for the monitor which can interrupt the code holding them
+pub fn try_print_stats() {
+    let stats = try_stats();
+    let address_space = KERNEL_ADDRESS_SPACE.try_lock();
+
+    match (stats, address_space) {
+        (Some(stats), Some(address_space)) => print(&stats, &address_space),
+        _ => println!("Memory: the frame allocator or the kernel address \
+                       space is locked"),
+    }
+}
+
+fn print(stats: &MemoryStats, address_space: &AddressSpace) {
+    let mib = |frames: u64| frames * PAGE_SIZE / 1024 / 1024;
+
+    println!("Memory: {} MiB used, {} MiB free, {} MiB total",
+             mib(stats.used_frames()), mib(stats.free_frames()),
+             mib(stats.total_frames()));
+
+    for zone in stats.zones.iter() {
+        println!("  Zone {:<6}: {} / {} frames used, largest block {} KiB",
+                 zone.zone.name(), zone.used_frames(), zone.total_frames,
+                 zone.largest_free_block * PAGE_SIZE / 1024);
+    }
+
+    println!("  Page tables: {} frames", stats.page_table_frames);
+
+    println!("Kernel regions: {} ({} MiB)", stats.kernel_regions,
+             stats.kernel_region_bytes / 1024 / 1024);
+
+    for region in address_space.regions() {
+        println!("  {:#018x} - {:#018x} {} ({:?})",
+                 region.start, region.end, region.name, region.kind);
+    }
+}
+
+/// The flags we show inside the dump, the accessed and dirty bits changes
+/// all the time so they would break up the ranges. The cache bits are
+/// decoded into the cache mode
+const DUMP_FLAGS: u64 = PAGE_PRESENT | PAGE_WRITE | PAGE_USER | PAGE_NXE |
+    PAGE_GLOBAL | PAGE_COW;
+
+/// A range of pages with the same flags that maps to a contiguous range
+/// of physical memory
+struct DumpRange {
+    start: u64,
+    end: u64,
+    physical: u64,
+    flags: u64,
+    cache_mode: CacheMode,
+}
+
+impl DumpRange {
+    fn print(&self) {
+        let flag = |bit: u64, c: char| {
+            if self.flags & bit != 0 { c } else { '-' }
+        };
+
+        println!("{:#018x} - {:#018x} -> {:#012x} {}{}{}{}{}{} {:<3} {} KiB",
+                 self.start, self.end, self.physical,
+                 'r',
+                 flag(PAGE_WRITE, 'w'),
+                 if self.flags & PAGE_NXE == 0 { 'x' } else { '-' },
+                 flag(PAGE_USER, 'u'),
+                 flag(PAGE_GLOBAL, 'g'),
+                 flag(PAGE_COW, 'o'),
+                 self.cache_mode.short_name(),
+                 (self.end - self.start) / 1024);
+    }
+}
+
+/// Print the entry for `address` inside every level of the active page
+/// table, the walk stops at an entry that is not present or at a huge page
+pub fn print_page_table_entries(address: u64) {
+    if !physmap::enabled() {
+        println!("The direct map is not setup, can't walk the page table");
+        return;
+    }
+
+    let mut memory = physmap::PhysmapMemory;
+    let table = physmap::active_table(&mut memory);
+
+    let mut physical = table.p4();
+
+    for level in (1..=4).rev() {
+        let index = page_table::table_index(address, level);
+        let entry = table.entry(physical, index);
+
+        println!("P{}[{:>3}] at {:#x}: {:#018x}", level, index,
+                 physical + index as u64 * 8, entry);
+
+        if entry & PAGE_PRESENT == 0 {
+            println!("Not present");
+            return;
+        }
+
+        if level > 1 && entry & PAGE_HUGE != 0 {
+            break;
+        }
+
+        physical = entry & page_table::ADDRESS_MASK;
+    }
+
+    if let Some(physical) = table.translate(address) {
+        println!("{:#x} -> {:#x}", address, physical);
+    }
+}
+
+/// Print the layout of the active page table, pages that are next to each
+/// other with the same flags and contiguous physical memory are printed as
+/// one range. The flags are read, write, execute, user, global and
+/// copy-on-write followed by the cache mode from the PAT index
+pub fn dump_page_table() {
+    if !physmap::enabled() {
+        println!("The direct map is not setup, can't dump the page table");
+        return;
+    }
+
+    let mut memory = physmap::PhysmapMemory;
+    let table = physmap::active_table(&mut memory);
+
+    println!("Page table at {:#x}:", table.p4());
+
+    let mut current: Option<DumpRange> = None;
+
+    table.for_each_mapping(|mapping| {
+        let flags = mapping.flags & DUMP_FLAGS;
+        let cache_mode = CacheMode::from_page_flags(mapping.flags,
+                                                    mapping.size != PAGE_SIZE);
+
+        if let Some(range) = current.as_mut() {
+            if range.end == mapping.virtual_address &&
+                range.flags == flags &&
+                range.cache_mode == cache_mode &&
+                range.physical + (range.end - range.start) ==
+                    mapping.physical_address
+            {
+                range.end += mapping.size;
+                return;
+            }
+
+            range.print();
+        }
+
+        current = Some(DumpRange {
+            start: mapping.virtual_address,
+            end: mapping.virtual_address + mapping.size,
+            physical: mapping.physical_address,
+            flags: flags,
+            cache_mode: cache_mode,
+        });
+    });
+
+    if let Some(range) = current {
+        range.print();
+    }
+}
@@ -0,0 +1,112 @@
+//! The virtual memory regions of an address space, the page fault handler
+//! uses these to know what should be mapped at a faulting address
+
+use spin::Mutex;
+use super::{PAGE_WRITE, PAGE_USER, PAGE_NXE};
+use super::vmalloc;
+
+/// The number of regions we keep for the kernel sections and the other
+/// memory the kernel maps at boot
+const FIXED_REGIONS: usize = 32;
+
+/// The maximum number of regions inside an address space, every vmalloc
+/// area has a region so there is room for all of them next to the fixed
+/// regions
+const MAX_REGIONS: usize = FIXED_REGIONS + vmalloc::MAX_AREAS;
+
+/// The address space of the kernel, this is the only address space until
+/// we have processes
+pub static KERNEL_ADDRESS_SPACE: Mutex<AddressSpace> =
+    Mutex::new(AddressSpace::new());
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RegionKind {
+    /// Memory that gets a zeroed frame the first time it's touched
+    Anonymous,
+
+    /// Memory that is mapped up front, e.g. the kernel image, so a fault
+    /// inside these regions is always a bug
+    Fixed,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Region {
+    pub name: &'static str,
+
+    /// The start of the region (inclusive)
+    pub start: u64,
+    /// The end of the region (exclusive)
+    pub end: u64,
+
+    /// The page flags the pages inside the region should be mapped with
+    pub flags: u64,
+
+    pub kind: RegionKind,
+}
+
+impl Region {
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.start && address < self.end
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.flags & PAGE_WRITE != 0
+    }
+
+    pub fn is_executable(&self) -> bool {
+        self.flags & PAGE_NXE == 0
+    }
+
+    pub fn is_user(&self) -> bool {
+        self.flags & PAGE_USER != 0
+    }
+}
+
+pub struct AddressSpace {
+    regions: [Option<Region>; MAX_REGIONS],
+}
+
+impl AddressSpace {
+    pub const fn new() -> AddressSpace {
+        AddressSpace {
+            regions: [None; MAX_REGIONS],
+        }
+    }
+
+    /// Add `region` to the address space, returns `None` if the region
+    /// overlaps with another region or if we are out of slots
+    pub fn add_region(&mut self, region: Region) -> Option<()> {
+        assert!(region.start < region.end, "Invalid region shape");
+
+        let overlaps = self.regions().any(|x| {
+            region.start < x.end && x.start < region.end
+        });
+
+        if overlaps {
+            return None;
+        }
+
+        let slot = self.regions.iter_mut().find(|x| x.is_none())?;
+        *slot = Some(region);
+
+        Some(())
+    }
+
+    /// Remove the region starting at `start` and return it
+    pub fn remove_region(&mut self, start: u64) -> Option<Region> {
+        let slot = self.regions.iter_mut()
+            .find(|x| x.map(|x| x.start == start).unwrap_or(false))?;
+
+        slot.take()
+    }
+
+    /// Find the region that contains `address`
+    pub fn find_region(&self, address: u64) -> Option<&Region> {
+        self.regions().find(|x| x.contains(address))
+    }
+
+    /// Iterate over all the regions inside the address space
+    pub fn regions(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter().filter_map(|x| x.as_ref())
+    }
+}
@@ -0,0 +1,242 @@
+//! Allocator for the kernel virtual memory inside the vmalloc window, this
+//! is used for large buffers that only need to be virtually contiguous and
+//! for mapping device memory. Every area is followed by an unmapped guard
+//! page so an overflow faults instead of running into the next area
+
+use spin::Mutex;
+use rangeset::{Range, RangeSet};
+use super::{PAGE_SIZE, PAGE_WRITE, PAGE_NXE, CacheMode};
+use super::{PhysicalAddress, PhysicalFrame, VirtualAddress, Page};
+use super::{FRAME_ALLOCATOR, FrameAllocator, ActivePageTable};
+use super::frame_info;
+use super::vma::{KERNEL_ADDRESS_SPACE, Region, RegionKind};
+
+/// The start of the vmalloc window, this is P4 entry 384
+pub const VMALLOC_START: u64 = 0xffff_c000_0000_0000;
+
+/// The end of the vmalloc window (exclusive), the window is 1 TiB
+pub const VMALLOC_END: u64 = 0xffff_c100_0000_0000;
+
+/// The maximum number of areas that can be allocated at the same time
+pub const MAX_AREAS: usize = 64;
+
+static VMALLOC: Mutex<Vmalloc> = Mutex::new(Vmalloc::new());
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum AreaKind {
+    /// Memory backed by frames we allocated, the frames are freed with the
+    /// area
+    Vmalloc,
+
+    /// A mapping of device memory, the frames are not ours to free
+    Ioremap,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Area {
+    /// The start of the area, this is always page aligned
+    start: u64,
+
+    /// The number of mapped pages, the guard page is not included
+    pages: u64,
+
+    kind: AreaKind,
+}
+
+struct Vmalloc {
+    /// The free virtual memory inside the window
+    free: RangeSet,
+
+    areas: [Option<Area>; MAX_AREAS],
+
+    initialized: bool,
+}
+
+impl Vmalloc {
+    const fn new() -> Vmalloc {
+        Vmalloc {
+            free: RangeSet::new(),
+            areas: [None; MAX_AREAS],
+            initialized: false,
+        }
+    }
+
+    /// Reserve virtual memory for `pages` pages and a guard page, returns
+    /// the start of the area
+    fn reserve(&mut self, pages: u64, kind: AreaKind) -> Option<u64> {
+        if !self.initialized {
+            self.free.insert(Range {
+                start: VMALLOC_START,
+                end: VMALLOC_END - 1,
+            });
+            self.initialized = true;
+        }
+
+        let slot = self.areas.iter().position(|x| x.is_none())?;
+        let size = (pages + 1) * PAGE_SIZE;
+        let start = self.free.allocate(size, PAGE_SIZE)? as u64;
+
+        self.areas[slot] = Some(Area {
+            start: start,
+            pages: pages,
+            kind: kind,
+        });
+
+        Some(start)
+    }
+
+    /// Give the virtual memory for the area starting at `start` back and
+    /// return the area
+    fn release(&mut self, start: u64) -> Option<Area> {
+        let slot = self.areas.iter_mut()
+            .find(|x| x.map(|x| x.start == start).unwrap_or(false))?;
+        let area = slot.take()?;
+
+        self.free.insert(Range {
+            start: area.start,
+            end: area.start + (area.pages + 1) * PAGE_SIZE - 1,
+        });
+
+        Some(area)
+    }
+}
+
+/// Add the region for the area so faults inside it can be reported,
+/// returns `None` if the kernel address space is full
+fn add_region(name: &'static str, start: u64, pages: u64, flags: u64)
+    -> Option<()>
+{
+    KERNEL_ADDRESS_SPACE.lock().add_region(Region {
+        name: name,
+        start: start,
+        end: start + pages * PAGE_SIZE,
+        flags: flags,
+        kind: RegionKind::Fixed,
+    })
+}
+
+/// Unmap the first `pages` pages of the area at `start`, the frames are
+/// freed if the area is backed by our own frames
+fn unmap_pages(start: u64, pages: u64, kind: AreaKind) {
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    for index in 0..pages {
+        let page = Page::containing_address(
+            VirtualAddress(start + index * PAGE_SIZE));
+        let frame = page_table.unmap(page);
+
+        if kind == AreaKind::Vmalloc && frame_info::put_ref(frame) == 0 {
+            allocator.deallocate_frame(frame);
+        }
+    }
+}
+
+/// Allocate `size` bytes of virtually contiguous memory, the memory is
+/// backed by frames that don't need to be physically contiguous
+pub fn vmalloc(size: u64) -> Option<VirtualAddress> {
+    vmalloc_cached(size, CacheMode::WriteBack)
+}
+
+/// Allocate `size` bytes like `vmalloc` but map the memory with
+/// `cache_mode`, e.g. `CacheMode::Uncached` for memory a device reads
+pub fn vmalloc_cached(size: u64, cache_mode: CacheMode)
+    -> Option<VirtualAddress>
+{
+    let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    if pages == 0 {
+        return None;
+    }
+
+    let flags = PAGE_WRITE | PAGE_NXE;
+    let start = VMALLOC.lock().reserve(pages, AreaKind::Vmalloc)?;
+
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    for index in 0..pages {
+        let frame = match allocator.allocate_frame() {
+            Some(frame) => frame,
+            None => {
+                // Undo what we have mapped so far
+                drop(allocator);
+                unmap_pages(start, index, AreaKind::Vmalloc);
+                VMALLOC.lock().release(start);
+
+                return None;
+            }
+        };
+
+        let page = Page::containing_address(
+            VirtualAddress(start + index * PAGE_SIZE));
+        page_table.map_to_cached(page, frame, flags, cache_mode,
+                                 &mut *allocator);
+    }
+
+    drop(allocator);
+
+    let flags = flags | cache_mode.page_flags();
+    if add_region("vmalloc", start, pages, flags).is_none() {
+        unmap_pages(start, pages, AreaKind::Vmalloc);
+        VMALLOC.lock().release(start);
+
+        return None;
+    }
+
+    Some(VirtualAddress(start))
+}
+
+/// Free the memory at `address` allocated with `vmalloc` or mapped with
+/// `ioremap`
+pub fn vfree(address: VirtualAddress) {
+    let start = address.0 & !(PAGE_SIZE - 1);
+
+    let area = VMALLOC.lock().release(start)
+        .expect("Trying to free an address that is not allocated");
+
+    KERNEL_ADDRESS_SPACE.lock().remove_region(area.start);
+
+    unmap_pages(area.start, area.pages, area.kind);
+}
+
+/// Map `size` bytes of device memory at `address` with `cache_mode`,
+/// returns the virtual address for `address`. Device registers should use
+/// `CacheMode::Uncached` and framebuffers `CacheMode::WriteCombining`, the
+/// mapping is removed with `vfree`
+pub fn ioremap(address: PhysicalAddress, size: u64, cache_mode: CacheMode)
+    -> Option<VirtualAddress>
+{
+    if size == 0 {
+        return None;
+    }
+
+    let offset = address.0 & (PAGE_SIZE - 1);
+    let first = PhysicalFrame::containing_address(address);
+    let pages = (offset + size + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    let flags = PAGE_WRITE | PAGE_NXE;
+    let start = VMALLOC.lock().reserve(pages, AreaKind::Ioremap)?;
+
+    let mut page_table = unsafe { ActivePageTable::new() };
+    let mut allocator = FRAME_ALLOCATOR.lock();
+
+    for index in 0..pages {
+        let page = Page::containing_address(
+            VirtualAddress(start + index * PAGE_SIZE));
+        page_table.map_to_cached(page, PhysicalFrame(first.0 + index),
+                                 flags, cache_mode, &mut *allocator);
+    }
+
+    drop(allocator);
+
+    let flags = flags | cache_mode.page_flags();
+
+    if add_region("ioremap", start, pages, flags).is_none() {
+        unmap_pages(start, pages, AreaKind::Ioremap);
+        VMALLOC.lock().release(start);
+
+        return None;
+    }
+
+    Some(VirtualAddress(start + offset))
+}
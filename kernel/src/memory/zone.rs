@@ -0,0 +1,221 @@
+//! Physical memory zones, devices that can only do DMA to the low 16 MiB or
+//! to the low 4 GiB need memory from those ranges, so every zone has its own
+//! buddy allocator and normal allocations stay out of the low zones as long
+//! as there is memory left in the higher zones
+
+use rangeset::{Range, RangeSet};
+use super::{PAGE_SIZE, PhysicalAddress, PhysicalFrame, FrameAllocator};
+use super::frame_info;
+use super::buddy::{self, BuddyAllocator, BuddyStats, MAX_ORDER};
+
+/// The end of the ISA DMA zone (exclusive)
+const DMA_END: u64 = 16 * 1024 * 1024;
+
+/// The end of the zone for devices that can only do 32-bit DMA (exclusive)
+const DMA32_END: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The number of zones
+pub const ZONE_COUNT: usize = 3;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Zone {
+    /// The memory below 16 MiB for legacy ISA DMA
+    Dma = 0,
+
+    /// The memory from 16 MiB to 4 GiB for devices with 32-bit DMA
+    Dma32 = 1,
+
+    /// All the memory above 4 GiB
+    Normal = 2,
+}
+
+impl Zone {
+    /// All the zones from the lowest to the highest
+    pub const ALL: [Zone; ZONE_COUNT] = [Zone::Dma, Zone::Dma32, Zone::Normal];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Zone::Dma    => "DMA",
+            Zone::Dma32  => "DMA32",
+            Zone::Normal => "Normal",
+        }
+    }
+
+    /// The physical memory the zone covers as start (inclusive) and end
+    /// (exclusive)
+    pub fn bounds(&self) -> (u64, u64) {
+        match self {
+            Zone::Dma    => (0, DMA_END),
+            Zone::Dma32  => (DMA_END, DMA32_END),
+            Zone::Normal => (DMA32_END, !0 & !(PAGE_SIZE - 1)),
+        }
+    }
+
+    /// Get the zone that contains `address`
+    pub fn containing_address(address: PhysicalAddress) -> Zone {
+        if address.0 < DMA_END {
+            Zone::Dma
+        } else if address.0 < DMA32_END {
+            Zone::Dma32
+        } else {
+            Zone::Normal
+        }
+    }
+}
+
+/// Get the memory above the ISA DMA zone, the boot allocations prefer this
+/// memory so the low 16 MiB is left for the devices that need it
+pub(super) fn boot_preferred_memory() -> RangeSet {
+    let mut memory = RangeSet::new();
+    memory.insert(Range {
+        start: DMA_END,
+        end: !0,
+    });
+
+    memory
+}
+
+pub(super) struct ZoneAllocator {
+    zones: [BuddyAllocator; ZONE_COUNT],
+}
+
+impl ZoneAllocator {
+    pub const fn new() -> ZoneAllocator {
+        ZoneAllocator {
+            zones: [BuddyAllocator::new(), BuddyAllocator::new(),
+                    BuddyAllocator::new()],
+        }
+    }
+
+    /// Setup a buddy allocator for every zone that has memory below
+    /// `memory_end` and give all the memory inside `boot_memory` to the
+    /// zones, this needs to be called after the direct map is setup
+    pub fn init(&mut self, boot_memory: &mut RangeSet, memory_end: u64) {
+        let memory_end = memory_end & !(PAGE_SIZE - 1);
+
+        // Allocate the bitmaps for all the zones before we hand out the
+        // boot memory
+        for &zone in Zone::ALL.iter() {
+            let (start, end) = zone.bounds();
+            if start >= memory_end {
+                continue;
+            }
+
+            let end = core::cmp::min(end, memory_end);
+            self.zones[zone as usize].init(PhysicalFrame(start / PAGE_SIZE),
+                                           PhysicalFrame(end / PAGE_SIZE),
+                                           boot_memory);
+        }
+
+        for range in boot_memory.entries() {
+            let start = (range.start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+            let end = range.end.saturating_add(1) & !(PAGE_SIZE - 1);
+
+            self.add_memory(start, end);
+        }
+
+        // The memory is owned by the zones now
+        *boot_memory = RangeSet::new();
+
+        for &zone in Zone::ALL.iter() {
+            let stats = self.zones[zone as usize].stats();
+            println!("Zone {:<6}: {} free frames ({} MiB)",
+                     zone.name(), stats.free_frames,
+                     stats.free_frames * PAGE_SIZE / 1024 / 1024);
+        }
+    }
+
+    /// Give the physical memory from `start` to `end` (exclusive) to the
+    /// zones, the range is split at the zone boundaries
+    pub fn add_memory(&mut self, start: u64, end: u64) {
+        if start >= end {
+            return;
+        }
+
+        frame_info::set_usable(PhysicalFrame(start / PAGE_SIZE),
+                               PhysicalFrame(end / PAGE_SIZE));
+
+        for &zone in Zone::ALL.iter() {
+            let (zone_start, zone_end) = zone.bounds();
+
+            let start = core::cmp::max(start, zone_start);
+            let end = core::cmp::min(end, zone_end);
+            if start >= end {
+                continue;
+            }
+
+            self.zones[zone as usize]
+                .add_range(PhysicalFrame(start / PAGE_SIZE),
+                           PhysicalFrame(end / PAGE_SIZE));
+        }
+    }
+
+    /// Allocate `count` physically contiguous frames from `zone`, when
+    /// `zone` is out of memory we fall back to the lower zones because the
+    /// memory there satisfies the constraint aswell. The frames are marked
+    /// as allocated by the kernel with one reference
+    pub fn allocate(&mut self, count: u64, zone: Zone)
+        -> Option<PhysicalFrame>
+    {
+        let order = buddy::order_for(count);
+        if count == 0 || order >= MAX_ORDER {
+            return None;
+        }
+
+        let (index, frame) = (0..=zone as usize).rev()
+            .find_map(|x| self.zones[x].allocate(order).map(|f| (x, f)))?;
+
+        // Give back the frames at the end of the block we don't need, the
+        // buddy allocator merges them into blocks again
+        for extra in frame.0 + count..frame.0 + (1 << order) {
+            self.zones[index].free(PhysicalFrame(extra), 0);
+        }
+
+        for frame in frame.0..frame.0 + count {
+            frame_info::set_allocated(PhysicalFrame(frame),
+                                      frame_info::KERNEL_OWNER, 0);
+        }
+
+        Some(frame)
+    }
+
+    /// Free `count` frames starting at `frame` that was allocated with
+    /// `allocate`
+    pub fn free(&mut self, frame: PhysicalFrame, count: u64) {
+        frame_info::set_usable(frame, PhysicalFrame(frame.0 + count));
+
+        for frame in frame.0..frame.0 + count {
+            let frame = PhysicalFrame(frame);
+            let zone = self.zones.iter_mut()
+                .find(|x| x.contains(frame))
+                .expect("Freed frame is outside of all the zones");
+
+            zone.free(frame, 0);
+        }
+    }
+
+    pub fn stats(&self, zone: Zone) -> BuddyStats {
+        self.zones[zone as usize].stats()
+    }
+
+    /// Get the free memory of all the zones, the bool is `false` if the
+    /// memory is too fragmented to fit inside the `RangeSet`
+    pub fn free_memory(&self) -> (RangeSet, bool) {
+        let mut memory = RangeSet::new();
+        let complete = self.zones.iter()
+            .all(|zone| zone.free_memory(&mut memory));
+
+        (memory, complete)
+    }
+}
+
+impl FrameAllocator for ZoneAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysicalFrame> {
+        self.allocate(1, Zone::Normal)
+    }
+
+    fn deallocate_frame(&mut self, frame: PhysicalFrame) -> Option<()> {
+        self.free(frame, 1);
+        Some(())
+    }
+}
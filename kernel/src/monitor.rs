@@ -0,0 +1,309 @@
+//! The kernel monitor, a small shell to look at the state of the kernel when
+//! something goes wrong. The monitor is entered after a panic, with
+//! Alt+SysRq and at boot with "debug" on the command line. It runs with the
+//! interrupts disabled and polls the keyboard and COM1 for the input, so it
+//! works whatever state the interrupts are in
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::arch::x86_64;
+use crate::keyboard::{self, Key};
+use crate::memory::{self, VirtualAddress};
+use crate::backtrace;
+use crate::console;
+use crate::interrupts;
+use crate::serial;
+
+/// The longest line we take
+const LINE_SIZE: usize = 80;
+
+/// The number of bytes `dump` shows without a length, and the most bytes
+/// it shows at once
+const DEFAULT_DUMP_SIZE: u64 = 64;
+const MAX_DUMP_SIZE: u64 = 4096;
+
+/// Set by "debug" on the command line
+static ENTER_AT_BOOT: AtomicBool = AtomicBool::new(false);
+
+/// Why we are inside the monitor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Reason {
+    Boot,
+    SysRq,
+    Panic,
+}
+
+impl Reason {
+    fn name(&self) -> &'static str {
+        match self {
+            Reason::Boot => "boot",
+            Reason::SysRq => "SysRq",
+            Reason::Panic => "panic",
+        }
+    }
+}
+
+const HELP: &str = "\
+Commands, the numbers are hex:
+  dump <address> [length]  Dump the memory at the virtual address
+  translate <address>      Translate the virtual address
+  pte <address>            Print the page table entries of the address
+  pagetable                Print the layout of the page table
+  memory                   Print the memory statistics and the free memory
+  irqs                     Print the number of times the IRQs was raised
+  bt                       Print the backtrace of the monitor
+  reboot                   Reset the machine
+  exit                     Leave the monitor";
+
+/// Enter the monitor when the boot is done, this is for "debug" on the
+/// command line
+pub fn request_at_boot() {
+    ENTER_AT_BOOT.store(true, Ordering::Relaxed);
+}
+
+/// Enter the monitor if it was requested on the command line
+pub fn enter_if_requested() {
+    if ENTER_AT_BOOT.swap(false, Ordering::Relaxed) {
+        enter(Reason::Boot);
+    }
+}
+
+/// Run the monitor until the user leaves it, after a panic the monitor is
+/// never left
+pub fn enter(reason: Reason) {
+    x86_64::without_interrupts(|| {
+        // The code we stopped might hold the locks of the output and of the
+        // keyboard, the first line takes the console locks by force
+        emergency_println!("\n---------- KERNEL MONITOR ({}) ----------",
+                           reason.name());
+        unsafe { keyboard::KEYBOARD.force_unlock(); }
+
+        println!("Type 'help' for the commands");
+
+        let mut line = [0; LINE_SIZE];
+
+        loop {
+            print!("monitor> ");
+
+            let length = read_line(&mut line);
+            let line = core::str::from_utf8(&line[..length]).unwrap_or("");
+
+            if execute(line, reason) {
+                break;
+            }
+        }
+
+        println!("Leaving the monitor");
+    });
+}
+
+/// Wait for the next byte from the keyboard or from COM1
+fn read_byte() -> u8 {
+    loop {
+        if let Some(event) = keyboard::poll() {
+            match event.key {
+                Key::Character(character) => return character,
+                Key::Enter => return b'\n',
+                Key::Backspace => return 0x08,
+                _ => {}
+            }
+        }
+
+        if let Some(byte) = serial::SERIAL.lock().try_read_byte() {
+            return byte;
+        }
+    }
+}
+
+/// Read a line into `line` with echo, returns the length of the line
+fn read_line(line: &mut [u8; LINE_SIZE]) -> usize {
+    let mut length = 0;
+
+    loop {
+        match read_byte() {
+            // A terminal on the serial port sends CR for Enter
+            b'\n' | b'\r' => {
+                println!("");
+                return length;
+            }
+
+            0x08 | 0x7f => {
+                if length > 0 {
+                    length -= 1;
+                    print!("\x08 \x08");
+                }
+            }
+
+            byte @ 0x20..=0x7e if length < LINE_SIZE => {
+                line[length] = byte;
+                length += 1;
+                print!("{}", byte as char);
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Parse a hex number, the "0x" is optional
+fn parse_number(argument: Option<&str>) -> Option<u64> {
+    let argument = argument?;
+    let digits = argument.strip_prefix("0x").unwrap_or(argument);
+
+    u64::from_str_radix(digits, 16).ok()
+}
+
+/// Run the command on `line`, returns `true` if we should leave the
+/// monitor
+fn execute(line: &str, reason: Reason) -> bool {
+    let mut arguments = line.split_whitespace();
+
+    let command = match arguments.next() {
+        Some(command) => command,
+        None => return false,
+    };
+
+    match command {
+        "help" => println!("{}", HELP),
+
+        "dump" => match parse_number(arguments.next()) {
+            Some(address) => {
+                let length = parse_number(arguments.next())
+                    .unwrap_or(DEFAULT_DUMP_SIZE);
+                dump(address, length);
+            }
+            None => println!("Usage: dump <address> [length]"),
+        },
+
+        "translate" => match parse_number(arguments.next()) {
+            Some(address) if !memory::is_canonical(address) =>
+                println!("{:#x} is not canonical", address),
+            Some(address) => {
+                match memory::translate(VirtualAddress(address)) {
+                    Some(physical) =>
+                        println!("{:#x} -> {:#x}", address, physical.0),
+                    None => println!("{:#x} is not mapped", address),
+                }
+            }
+            None => println!("Usage: translate <address>"),
+        },
+
+        "pte" => match parse_number(arguments.next()) {
+            Some(address) if !memory::is_canonical(address) =>
+                println!("{:#x} is not canonical", address),
+            Some(address) => memory::stats::print_page_table_entries(address),
+            None => println!("Usage: pte <address>"),
+        },
+
+        "pagetable" => memory::stats::dump_page_table(),
+
+        "memory" => print_memory(),
+
+        "irqs" => {
+            for irq in 0..interrupts::IRQ_COUNT {
+                let count = interrupts::irq_count(irq);
+                if count != 0 {
+                    println!("IRQ {:>2}: {}", irq, count);
+                }
+            }
+        }
+
+        "bt" => backtrace::print(x86_64::rbp(), console::print),
+
+        "reboot" => x86_64::reboot(),
+
+        "exit" => {
+            if reason == Reason::Panic {
+                println!("The kernel panicked, there is nothing to go back \
+                          to");
+            } else {
+                return true;
+            }
+        }
+
+        _ => println!("Unknown command '{}', try 'help'", command),
+    }
+
+    false
+}
+
+/// Dump `length` bytes from `address` as hex and ASCII, 16 bytes a line
+fn dump(address: u64, length: u64) {
+    let length = length.min(MAX_DUMP_SIZE);
+
+    for offset in (0..length).step_by(16) {
+        let start = address.wrapping_add(offset);
+        let count = (length - offset).min(16);
+        let end = start.wrapping_add(count - 1);
+
+        // Reading inside the hole would be a general protection fault
+        if !memory::is_canonical(start) || !memory::is_canonical(end) {
+            println!("{:#018x}: not canonical", start);
+            return;
+        }
+
+        // The line can only cross into the next page at the end
+        if memory::translate(VirtualAddress(start)).is_none() ||
+            memory::translate(VirtualAddress(end)).is_none()
+        {
+            println!("{:#018x}: not mapped", start);
+            return;
+        }
+
+        let mut bytes = [0; 16];
+        for (index, byte) in bytes.iter_mut().take(count as usize)
+            .enumerate()
+        {
+            *byte = unsafe {
+                core::ptr::read_volatile(start.wrapping_add(index as u64)
+                                         as *const u8)
+            };
+        }
+
+        print!("{:#018x}: ", start);
+
+        for index in 0..16 {
+            if index < count as usize {
+                print!("{:02x} ", bytes[index]);
+            } else {
+                print!("   ");
+            }
+        }
+
+        for &byte in bytes.iter().take(count as usize) {
+            let character = if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            };
+
+            print!("{}", character);
+        }
+
+        println!("");
+    }
+}
+
+/// Print the memory statistics and the free memory of the frame allocator,
+/// the monitor might have interrupted the code holding one of the locks so
+/// nothing here waits for them
+fn print_memory() {
+    memory::stats::try_print_stats();
+
+    let (free, complete) = match memory::free_memory() {
+        Some(free) => free,
+        None => {
+            println!("The frame allocator is locked");
+            return;
+        }
+    };
+
+    println!("Free memory:");
+    for range in free.entries() {
+        println!("  {:#014x} - {:#014x} ({} KiB)", range.start, range.end,
+                 (range.end - range.start + 1) / 1024);
+    }
+
+    if !complete {
+        println!("  (the free memory is too fragmented to show all of it)");
+    }
+}
@@ -1,22 +1,69 @@
 use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, Ordering};
+use crate::console;
+use crate::backtrace;
+use crate::monitor;
+use crate::arch::x86_64;
+
+/// Set when the first panic starts, a panic after that is a panic inside
+/// the panic handler
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    // TODO(patrik): Should we disable interupts?
-    println!("---------- KERNEL PANIC ----------");
-    
+    // Stop everything else first, the panic might be inside an interrupt
+    // handler or with the console locked so the output takes the locks by
+    // force
+    x86_64::disable_interrupts();
+
+    // The registers are read before we do anything else with them
+    let rsp = x86_64::rsp();
+    let rbp = x86_64::rbp();
+    let rflags = x86_64::rflags();
+
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        // The console might be what panicked so we only use the serial port
+        console::serial_emergency_print(
+            format_args!("\n---------- PANIC INSIDE PANIC ----------\n"));
+
+        if let Some(msg) = info.message() {
+            console::serial_emergency_print(
+                format_args!("Message: {}\n", msg));
+        }
+
+        x86_64::halt_forever();
+    }
+
+    emergency_println!("---------- KERNEL PANIC ----------");
+
     if let Some(msg) = info.message() {
-        println!("Message: {}", msg);
+        emergency_println!("Message: {}", msg);
     }
 
     if let Some(loc) = info.location() {
-        println!("Location: {}:{}:{}", 
+        emergency_println!("Location: {}:{}:{}",
                  loc.file(), loc.line(), loc.column());
     }
 
+    emergency_println!("CPU: {}", x86_64::apic_id());
+
+    emergency_println!("RSP: {:#018x} RBP: {:#018x} RFLAGS: {:#x}",
+                       rsp, rbp, rflags);
+    emergency_println!("CR0: {:#018x} CR2: {:#018x}",
+                       x86_64::cr0(), x86_64::cr2());
+    emergency_println!("CR3: {:#018x} CR4: {:#018x}",
+                       x86_64::cr3(), x86_64::cr4());
+
+    // The backtrace starts inside the panic machinery of core, the frames
+    // after that are the code that panicked. A frame of an exception
+    // handler is followed by the error code or the interrupted RIP
+    emergency_println!("Backtrace:");
+    backtrace::print(rbp, console::emergency_print);
+
+    emergency_println!("----------------------------------");
 
-    println!("----------------------------------");
+    // Let the user look around before we stop for good
+    monitor::enter(monitor::Reason::Panic);
 
-    // TODO(patrik): Replace with a halt instruction
-    loop {}
+    x86_64::halt_forever();
 }
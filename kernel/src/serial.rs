@@ -0,0 +1,148 @@
+//! Driver for the 16550 UART, all the console output is mirrored to COM1 so
+//! it can be read from the host with a terminal emulator
+
+use spin::Mutex;
+use crate::arch::x86_64;
+
+/// The I/O port of the first serial port
+const COM1: u16 = 0x3f8;
+
+/// The I/O port of the second serial port, the GDB stub has it so the
+/// protocol doesn't mix with the console output
+pub const COM2: u16 = 0x2f8;
+
+/// The divisor for 38400 baud, the UART runs at 115200 baud / divisor
+const BAUD_DIVISOR: u16 = 3;
+
+// The registers relative to the base port
+const DATA:          u16 = 0;
+const INTERRUPT:     u16 = 1;
+const FIFO_CONTROL:  u16 = 2;
+const LINE_CONTROL:  u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS:   u16 = 5;
+
+/// Line control bit to access the baud rate divisor
+const LINE_DLAB: u8 = 1 << 7;
+
+/// Line control value for 8 data bits, no parity and one stop bit
+const LINE_8N1: u8 = 0b11;
+
+/// Line status bit that is set when we can send the next byte
+const LINE_TRANSMIT_EMPTY: u8 = 1 << 5;
+
+/// Line status bit that is set when a received byte is waiting
+const LINE_DATA_READY: u8 = 1 << 0;
+
+/// Interrupt enable bit for an interrupt when a byte is received
+const INTERRUPT_RECEIVED: u8 = 1 << 0;
+
+/// Modem control bit that connects the interrupt of the UART to the PIC
+const MODEM_OUT2: u8 = 1 << 3;
+
+/// The number of times we check the line status before we drop a byte,
+/// so a missing UART doesn't hang the kernel
+const TRANSMIT_TIMEOUT: u32 = 100000;
+
+pub static SERIAL: Mutex<SerialPort> = Mutex::new(SerialPort::new(COM1));
+
+pub struct SerialPort {
+    port: u16,
+    initialized: bool,
+}
+
+impl SerialPort {
+    pub const fn new(port: u16) -> SerialPort {
+        SerialPort {
+            port: port,
+            initialized: false,
+        }
+    }
+
+    /// Setup the UART for 38400 baud 8N1 with the FIFOs enabled and the
+    /// interrupts disabled
+    pub fn init(&mut self) {
+        x86_64::outb(self.port + INTERRUPT, 0x00);
+
+        x86_64::outb(self.port + LINE_CONTROL, LINE_DLAB);
+        x86_64::outb(self.port + DATA, BAUD_DIVISOR as u8);
+        x86_64::outb(self.port + INTERRUPT, (BAUD_DIVISOR >> 8) as u8);
+        x86_64::outb(self.port + LINE_CONTROL, LINE_8N1);
+
+        // Enable and clear the FIFOs with a 14 byte threshold
+        x86_64::outb(self.port + FIFO_CONTROL, 0xc7);
+
+        // Set DTR and RTS
+        x86_64::outb(self.port + MODEM_CONTROL, 0x03);
+
+        self.initialized = true;
+    }
+
+    /// Send a byte, a newline is sent as CR LF so the terminal on the
+    /// other side starts the next line at the first column
+    pub fn write_byte(&mut self, byte: u8) {
+        if !self.initialized {
+            return;
+        }
+
+        if byte == b'\n' {
+            self.send(b'\r');
+        }
+
+        self.send(byte);
+    }
+
+    /// Send a byte as it is, this is for the protocols where a newline is
+    /// only a byte
+    pub fn send(&mut self, byte: u8) {
+        for _ in 0..TRANSMIT_TIMEOUT {
+            let status = x86_64::inb(self.port + LINE_STATUS);
+
+            if status & LINE_TRANSMIT_EMPTY != 0 {
+                x86_64::outb(self.port + DATA, byte);
+                return;
+            }
+        }
+    }
+
+    /// Get the received byte if there is one
+    pub fn try_read_byte(&mut self) -> Option<u8> {
+        if !self.initialized {
+            return None;
+        }
+
+        let status = x86_64::inb(self.port + LINE_STATUS);
+        if status & LINE_DATA_READY == 0 {
+            return None;
+        }
+
+        Some(x86_64::inb(self.port + DATA))
+    }
+
+    /// Wait for the next received byte
+    pub fn read_byte(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_read_byte() {
+                return byte;
+            }
+        }
+    }
+
+    /// Raise the IRQ of the port when a byte is received
+    pub fn enable_receive_interrupt(&mut self) {
+        x86_64::outb(self.port + INTERRUPT, INTERRUPT_RECEIVED);
+
+        let modem_control = x86_64::inb(self.port + MODEM_CONTROL);
+        x86_64::outb(self.port + MODEM_CONTROL, modem_control | MODEM_OUT2);
+    }
+}
+
+impl core::fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for byte in s.bytes() {
+            self.write_byte(byte);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,105 @@
+//! The symbol table of the kernel, the build driver extracts the functions
+//! from kernel.bin with nm and GRUB loads the table as the "symbols"
+//! module. The table is text with one function per line as
+//! "start size name", the numbers are hex and the lines are sorted by the
+//! start address
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use multiboot2::BootInformation;
+use crate::memory;
+
+/// The name of the multiboot module with the symbol table
+const SYMBOLS_MODULE: &str = "symbols";
+
+// Where the table is mapped, these are atomics instead of a lock so the
+// panic handler can look up the symbols whatever state the kernel is in
+static TABLE_ADDRESS: AtomicU64 = AtomicU64::new(0);
+static TABLE_SIZE: AtomicU64 = AtomicU64::new(0);
+
+/// The function an address is inside of
+#[derive(Copy, Clone, Debug)]
+pub struct Symbol {
+    pub name: &'static str,
+
+    /// The distance from the start of the function
+    pub offset: u64,
+}
+
+/// Map the symbol table if GRUB loaded it, without the table the
+/// backtraces only have the addresses
+pub fn init(boot_info: &BootInformation) {
+    // The table is never unmapped so the data lives forever
+    let (address, data) = match memory::map_module(boot_info,
+                                                   SYMBOLS_MODULE)
+    {
+        Some(module) => module,
+        None => {
+            println!("No symbol table, the backtraces has no names");
+            return;
+        }
+    };
+
+    TABLE_SIZE.store(data.len() as u64, Ordering::Release);
+    TABLE_ADDRESS.store(address.0, Ordering::Release);
+
+    println!("Symbol table: {} KiB", data.len() / 1024);
+}
+
+fn table() -> Option<&'static [u8]> {
+    let address = TABLE_ADDRESS.load(Ordering::Acquire);
+    if address == 0 {
+        return None;
+    }
+
+    let size = TABLE_SIZE.load(Ordering::Acquire);
+
+    unsafe {
+        Some(core::slice::from_raw_parts(address as *const u8,
+                                         size as usize))
+    }
+}
+
+fn parse_hex(bytes: &[u8]) -> Option<u64> {
+    u64::from_str_radix(core::str::from_utf8(bytes).ok()?, 16).ok()
+}
+
+/// Parse a line of the table into the start, the size and the name
+fn parse_line(line: &'static [u8]) -> Option<(u64, u64, &'static str)> {
+    let mut fields = line.splitn(3, |&byte| byte == b' ');
+
+    let start = parse_hex(fields.next()?)?;
+    let size = parse_hex(fields.next()?)?;
+    let name = core::str::from_utf8(fields.next()?).ok()?;
+
+    Some((start, size, name))
+}
+
+/// Find the function `address` is inside of
+pub fn lookup(address: u64) -> Option<Symbol> {
+    // The table has the addresses the kernel is linked at
+    let address = address.wrapping_sub(memory::kernel_slide());
+
+    let mut symbol = None;
+
+    for line in table()?.split(|&byte| byte == b'\n') {
+        let (start, size, name) = match parse_line(line) {
+            Some(entry) => entry,
+            None => continue,
+        };
+
+        if start > address {
+            break;
+        }
+
+        // The symbols from the assembly code has no size, we guess that
+        // they go on until the next symbol
+        if size == 0 || address - start < size {
+            symbol = Some(Symbol {
+                name: name,
+                offset: address - start,
+            });
+        }
+    }
+
+    symbol
+}
@@ -1,17 +1,26 @@
-use spin::Mutex;
-
-const BUFFER_WIDTH: usize = 80;
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_ADDRESS: usize = 0xb8000;
-
-pub static WRITER: Mutex<VGAWriter> = Mutex::new(VGAWriter{
-    x: 0,
-    y: 0,
-    address: BUFFER_ADDRESS,
-    foreground_color: Color::White,
-    background_color: Color::Black,
-    clear_color: Color::Black
-});
+use crate::arch::x86_64;
+
+pub const BUFFER_WIDTH: usize = 80;
+pub const BUFFER_HEIGHT: usize = 25;
+// The VGA buffer is at 0xb8000 but the kernel only maps it 
+// inside the higher half, which moves with the KASLR slide
+const BUFFER_PHYSICAL: usize = 0xb8000;
+const BUFFER_ADDRESS: usize = 
+    crate::memory::KERNEL_OFFSET as usize + BUFFER_PHYSICAL;
+
+// The CRTC index and data ports, the index selects the register we access
+// through the data port
+const CRTC_INDEX: u16 = 0x3d4;
+const CRTC_DATA: u16 = 0x3d5;
+
+// The CRTC registers for the cursor
+const CRTC_CURSOR_START: u8 = 0x0a;
+const CRTC_CURSOR_END: u8 = 0x0b;
+const CRTC_CURSOR_HIGH: u8 = 0x0e;
+const CRTC_CURSOR_LOW: u8 = 0x0f;
+
+// The bit inside the cursor start register that hides the cursor
+const CURSOR_DISABLE: u8 = 1 << 5;
 
 #[allow(dead_code)]
 #[repr(u8)]
@@ -35,48 +44,37 @@ pub enum Color {
     White      = 15,
 }
 
-fn encode_color(foreground: Color, background: Color) -> u8 {
-    (background as u8) << 4 | (foreground as u8)
-}
-
-pub struct VGAWriter {
-    x: u32,
-    y: u32,
+// The text mode display, the console writes the cells with the character
+// and the attribute straight into the VGA buffer
+pub struct VgaText {
     address: usize,
-
-    foreground_color: Color,
-    background_color: Color,
-    clear_color: Color
 }
 
-impl VGAWriter {
-    
-    // Clear the vga buffer
-    // TODO(patrik): Support to pick the background color of the clear
-    pub fn clear(&mut self, clear_color: Color) {
-        for offset in 0..(BUFFER_WIDTH * BUFFER_HEIGHT) {
-            let address = self.address + offset * 2; 
-
-            let color = encode_color(Color::White, clear_color);
-            let character = b' ';
-            let entry = (color as u16) << 8 | (character as u16);
-
-            unsafe {
-                core::ptr::write_volatile(address as *mut u16, entry);
-            }
+impl VgaText {
+    pub const fn new() -> VgaText {
+        VgaText {
+            address: BUFFER_ADDRESS,
         }
+    }
 
-        self.x = 0;
-        self.y = 0;
-        self.clear_color = clear_color;
+    // Move the buffer to the higher half alias of the slid kernel, this
+    // needs to be done before we print anything
+    pub fn update_address(&mut self) {
+        self.address = 
+            crate::memory::kernel_offset() as usize + BUFFER_PHYSICAL;
     }
 
-    pub fn set_color(&mut self, foreground: Color, background: Color) {
-        self.foreground_color = foreground;
-        self.background_color = background;
+    // Write the entry to the VGA buffer
+    pub fn write_entry(&mut self, column: usize, row: usize, entry: u16) {
+        let address = self.address + (column + row * BUFFER_WIDTH) * 2;
+
+        unsafe {
+            core::ptr::write_volatile(address as *mut u16, entry);
+        }
     }
 
-    fn scroll_up(&mut self) {
+    // Move every line one line up, the last line is left as it is
+    pub fn scroll_up(&mut self) {
         for y in 0..BUFFER_HEIGHT - 1 {
             let current_row = y;
             let next_row = y + 1;
@@ -93,102 +91,40 @@ impl VGAWriter {
                                                BUFFER_WIDTH);
             }
         }
-
-        let y = BUFFER_HEIGHT - 1;
-        // TODO(patrik): Should replace with a clear row function and support
-        // other colors
-        for x in 0..BUFFER_WIDTH {
-            // Calculate the offset
-            let offset = (x + (y * BUFFER_WIDTH)) * 2;
-            // Add the offset to the base address for the buffer
-            let address = self.address + offset; 
-
-            // Construct the entry for the buffer
-            let color = encode_color(Color::White, self.clear_color);
-            let character = b' ';
-            let entry = (color as u16) << 8 | character as u16;
-
-            unsafe {
-                // Write the entry to the buffer at the address calculated
-                core::ptr::write_volatile(address as *mut u16, entry);
-            }
-        }
     }
 
-    // Function to print a single character to the screen 
-    // with the x and y cordinates
-    fn write_byte(&mut self, character: u8) {
-        match character {
-            b'\n' => {
-                self.y += 1;
-                self.x = 0;
-
-                // Check if the y is over the height then we should scroll the
-                // buffer
-                if self.y as usize >= BUFFER_HEIGHT {
-                    // Scroll the buffer up
-                    self.scroll_up();
-                    // Reset the y 
-                    self.y = BUFFER_HEIGHT as u32 - 1;
-                }
-            }
+    // Change the scanlines the cursor covers, the character cells are 16
+    // scanlines high
+    pub fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        x86_64::outb(CRTC_INDEX, CRTC_CURSOR_START);
+        let value = x86_64::inb(CRTC_DATA) & 0xc0;
+        x86_64::outb(CRTC_DATA, value | (start & 0x1f));
 
-            _ => {
-                // Cast the cordinates to a usize
-                let x = self.x as usize;
-                let y = self.y as usize;
-
-                // Calculate the offset inside the VGA buffer 
-                let offset = (x + (y * BUFFER_WIDTH)) * 2;
-                // Add the offset to the address of the buffer
-                let address = self.address + offset;
-
-                // The color of the character
-                // TODO(patrik): Let the user to pick the color
-                let color = 
-                    encode_color(self.foreground_color, self.background_color);
-                // The entry we set in the buffer
-                let entry = (color as u16) << 8 | (character as u16);
-
-                unsafe {
-                    // Write the entry to the address we calculated
-                    core::ptr::write_volatile(address as *mut u16, entry);
-                }
-
-                // Increment the x cordinate
-                self.x += 1;
-            }
-        }
+        x86_64::outb(CRTC_INDEX, CRTC_CURSOR_END);
+        let value = x86_64::inb(CRTC_DATA) & 0xe0;
+        x86_64::outb(CRTC_DATA, value | (end & 0x1f));
     }
-}
 
-// Implement the fmt Write so we can use the write! macro and 
-// so we can implement the print macro
-impl core::fmt::Write for VGAWriter {
-    fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        // Loop through all the bytes in the string and write 
-        // them to the buffer
-        for byte in s.bytes() {
-            self.write_byte(byte)
-        }
+    // Move the hardware cursor to the cell, `None` hides the cursor
+    pub fn set_cursor(&mut self, cursor: Option<(usize, usize)>) {
+        x86_64::outb(CRTC_INDEX, CRTC_CURSOR_START);
+        let start = x86_64::inb(CRTC_DATA);
 
-        Ok(())
-    }
-}
+        let (column, row) = match cursor {
+            Some(cursor) => cursor,
+            None => {
+                x86_64::outb(CRTC_DATA, start | CURSOR_DISABLE);
+                return;
+            }
+        };
 
-// Print the format arguments 
-pub fn print(args: core::fmt::Arguments) { 
-    use core::fmt::Write;
-    WRITER.lock().write_fmt(args).unwrap();
-}
+        x86_64::outb(CRTC_DATA, start & !CURSOR_DISABLE);
 
-macro_rules! print {
-    ($($arg:tt)*) => ({
-        $crate::vga_buffer::print(format_args!($($arg)*));
-    });
-}
+        let position = (column + row * BUFFER_WIDTH) as u16;
 
-macro_rules! println {
-    ($fmt:expr) => (print!(concat!($fmt, "\n")));
-    ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
+        x86_64::outb(CRTC_INDEX, CRTC_CURSOR_HIGH);
+        x86_64::outb(CRTC_DATA, (position >> 8) as u8);
+        x86_64::outb(CRTC_INDEX, CRTC_CURSOR_LOW);
+        x86_64::outb(CRTC_DATA, position as u8);
+    }
 }
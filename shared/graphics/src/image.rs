@@ -0,0 +1,349 @@
+//! Decoders for the BMP and TGA images, this is enough to show a boot logo.
+//! The caller asks for the size first and then gives us a buffer to decode
+//! the pixels into so the decoders never allocate
+
+use core::convert::TryInto;
+use crate::Color;
+
+/// The size of the BMP file header and the smallest info header
+const BMP_FILE_HEADER_SIZE: usize = 14;
+const BMP_INFO_HEADER_SIZE: usize = 40;
+
+/// The info header size of the versions with the alpha mask inside it
+const BMP_V3_HEADER_SIZE: usize = 56;
+
+// The BMP compression types we support
+const BMP_RGB: u32 = 0;
+const BMP_BITFIELDS: u32 = 3;
+const BMP_ALPHA_BITFIELDS: u32 = 6;
+
+/// The size of the TGA header
+const TGA_HEADER_SIZE: usize = 18;
+
+// The TGA image types we support
+const TGA_TRUE_COLOR: u8 = 2;
+const TGA_TRUE_COLOR_RLE: u8 = 10;
+
+/// The TGA descriptor bit for images stored from the top
+const TGA_TOP_TO_BOTTOM: u8 = 1 << 5;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Bmp,
+    Tga,
+}
+
+/// Get the format of the image, TGA has no magic so we check if the header
+/// looks like something we can decode
+pub fn format(data: &[u8]) -> Option<Format> {
+    if data.starts_with(b"BM") {
+        return Some(Format::Bmp);
+    }
+
+    if TgaHeader::parse(data).is_some() {
+        return Some(Format::Tga);
+    }
+
+    None
+}
+
+/// Get the width and the height of the image
+pub fn size(data: &[u8]) -> Option<(usize, usize)> {
+    match format(data)? {
+        Format::Bmp => {
+            let header = BmpHeader::parse(data)?;
+            Some((header.width, header.height))
+        }
+
+        Format::Tga => {
+            let header = TgaHeader::parse(data)?;
+            Some((header.width, header.height))
+        }
+    }
+}
+
+/// Decode the image into `pixels` row after row from the top, returns the
+/// width and the height or `None` if the image is broken, not supported or
+/// if `pixels` is too small
+pub fn decode(data: &[u8], pixels: &mut [Color]) -> Option<(usize, usize)> {
+    match format(data)? {
+        Format::Bmp => decode_bmp(data, pixels),
+        Format::Tga => decode_tga(data, pixels),
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// A color channel inside a BMP pixel given as a bit mask
+#[derive(Copy, Clone, Debug)]
+struct Channel {
+    mask: u32,
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn new(mask: u32) -> Channel {
+        let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+
+        Channel {
+            mask,
+            shift,
+            bits: (mask >> shift).count_ones(),
+        }
+    }
+
+    /// Get the channel from the pixel scaled to 8 bits, `default` is used
+    /// if the channel is missing
+    fn get(&self, pixel: u32, default: u8) -> u8 {
+        if self.bits == 0 {
+            return default;
+        }
+
+        let value = (pixel & self.mask) >> self.shift;
+        let max = (1u64 << self.bits) - 1;
+
+        (value as u64 * 255 / max) as u8
+    }
+}
+
+struct BmpHeader {
+    width: usize,
+    height: usize,
+
+    /// The rows are stored from the top instead of from the bottom
+    top_down: bool,
+
+    bits_per_pixel: usize,
+    data_offset: usize,
+
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+    alpha: Channel,
+}
+
+impl BmpHeader {
+    fn parse(data: &[u8]) -> Option<BmpHeader> {
+        if !data.starts_with(b"BM") {
+            return None;
+        }
+
+        let data_offset = read_u32(data, 10)? as usize;
+        let header_size = read_u32(data, 14)? as usize;
+
+        if header_size < BMP_INFO_HEADER_SIZE {
+            return None;
+        }
+
+        let width = read_u32(data, 18)? as i32;
+        let height = read_u32(data, 22)? as i32;
+        let planes = read_u16(data, 26)?;
+        let bits_per_pixel = read_u16(data, 28)? as usize;
+        let compression = read_u32(data, 30)?;
+
+        if width <= 0 || height == 0 || planes != 1 {
+            return None;
+        }
+
+        // The default masks of the uncompressed images, the 32 bit images
+        // don't use the highest byte
+        let mut masks = (0x00ff0000, 0x0000ff00, 0x000000ff, 0);
+
+        // TODO(patrik): Support the images with a palette
+        match (bits_per_pixel, compression) {
+            (24, BMP_RGB) | (32, BMP_RGB) => {}
+
+            (32, BMP_BITFIELDS) | (32, BMP_ALPHA_BITFIELDS) => {
+                // The masks are right after the info header, the later
+                // versions of the header has them inside with the alpha
+                let masks_offset = BMP_FILE_HEADER_SIZE + BMP_INFO_HEADER_SIZE;
+                masks.0 = read_u32(data, masks_offset)?;
+                masks.1 = read_u32(data, masks_offset + 4)?;
+                masks.2 = read_u32(data, masks_offset + 8)?;
+
+                if header_size >= BMP_V3_HEADER_SIZE ||
+                    compression == BMP_ALPHA_BITFIELDS
+                {
+                    masks.3 = read_u32(data, masks_offset + 12)?;
+                }
+            }
+
+            _ => return None,
+        }
+
+        Some(BmpHeader {
+            width: width as usize,
+            height: height.unsigned_abs() as usize,
+            top_down: height < 0,
+            bits_per_pixel,
+            data_offset,
+            red: Channel::new(masks.0),
+            green: Channel::new(masks.1),
+            blue: Channel::new(masks.2),
+            alpha: Channel::new(masks.3),
+        })
+    }
+}
+
+fn decode_bmp(data: &[u8], pixels: &mut [Color]) -> Option<(usize, usize)> {
+    let header = BmpHeader::parse(data)?;
+    let (width, height) = (header.width, header.height);
+
+    if pixels.len() < width.checked_mul(height)? {
+        return None;
+    }
+
+    // Every row is padded to 4 bytes
+    let bytes_per_pixel = header.bits_per_pixel / 8;
+    let stride = (width.checked_mul(bytes_per_pixel)? + 3) & !3;
+
+    for row in 0..height {
+        // The rows are stored from the bottom unless the height is negative
+        let y = if header.top_down { row } else { height - 1 - row };
+
+        let start = header.data_offset.checked_add(row.checked_mul(stride)?)?;
+        let line = data.get(start..start + width * bytes_per_pixel)?;
+
+        for (x, pixel) in line.chunks_exact(bytes_per_pixel).enumerate() {
+            let value = if bytes_per_pixel == 4 {
+                u32::from_le_bytes(pixel.try_into().ok()?)
+            } else {
+                u32::from_le_bytes([pixel[0], pixel[1], pixel[2], 0])
+            };
+
+            pixels[x + y * width] = Color::rgba(
+                header.red.get(value, 0),
+                header.green.get(value, 0),
+                header.blue.get(value, 0),
+                header.alpha.get(value, 255));
+        }
+    }
+
+    Some((width, height))
+}
+
+struct TgaHeader {
+    width: usize,
+    height: usize,
+
+    rle: bool,
+    top_down: bool,
+    bytes_per_pixel: usize,
+
+    /// The image has alpha inside the fourth byte of the pixels
+    alpha: bool,
+
+    data_offset: usize,
+}
+
+impl TgaHeader {
+    fn parse(data: &[u8]) -> Option<TgaHeader> {
+        let header = data.get(..TGA_HEADER_SIZE)?;
+
+        let id_length = header[0] as usize;
+        let color_map_type = header[1];
+        let image_type = header[2];
+        let width = read_u16(header, 12)? as usize;
+        let height = read_u16(header, 14)? as usize;
+        let bits_per_pixel = header[16];
+        let descriptor = header[17];
+
+        // TODO(patrik): Support the color mapped and the grayscale images
+        if color_map_type != 0 {
+            return None;
+        }
+
+        let rle = match image_type {
+            TGA_TRUE_COLOR => false,
+            TGA_TRUE_COLOR_RLE => true,
+            _ => return None,
+        };
+
+        if bits_per_pixel != 24 && bits_per_pixel != 32 {
+            return None;
+        }
+
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        Some(TgaHeader {
+            width,
+            height,
+            rle,
+            top_down: descriptor & TGA_TOP_TO_BOTTOM != 0,
+            bytes_per_pixel: bits_per_pixel as usize / 8,
+            alpha: bits_per_pixel == 32 && descriptor & 0xf != 0,
+            data_offset: TGA_HEADER_SIZE + id_length,
+        })
+    }
+
+    /// Get the color of the pixel, the channels are stored as BGR(A)
+    fn color(&self, pixel: &[u8]) -> Color {
+        let alpha = if self.alpha { pixel[3] } else { 255 };
+        Color::rgba(pixel[2], pixel[1], pixel[0], alpha)
+    }
+}
+
+fn decode_tga(data: &[u8], pixels: &mut [Color]) -> Option<(usize, usize)> {
+    let header = TgaHeader::parse(data)?;
+    let (width, height) = (header.width, header.height);
+    let count = width.checked_mul(height)?;
+
+    if pixels.len() < count {
+        return None;
+    }
+
+    let bytes_per_pixel = header.bytes_per_pixel;
+    let mut offset = header.data_offset;
+    let mut index = 0;
+
+    // Store the pixel at `index` in the order of the file, the rows are
+    // stored from the bottom unless the descriptor says otherwise
+    let mut store = |index: usize, color: Color| {
+        let (x, row) = (index % width, index / width);
+        let y = if header.top_down { row } else { height - 1 - row };
+        pixels[x + y * width] = color;
+    };
+
+    while index < count {
+        // A run length packet has one pixel for the whole run and a raw
+        // packet has all the pixels
+        let (run, length) = if header.rle {
+            let packet = *data.get(offset)?;
+            offset += 1;
+            (packet & 0x80 != 0, (packet & 0x7f) as usize + 1)
+        } else {
+            (false, count)
+        };
+
+        let length = length.min(count - index);
+
+        if run {
+            let pixel = data.get(offset..offset + bytes_per_pixel)?;
+            let color = header.color(pixel);
+            offset += bytes_per_pixel;
+
+            for _ in 0..length {
+                store(index, color);
+                index += 1;
+            }
+        } else {
+            for _ in 0..length {
+                let pixel = data.get(offset..offset + bytes_per_pixel)?;
+                store(index, header.color(pixel));
+                offset += bytes_per_pixel;
+                index += 1;
+            }
+        }
+    }
+
+    Some((width, height))
+}
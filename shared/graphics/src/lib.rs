@@ -0,0 +1,505 @@
+//! A small 2D graphics library, everything is drawn into a `Canvas` in
+//! memory and the parts that changed are flushed to a `Target` like the
+//! framebuffer. The library never allocates, the caller gives it the pixel
+//! buffers, so the same code runs inside the kernel and on the host
+
+#![no_std]
+
+pub mod image;
+
+/// The maximum number of dirty rectangles we track, when we have more the
+/// rectangles are merged into larger ones
+const MAX_DIRTY_RECTS: usize = 16;
+
+/// A 32 bit color with the alpha in the highest byte (0xAARRGGBB)
+#[repr(transparent)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Color(pub u32);
+
+impl Color {
+    pub const TRANSPARENT: Color = Color(0);
+    pub const BLACK: Color = Color::rgb(0, 0, 0);
+    pub const WHITE: Color = Color::rgb(255, 255, 255);
+
+    pub const fn rgb(red: u8, green: u8, blue: u8) -> Color {
+        Color::rgba(red, green, blue, 255)
+    }
+
+    pub const fn rgba(red: u8, green: u8, blue: u8, alpha: u8) -> Color {
+        Color((alpha as u32) << 24 | (red as u32) << 16 |
+              (green as u32) << 8 | blue as u32)
+    }
+
+    pub fn red(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub fn green(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub fn blue(self) -> u8 {
+        self.0 as u8
+    }
+
+    pub fn alpha(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    /// Draw the color on top of `background` with the alpha of the color,
+    /// the colors are not premultiplied so the background should be opaque
+    /// for the color channels to be exact
+    pub fn over(self, background: Color) -> Color {
+        let alpha = self.alpha() as u32;
+
+        match alpha {
+            255 => self,
+            0 => background,
+
+            _ => {
+                let inverse = 255 - alpha;
+                let mix = |source: u8, destination: u8| {
+                    ((source as u32 * alpha + destination as u32 * inverse +
+                      127) / 255) as u8
+                };
+
+                let result_alpha =
+                    alpha + (background.alpha() as u32 * inverse + 127) / 255;
+
+                Color::rgba(mix(self.red(), background.red()),
+                            mix(self.green(), background.green()),
+                            mix(self.blue(), background.blue()),
+                            result_alpha as u8)
+            }
+        }
+    }
+}
+
+/// A rectangle in pixels, the right and bottom edges are exclusive
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub const fn new(x: i32, y: i32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x.saturating_add(self.width as i32)
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y.saturating_add(self.height as i32)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// Get the part both rectangles cover, `None` if they don't overlap
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let left = self.x.max(other.x);
+        let top = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        if right <= left || bottom <= top {
+            return None;
+        }
+
+        Some(Rect::new(left, top, (right - left) as u32,
+                       (bottom - top) as u32))
+    }
+
+    /// Get the smallest rectangle that covers both rectangles
+    pub fn union(&self, other: &Rect) -> Rect {
+        if self.is_empty() {
+            return *other;
+        }
+
+        if other.is_empty() {
+            return *self;
+        }
+
+        let left = self.x.min(other.x);
+        let top = self.y.min(other.y);
+        let right = self.right().max(other.right());
+        let bottom = self.bottom().max(other.bottom());
+
+        Rect::new(left, top, (right - left) as u32, (bottom - top) as u32)
+    }
+}
+
+/// An image we can draw onto a canvas, the pixels are stored row after row
+#[derive(Copy, Clone, Debug)]
+pub struct Bitmap<'a> {
+    pixels: &'a [Color],
+    width: usize,
+    height: usize,
+}
+
+impl<'a> Bitmap<'a> {
+    /// Create the bitmap, returns `None` if the pixels are too few for the
+    /// size
+    pub fn new(pixels: &'a [Color], width: usize, height: usize)
+        -> Option<Bitmap<'a>>
+    {
+        if pixels.len() < width.checked_mul(height)? {
+            return None;
+        }
+
+        Some(Bitmap {
+            pixels,
+            width,
+            height,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+
+        Some(self.pixels[x + y * self.width])
+    }
+}
+
+/// Where the canvas flushes the pixels to, e.g. the framebuffer which
+/// converts the colors to the pixel format of the screen
+pub trait Target {
+    /// Write a line of `pixels` starting at `x` and `y`
+    fn write_span(&mut self, x: usize, y: usize, pixels: &[Color]);
+}
+
+/// The parts of the canvas that changed since the last flush
+struct DirtyRects {
+    rects: [Rect; MAX_DIRTY_RECTS],
+    count: usize,
+}
+
+impl DirtyRects {
+    const fn new() -> DirtyRects {
+        DirtyRects {
+            rects: [Rect::new(0, 0, 0, 0); MAX_DIRTY_RECTS],
+            count: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[Rect] {
+        &self.rects[..self.count]
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.rects[index] = self.rects[self.count - 1];
+        self.count -= 1;
+    }
+
+    /// Add the rectangle, the rectangles never overlap so a pixel is only
+    /// flushed once
+    fn add(&mut self, mut rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+
+        // Merge the rectangle with all the rectangles it overlaps, the
+        // merged rectangle can overlap more so we check again
+        while let Some(index) = self.as_slice().iter()
+            .position(|x| x.intersection(&rect).is_some())
+        {
+            rect = rect.union(&self.rects[index]);
+            self.remove(index);
+        }
+
+        if self.count < MAX_DIRTY_RECTS {
+            self.rects[self.count] = rect;
+            self.count += 1;
+            return;
+        }
+
+        // We are full so merge with the rectangle that grows the least
+        let index = (0..self.count)
+            .min_by_key(|&i| {
+                rect.union(&self.rects[i]).area() - self.rects[i].area()
+            })
+            .unwrap();
+
+        rect = rect.union(&self.rects[index]);
+        self.remove(index);
+        self.add(rect);
+    }
+
+    fn clear(&mut self) {
+        self.count = 0;
+    }
+}
+
+/// A buffer in memory we draw on, this is the back buffer for double
+/// buffering. Everything is clipped to the clip rectangle and every change
+/// is tracked so `flush` only writes the changed pixels to the target
+pub struct Canvas<'a> {
+    pixels: &'a mut [Color],
+    width: usize,
+    height: usize,
+
+    clip: Rect,
+    dirty: DirtyRects,
+}
+
+impl<'a> Canvas<'a> {
+    /// Create a canvas over `pixels`, returns `None` if the pixels are too
+    /// few for the size
+    pub fn new(pixels: &'a mut [Color], width: usize, height: usize)
+        -> Option<Canvas<'a>>
+    {
+        if pixels.len() < width.checked_mul(height)? {
+            return None;
+        }
+
+        if width > i32::MAX as usize || height > i32::MAX as usize {
+            return None;
+        }
+
+        Some(Canvas {
+            pixels,
+            width,
+            height,
+            clip: Rect::new(0, 0, width as u32, height as u32),
+            dirty: DirtyRects::new(),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The rectangle that covers the whole canvas
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width as u32, self.height as u32)
+    }
+
+    pub fn clip(&self) -> Rect {
+        self.clip
+    }
+
+    /// Only draw inside `clip` from now on, the clip is limited to the
+    /// canvas
+    pub fn set_clip(&mut self, clip: Rect) {
+        self.clip = clip.intersection(&self.bounds())
+            .unwrap_or(Rect::new(0, 0, 0, 0));
+    }
+
+    /// Draw on the whole canvas again
+    pub fn reset_clip(&mut self) {
+        self.clip = self.bounds();
+    }
+
+    /// The rectangles that changed since the last flush
+    pub fn dirty_rects(&self) -> &[Rect] {
+        self.dirty.as_slice()
+    }
+
+    pub fn pixel(&self, x: i32, y: i32) -> Option<Color> {
+        if !self.bounds().contains(x, y) {
+            return None;
+        }
+
+        Some(self.pixels[x as usize + y as usize * self.width])
+    }
+
+    /// Blend the color into the pixel if it's inside the clip
+    fn plot(&mut self, x: i32, y: i32, color: Color) {
+        if !self.clip.contains(x, y) {
+            return;
+        }
+
+        let pixel = &mut self.pixels[x as usize + y as usize * self.width];
+        *pixel = color.over(*pixel);
+    }
+
+    /// Mark the part of `rect` inside the clip as changed
+    fn mark(&mut self, rect: Rect) {
+        if let Some(rect) = rect.intersection(&self.clip) {
+            self.dirty.add(rect);
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        self.plot(x, y, color);
+        self.mark(Rect::new(x, y, 1, 1));
+    }
+
+    /// Fill the canvas inside the clip with `color`, the pixels are
+    /// replaced and not blended
+    pub fn clear(&mut self, color: Color) {
+        let clip = self.clip;
+
+        for y in clip.y..clip.bottom() {
+            let start = clip.x as usize + y as usize * self.width;
+            let end = start + clip.width as usize;
+
+            self.pixels[start..end].iter_mut().for_each(|x| *x = color);
+        }
+
+        self.mark(clip);
+    }
+
+    /// Draw a line from the start to the end with Bresenham's algorithm,
+    /// both ends are drawn
+    pub fn draw_line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32,
+                     color: Color)
+    {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            self.plot(x, y, color);
+
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let error2 = error * 2;
+
+            if error2 >= dy {
+                error += dy;
+                x += step_x;
+            }
+
+            if error2 <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+
+        let bounds = Rect::new(x0.min(x1), y0.min(y1), dx as u32 + 1,
+                               (-dy) as u32 + 1);
+        self.mark(bounds);
+    }
+
+    /// Draw the outline of the rectangle, the outline is inside the
+    /// rectangle
+    pub fn draw_rect(&mut self, rect: Rect, color: Color) {
+        if rect.is_empty() {
+            return;
+        }
+
+        // The sides are drawn as filled rectangles so the corners are only
+        // blended once
+        let (x, y) = (rect.x, rect.y);
+        self.fill_rect(Rect::new(x, y, rect.width, 1), color);
+
+        if rect.height > 1 {
+            let bottom = rect.bottom() - 1;
+            self.fill_rect(Rect::new(x, bottom, rect.width, 1), color);
+        }
+
+        if rect.height > 2 {
+            let height = rect.height - 2;
+            self.fill_rect(Rect::new(x, y + 1, 1, height), color);
+
+            if rect.width > 1 {
+                let right = rect.right() - 1;
+                self.fill_rect(Rect::new(right, y + 1, 1, height), color);
+            }
+        }
+    }
+
+    /// Fill the rectangle with `color`, the color is blended with the
+    /// pixels under it
+    pub fn fill_rect(&mut self, rect: Rect, color: Color) {
+        let area = match rect.intersection(&self.clip) {
+            Some(area) => area,
+            None => return,
+        };
+
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                self.plot(x, y, color);
+            }
+        }
+
+        self.mark(area);
+    }
+
+    /// Draw the bitmap with the top left corner at `x` and `y`, the pixels
+    /// are blended with the alpha of the bitmap
+    pub fn blit(&mut self, bitmap: &Bitmap, x: i32, y: i32) {
+        let rect = Rect::new(x, y, bitmap.width as u32,
+                             bitmap.height as u32);
+
+        let area = match rect.intersection(&self.clip) {
+            Some(area) => area,
+            None => return,
+        };
+
+        for target_y in area.y..area.bottom() {
+            let source_y = (target_y - y) as usize;
+
+            for target_x in area.x..area.right() {
+                let source_x = (target_x - x) as usize;
+                let color = bitmap.pixels[source_x + source_y * bitmap.width];
+                self.plot(target_x, target_y, color);
+            }
+        }
+
+        self.mark(area);
+    }
+
+    /// Write the pixels that changed since the last flush to `target`
+    pub fn flush<T: Target>(&mut self, target: &mut T) {
+        for rect in self.dirty.as_slice() {
+            for y in rect.y..rect.bottom() {
+                let start = rect.x as usize + y as usize * self.width;
+                let end = start + rect.width as usize;
+
+                target.write_span(rect.x as usize, y as usize,
+                                  &self.pixels[start..end]);
+            }
+        }
+
+        self.dirty.clear();
+    }
+
+    /// Write the whole canvas to `target`, e.g. when the target was drawn
+    /// over by something else
+    pub fn flush_all<T: Target>(&mut self, target: &mut T) {
+        self.dirty.clear();
+        self.dirty.add(self.bounds());
+        self.flush(target);
+    }
+}
@@ -0,0 +1,345 @@
+use graphics::{Canvas, Bitmap, Color, Rect, Target};
+use graphics::image::{self, Format};
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 48;
+
+const RED: Color = Color::rgb(255, 0, 0);
+const BLUE: Color = Color::rgb(0, 0, 255);
+
+// A target that stands in for the framebuffer, it records every span so
+// the tests can check what the canvas flushed
+struct Screen {
+    pixels: Vec<Color>,
+    spans: usize,
+    written: usize,
+}
+
+impl Screen {
+    fn new() -> Screen {
+        Screen {
+            pixels: vec![Color::TRANSPARENT; WIDTH * HEIGHT],
+            spans: 0,
+            written: 0,
+        }
+    }
+}
+
+impl Target for Screen {
+    fn write_span(&mut self, x: usize, y: usize, pixels: &[Color]) {
+        let start = x + y * WIDTH;
+        self.pixels[start..start + pixels.len()].copy_from_slice(pixels);
+        self.spans += 1;
+        self.written += pixels.len();
+    }
+}
+
+// Count the pixels of the canvas with `color`
+fn count(canvas: &Canvas, color: Color) -> usize {
+    let mut count = 0;
+
+    for y in 0..canvas.height() as i32 {
+        for x in 0..canvas.width() as i32 {
+            if canvas.pixel(x, y) == Some(color) {
+                count += 1;
+            }
+        }
+    }
+
+    count
+}
+
+#[test]
+fn primitives() {
+    let mut buffer = vec![Color::BLACK; WIDTH * HEIGHT];
+    let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT).unwrap();
+
+    canvas.set_pixel(3, 4, RED);
+    assert_eq!(canvas.pixel(3, 4), Some(RED));
+    assert_eq!(canvas.pixel(-1, 4), None);
+
+    // A line should have both ends and one pixel for every step of the
+    // longest axis
+    canvas.clear(Color::BLACK);
+    canvas.draw_line(2, 1, 20, 7, RED);
+    assert_eq!(canvas.pixel(2, 1), Some(RED));
+    assert_eq!(canvas.pixel(20, 7), Some(RED));
+    assert_eq!(count(&canvas, RED), 19);
+
+    // The same line backwards covers the same pixels
+    canvas.draw_line(20, 7, 2, 1, BLUE);
+    assert_eq!(count(&canvas, RED), 0);
+    assert_eq!(count(&canvas, BLUE), 19);
+
+    canvas.clear(Color::BLACK);
+    canvas.fill_rect(Rect::new(10, 10, 5, 4), RED);
+    assert_eq!(count(&canvas, RED), 20);
+
+    // The outline is inside the rectangle
+    canvas.clear(Color::BLACK);
+    canvas.draw_rect(Rect::new(10, 10, 5, 4), RED);
+    assert_eq!(count(&canvas, RED), 14);
+    assert_eq!(canvas.pixel(11, 11), Some(Color::BLACK));
+    assert_eq!(canvas.pixel(14, 13), Some(RED));
+}
+
+#[test]
+fn clipping() {
+    let mut buffer = vec![Color::BLACK; WIDTH * HEIGHT];
+    let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT).unwrap();
+
+    // Drawing outside of the canvas is clipped instead of crashing
+    canvas.fill_rect(Rect::new(-10, -10, 20, 20), RED);
+    assert_eq!(count(&canvas, RED), 100);
+
+    canvas.draw_line(-100, 5, 100, 5, BLUE);
+    assert_eq!(count(&canvas, BLUE), WIDTH);
+    assert_eq!(count(&canvas, RED), 90);
+
+    // Only the clip is drawn on
+    canvas.clear(Color::BLACK);
+    canvas.set_clip(Rect::new(20, 20, 4, 4));
+    canvas.fill_rect(Rect::new(0, 0, WIDTH as u32, HEIGHT as u32), RED);
+    assert_eq!(count(&canvas, RED), 16);
+    assert_eq!(canvas.pixel(19, 20), Some(Color::BLACK));
+
+    // The clip is limited to the canvas
+    canvas.set_clip(Rect::new(60, 40, 100, 100));
+    assert_eq!(canvas.clip(), Rect::new(60, 40, 4, 8));
+
+    canvas.set_clip(Rect::new(100, 100, 10, 10));
+    assert!(canvas.clip().is_empty());
+    canvas.clear(BLUE);
+    assert_eq!(count(&canvas, BLUE), 0);
+
+    canvas.reset_clip();
+    assert_eq!(canvas.clip(), canvas.bounds());
+}
+
+#[test]
+fn blending() {
+    // Half red over blue is half of each
+    let color = Color::rgba(255, 0, 0, 128).over(BLUE);
+    assert_eq!(color, Color::rgb(128, 0, 127));
+
+    assert_eq!(Color::TRANSPARENT.over(BLUE), BLUE);
+    assert_eq!(RED.over(BLUE), RED);
+
+    // The bitmap is blended with the alpha of every pixel and clipped to
+    // the canvas
+    let pixels = [
+        RED, Color::TRANSPARENT,
+        Color::rgba(255, 255, 255, 128), RED,
+    ];
+    let bitmap = Bitmap::new(&pixels, 2, 2).unwrap();
+    assert!(Bitmap::new(&pixels, 3, 2).is_none());
+
+    let mut buffer = vec![Color::BLACK; WIDTH * HEIGHT];
+    let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT).unwrap();
+
+    canvas.blit(&bitmap, 5, 5);
+    assert_eq!(canvas.pixel(5, 5), Some(RED));
+    assert_eq!(canvas.pixel(6, 5), Some(Color::BLACK));
+    assert_eq!(canvas.pixel(5, 6), Some(Color::rgb(128, 128, 128)));
+    assert_eq!(canvas.pixel(6, 6), Some(RED));
+
+    canvas.blit(&bitmap, WIDTH as i32 - 1, -1);
+    assert_eq!(canvas.pixel(WIDTH as i32 - 1, 0),
+               Some(Color::rgb(128, 128, 128)));
+}
+
+#[test]
+fn dirty_flush() {
+    let mut buffer = vec![Color::BLACK; WIDTH * HEIGHT];
+    let mut canvas = Canvas::new(&mut buffer, WIDTH, HEIGHT).unwrap();
+    let mut screen = Screen::new();
+
+    canvas.flush_all(&mut screen);
+    assert_eq!(screen.written, WIDTH * HEIGHT);
+    assert!(canvas.dirty_rects().is_empty());
+
+    // Only the changed pixels are flushed, the overlapping rectangles are
+    // merged so nothing is written twice
+    screen.written = 0;
+    canvas.fill_rect(Rect::new(0, 0, 4, 4), RED);
+    canvas.fill_rect(Rect::new(2, 2, 4, 4), RED);
+    canvas.set_pixel(40, 40, BLUE);
+    assert_eq!(canvas.dirty_rects().len(), 2);
+
+    canvas.flush(&mut screen);
+    assert_eq!(screen.written, 6 * 6 + 1);
+    assert_eq!(screen.pixels[40 + 40 * WIDTH], BLUE);
+    assert_eq!(screen.pixels[3 + 3 * WIDTH], RED);
+
+    // Nothing changed so nothing is flushed
+    screen.spans = 0;
+    canvas.flush(&mut screen);
+    assert_eq!(screen.spans, 0);
+
+    // Too many rectangles are merged, but every change still reaches the
+    // screen
+    for i in 0..40 {
+        canvas.set_pixel((i * 7) % WIDTH as i32, (i * 5) % HEIGHT as i32,
+                         Color::rgb(i as u8, 1, 2));
+    }
+
+    assert!(canvas.dirty_rects().len() <= 16);
+    canvas.flush(&mut screen);
+
+    for i in 0..40 {
+        let (x, y) = ((i * 7) % WIDTH, (i * 5) % HEIGHT);
+        assert_eq!(screen.pixels[x + y * WIDTH], Color::rgb(i as u8, 1, 2));
+    }
+
+    // The flushed screen is the same as the canvas
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            assert_eq!(Some(screen.pixels[x + y * WIDTH]),
+                       canvas.pixel(x as i32, y as i32));
+        }
+    }
+}
+
+// The pixels of the test images, the second row has a half transparent
+// pixel for the images with alpha
+const IMAGE: [[(u8, u8, u8, u8); 3]; 2] = [
+    [(255, 0, 0, 255), (0, 255, 0, 255), (0, 0, 255, 255)],
+    [(10, 20, 30, 255), (40, 50, 60, 128), (255, 255, 255, 255)],
+];
+
+fn image_color(x: usize, y: usize, alpha: bool) -> Color {
+    let (r, g, b, a) = IMAGE[y][x];
+    Color::rgba(r, g, b, if alpha { a } else { 255 })
+}
+
+// Build a bottom up BMP with `bits` per pixel, the 32 bit image has a V4
+// header with the alpha mask
+fn build_bmp(bits: u16) -> Vec<u8> {
+    let header_size: u32 = if bits == 32 { 108 } else { 40 };
+    let stride = (3 * bits as usize / 8 + 3) & !3;
+    let data_offset = 14 + header_size;
+
+    let mut data = Vec::new();
+    data.extend(b"BM");
+    data.extend(&(data_offset + 2 * stride as u32).to_le_bytes());
+    data.extend(&[0; 4]);
+    data.extend(&data_offset.to_le_bytes());
+
+    data.extend(&header_size.to_le_bytes());
+    data.extend(&3i32.to_le_bytes());
+    data.extend(&2i32.to_le_bytes());
+    data.extend(&1u16.to_le_bytes());
+    data.extend(&bits.to_le_bytes());
+    data.extend(&(if bits == 32 { 3u32 } else { 0 }).to_le_bytes());
+    data.extend(&[0; 20]);
+
+    if bits == 32 {
+        for mask in &[0x00ff0000u32, 0x0000ff00, 0x000000ff, 0xff000000] {
+            data.extend(&mask.to_le_bytes());
+        }
+
+        data.resize(data_offset as usize, 0);
+    }
+
+    for y in (0..2).rev() {
+        let start = data.len();
+
+        for &(r, g, b, a) in IMAGE[y].iter() {
+            data.extend(&[b, g, r]);
+
+            if bits == 32 {
+                data.push(a);
+            }
+        }
+
+        data.resize(start + stride, 0);
+    }
+
+    data
+}
+
+// Build a TGA with alpha, the RLE image packs the first row as a raw
+// packet and a run
+fn build_tga(rle: bool) -> Vec<u8> {
+    let mut data = vec![0u8; 18];
+    data[0] = 2;
+    data[2] = if rle { 10 } else { 2 };
+    data[12..14].copy_from_slice(&3u16.to_le_bytes());
+    data[14..16].copy_from_slice(&2u16.to_le_bytes());
+    data[16] = 32;
+    data[17] = 8 | (1 << 5);
+
+    // The image id we need to skip
+    data.extend(b"id");
+
+    let pixel = |x: usize, y: usize| {
+        let (r, g, b, a) = IMAGE[y][x];
+        [b, g, r, a]
+    };
+
+    if rle {
+        data.push(0x00);
+        data.extend(&pixel(0, 0));
+        data.push(0x81);
+        data.extend(&pixel(1, 0));
+
+        data.push(0x02);
+        for x in 0..3 {
+            data.extend(&pixel(x, 1));
+        }
+    } else {
+        for y in 0..2 {
+            for x in 0..3 {
+                data.extend(&pixel(x, y));
+            }
+        }
+    }
+
+    data
+}
+
+#[test]
+fn images() {
+    for &bits in &[24, 32] {
+        let data = build_bmp(bits);
+        assert_eq!(image::format(&data), Some(Format::Bmp));
+        assert_eq!(image::size(&data), Some((3, 2)));
+
+        let mut pixels = vec![Color::TRANSPARENT; 6];
+        assert_eq!(image::decode(&data, &mut pixels), Some((3, 2)));
+
+        for y in 0..2 {
+            for x in 0..3 {
+                assert_eq!(pixels[x + y * 3], image_color(x, y, bits == 32));
+            }
+        }
+
+        // A broken image or a small buffer fails without a panic
+        assert_eq!(image::decode(&data[..data.len() - 4], &mut pixels), None);
+        assert_eq!(image::decode(&data, &mut pixels[..5]), None);
+    }
+
+    let data = build_tga(false);
+    assert_eq!(image::format(&data), Some(Format::Tga));
+
+    let mut pixels = vec![Color::TRANSPARENT; 6];
+    assert_eq!(image::decode(&data, &mut pixels), Some((3, 2)));
+
+    for y in 0..2 {
+        for x in 0..3 {
+            assert_eq!(pixels[x + y * 3], image_color(x, y, true));
+        }
+    }
+
+    // The run repeats the green pixel over the blue one
+    let data = build_tga(true);
+    let mut pixels = vec![Color::TRANSPARENT; 6];
+    assert_eq!(image::decode(&data, &mut pixels), Some((3, 2)));
+    assert_eq!(pixels[0], image_color(0, 0, true));
+    assert_eq!(pixels[1], image_color(1, 0, true));
+    assert_eq!(pixels[2], image_color(1, 0, true));
+    assert_eq!(pixels[4], image_color(1, 1, true));
+
+    assert_eq!(image::decode(&data[..data.len() - 1], &mut pixels), None);
+    assert_eq!(image::format(b"not an image"), None);
+}
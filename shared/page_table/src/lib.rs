@@ -0,0 +1,384 @@
+//! The x86_64 4-level page table walker, the tables are accessed through a
+//! `PhysicalMemory` backend so the same code can run inside the kernel
+//! through the direct map and on the host against a simulated RAM buffer
+
+#![no_std]
+
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The size of a page mapped by a P2 entry
+pub const HUGE_PAGE_SIZE: u64 = PAGE_SIZE * 512;
+
+/// The size of a page mapped by a P3 entry
+pub const GIANT_PAGE_SIZE: u64 = HUGE_PAGE_SIZE * 512;
+
+/// The number of entries inside a table
+pub const ENTRIES: usize = 512;
+
+pub const PRESENT:       u64 = 1 <<  0;
+pub const WRITE:         u64 = 1 <<  1;
+pub const USER:          u64 = 1 <<  2;
+pub const WRITE_THROUGH: u64 = 1 <<  3;
+pub const NO_CACHE:      u64 = 1 <<  4;
+pub const ACCESSED:      u64 = 1 <<  5;
+pub const DIRTY:         u64 = 1 <<  6;
+pub const HUGE:          u64 = 1 <<  7;
+pub const GLOBAL:        u64 = 1 <<  8;
+pub const NXE:           u64 = 1 << 63;
+
+/// The bits of an entry with the physical address
+pub const ADDRESS_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Access to the physical memory the tables are stored in
+pub trait PhysicalMemory {
+    /// Read the u64 at the physical `address`
+    fn read_u64(&self, address: u64) -> u64;
+
+    /// Write `value` to the physical `address`
+    fn write_u64(&mut self, address: u64, value: u64);
+}
+
+/// Allocator for the frames of new tables
+pub trait FrameAllocator {
+    /// Allocate a frame and return the physical address of it
+    fn allocate_frame(&mut self) -> Option<u64>;
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MapError {
+    /// The page is already mapped
+    AlreadyMapped,
+
+    /// A huge page is in the way of the table we need
+    HugePage,
+
+    /// The allocator is out of frames for the tables
+    OutOfMemory,
+
+    /// The address is not aligned to the size of the page
+    Unaligned,
+}
+
+/// A mapping from a leaf entry
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Mapping {
+    /// The start of the page
+    pub virtual_address: u64,
+
+    /// The start of the frame
+    pub physical_address: u64,
+
+    /// The size of the page, 4 KiB, 2 MiB or 1 GiB
+    pub size: u64,
+
+    /// The flags of the entry without the address
+    pub flags: u64,
+}
+
+/// Get the table index for `address` at `level`, level 4 is the P4 table
+pub fn table_index(address: u64, level: usize) -> usize {
+    ((address >> (12 + 9 * (level - 1))) & 0x1ff) as usize
+}
+
+/// Sign extend bit 47 so the address is canonical
+pub fn canonical(address: u64) -> u64 {
+    (((address << 16) as i64) >> 16) as u64
+}
+
+/// The size of the page a leaf entry maps at `level`
+fn level_size(level: usize) -> u64 {
+    match level {
+        1 => PAGE_SIZE,
+        2 => HUGE_PAGE_SIZE,
+        3 => GIANT_PAGE_SIZE,
+        _ => unreachable!(),
+    }
+}
+
+pub struct PageTable<'a, M: PhysicalMemory> {
+    memory: &'a mut M,
+
+    /// The physical address of the P4 table
+    p4: u64,
+}
+
+impl<'a, M: PhysicalMemory> PageTable<'a, M> {
+    /// Use the tables starting at the P4 table at the physical address `p4`
+    pub fn new(memory: &'a mut M, p4: u64) -> PageTable<'a, M> {
+        PageTable {
+            memory,
+            p4,
+        }
+    }
+
+    pub fn p4(&self) -> u64 {
+        self.p4
+    }
+
+    /// Read entry `index` of the table at the physical address `table`
+    pub fn entry(&self, table: u64, index: usize) -> u64 {
+        self.memory.read_u64(table + index as u64 * 8)
+    }
+
+    pub fn set_entry(&mut self, table: u64, index: usize, value: u64) {
+        self.memory.write_u64(table + index as u64 * 8, value);
+    }
+
+    /// Zero the table at the physical address `table`
+    pub fn zero_table(&mut self, table: u64) {
+        for index in 0..ENTRIES {
+            self.set_entry(table, index, 0);
+        }
+    }
+
+    /// Get the table the entry points to, `None` if the entry is not
+    /// present or maps a huge page
+    fn next_table(&self, table: u64, index: usize) -> Option<u64> {
+        let entry = self.entry(table, index);
+
+        if entry & PRESENT != 0 && entry & HUGE == 0 {
+            Some(entry & ADDRESS_MASK)
+        } else {
+            None
+        }
+    }
+
+    /// Get the table the entry points to and create it if it's not present
+    fn next_table_create<A>(&mut self, table: u64, index: usize,
+                            user: bool, allocator: &mut A)
+        -> Result<u64, MapError>
+        where A: FrameAllocator
+    {
+        let entry = self.entry(table, index);
+
+        if entry & PRESENT != 0 {
+            if entry & HUGE != 0 {
+                return Err(MapError::HugePage);
+            }
+
+            // The user bit is checked at every level
+            if user && entry & USER == 0 {
+                self.set_entry(table, index, entry | USER);
+            }
+
+            return Ok(entry & ADDRESS_MASK);
+        }
+
+        let frame = allocator.allocate_frame()
+            .ok_or(MapError::OutOfMemory)?;
+        self.zero_table(frame);
+
+        // The permissions are checked at every level so the tables allow
+        // writes and the leaf entry decides
+        let flags = if user { PRESENT | WRITE | USER } else { PRESENT | WRITE };
+        self.set_entry(table, index, frame | flags);
+
+        Ok(frame)
+    }
+
+    /// Walk the tables for `address` down to `level` and return the table
+    /// at that level
+    fn table_at(&self, address: u64, level: usize) -> Option<u64> {
+        let mut table = self.p4;
+
+        for current in (level + 1..=4).rev() {
+            table = self.next_table(table, table_index(address, current))?;
+        }
+
+        Some(table)
+    }
+
+    /// Find the leaf entry that maps `address`, returns the table, the
+    /// level and the index of the entry
+    fn leaf(&self, address: u64) -> Option<(u64, usize, usize)> {
+        let mut table = self.p4;
+
+        for level in (1..=4).rev() {
+            let index = table_index(address, level);
+            let entry = self.entry(table, index);
+
+            if entry & PRESENT == 0 {
+                return None;
+            }
+
+            if level == 1 || (level <= 3 && entry & HUGE != 0) {
+                return Some((table, level, index));
+            }
+
+            table = entry & ADDRESS_MASK;
+        }
+
+        None
+    }
+
+    /// Get the mapping of the page containing `address`
+    pub fn mapping(&self, address: u64) -> Option<Mapping> {
+        let (table, level, index) = self.leaf(address)?;
+        let entry = self.entry(table, index);
+        let size = level_size(level);
+
+        // The PAT bit of a huge entry is bit 12 so it's not part of the
+        // address of the frame
+        let mask = if level == 1 {
+            ADDRESS_MASK
+        } else {
+            ADDRESS_MASK & !(size - 1)
+        };
+
+        Some(Mapping {
+            virtual_address: address & !(size - 1),
+            physical_address: entry & mask,
+            size,
+            flags: entry & !mask,
+        })
+    }
+
+    /// Translate the virtual `address` to a physical address
+    pub fn translate(&self, address: u64) -> Option<u64> {
+        self.mapping(address).map(|mapping| {
+            mapping.physical_address + (address - mapping.virtual_address)
+        })
+    }
+
+    /// Map the 4 KiB page at `address` to the frame at `physical` with
+    /// `flags`, the present bit is always set
+    pub fn map_to<A>(&mut self, address: u64, physical: u64, flags: u64,
+                     allocator: &mut A) -> Result<(), MapError>
+        where A: FrameAllocator
+    {
+        self.map_at_level(address, physical, flags, 1, allocator)
+    }
+
+    /// Map the 2 MiB page at `address` to the 2 MiB frame at `physical`
+    pub fn map_to_huge<A>(&mut self, address: u64, physical: u64,
+                          flags: u64, allocator: &mut A)
+        -> Result<(), MapError>
+        where A: FrameAllocator
+    {
+        self.map_at_level(address, physical, flags | HUGE, 2, allocator)
+    }
+
+    fn map_at_level<A>(&mut self, address: u64, physical: u64, flags: u64,
+                       level: usize, allocator: &mut A)
+        -> Result<(), MapError>
+        where A: FrameAllocator
+    {
+        let size = level_size(level);
+        if address & (size - 1) != 0 || physical & (size - 1) != 0 {
+            return Err(MapError::Unaligned);
+        }
+
+        let user = flags & USER != 0;
+
+        let mut table = self.p4;
+        for current in (level + 1..=4).rev() {
+            table = self.next_table_create(table,
+                                           table_index(address, current),
+                                           user, allocator)?;
+        }
+
+        let index = table_index(address, level);
+        if self.entry(table, index) & PRESENT != 0 {
+            return Err(MapError::AlreadyMapped);
+        }
+
+        self.set_entry(table, index, physical | PRESENT | flags);
+
+        Ok(())
+    }
+
+    /// Remove the mapping of the page at `address` and return the mapping
+    /// it had, the caller needs to flush the TLB
+    pub fn unmap(&mut self, address: u64) -> Option<Mapping> {
+        let mapping = self.mapping(address)?;
+        let (table, _, index) = self.leaf(address)?;
+
+        self.set_entry(table, index, 0);
+
+        Some(mapping)
+    }
+
+    /// Change the flags of the page at `address`, returns `None` if the
+    /// page is not mapped
+    pub fn update_flags<F>(&mut self, address: u64, f: F) -> Option<()>
+        where F: FnOnce(u64) -> u64
+    {
+        let mapping = self.mapping(address)?;
+        let (table, _, index) = self.leaf(address)?;
+
+        let flags = f(mapping.flags) | PRESENT;
+        self.set_entry(table, index, mapping.physical_address | flags);
+
+        Some(())
+    }
+
+    /// Get the P1 table for `address` if it exists
+    pub fn p1_table(&self, address: u64) -> Option<u64> {
+        self.table_at(address, 1)
+    }
+
+    /// Count the tables including the P4 table, the recursive entry is not
+    /// followed
+    pub fn table_count(&self) -> u64 {
+        self.count_tables(self.p4, 4)
+    }
+
+    fn count_tables(&self, table: u64, level: usize) -> u64 {
+        let mut count = 1;
+
+        if level == 1 {
+            return count;
+        }
+
+        for index in 0..ENTRIES {
+            let entry = self.entry(table, index);
+            if entry & PRESENT == 0 || entry & HUGE != 0 {
+                continue;
+            }
+
+            if level == 4 && entry & ADDRESS_MASK == self.p4 {
+                continue;
+            }
+
+            count += self.count_tables(entry & ADDRESS_MASK, level - 1);
+        }
+
+        count
+    }
+
+    /// Call `f` with every mapping inside the tables in order of the
+    /// virtual address
+    pub fn for_each_mapping<F>(&self, mut f: F)
+        where F: FnMut(Mapping)
+    {
+        self.walk_table(self.p4, 4, 0, &mut f);
+    }
+
+    fn walk_table<F>(&self, table: u64, level: usize, base: u64, f: &mut F)
+        where F: FnMut(Mapping)
+    {
+        for index in 0..ENTRIES {
+            let entry = self.entry(table, index);
+            if entry & PRESENT == 0 {
+                continue;
+            }
+
+            // A recursive entry points back to the P4 table, following it
+            // would walk all the tables again as if they were pages
+            if level == 4 && entry & ADDRESS_MASK == self.p4 {
+                continue;
+            }
+
+            let address = canonical(base |
+                                    (index as u64) << (12 + 9 * (level - 1)));
+
+            if level == 1 || (level <= 3 && entry & HUGE != 0) {
+                if let Some(mapping) = self.mapping(address) {
+                    f(mapping);
+                }
+            } else {
+                self.walk_table(entry & ADDRESS_MASK, level - 1, address, f);
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+use page_table::{PageTable, PhysicalMemory, FrameAllocator, MapError};
+use page_table::{PAGE_SIZE, HUGE_PAGE_SIZE, WRITE, USER, NXE, HUGE};
+
+// The size of the simulated RAM, this is enough for a few thousand tables
+const RAM_SIZE: u64 = 64 * 1024 * 1024;
+
+// Simulated physical memory, physical address 0 is the start of the buffer
+struct Ram {
+    memory: Vec<u64>,
+}
+
+impl Ram {
+    fn new() -> Ram {
+        Ram {
+            memory: vec![0; (RAM_SIZE / 8) as usize],
+        }
+    }
+}
+
+impl PhysicalMemory for Ram {
+    fn read_u64(&self, address: u64) -> u64 {
+        assert!(address & 7 == 0, "Unaligned read at {:#x}", address);
+        self.memory[(address / 8) as usize]
+    }
+
+    fn write_u64(&mut self, address: u64, value: u64) {
+        assert!(address & 7 == 0, "Unaligned write at {:#x}", address);
+        self.memory[(address / 8) as usize] = value;
+    }
+}
+
+// Hands out frames from the end of the RAM so the tables never overlap
+// the frames the tests map
+struct BumpAllocator {
+    next: u64,
+    end: u64,
+    allocated: u64,
+}
+
+impl BumpAllocator {
+    fn new(start: u64, end: u64) -> BumpAllocator {
+        BumpAllocator {
+            next: start,
+            end,
+            allocated: 0,
+        }
+    }
+}
+
+impl FrameAllocator for BumpAllocator {
+    fn allocate_frame(&mut self) -> Option<u64> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        let frame = self.next;
+        self.next += PAGE_SIZE;
+        self.allocated += 1;
+
+        Some(frame)
+    }
+}
+
+// Small xorshift generator so the random tests are the same every run
+// without needing any crates
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Setup the RAM with a P4 table at the start of the table area
+fn setup() -> (Ram, BumpAllocator, u64) {
+    let ram = Ram::new();
+    let mut allocator = BumpAllocator::new(RAM_SIZE / 2, RAM_SIZE);
+    let p4 = allocator.allocate_frame().unwrap();
+
+    (ram, allocator, p4)
+}
+
+#[test]
+fn map_translate() {
+    let (mut ram, mut allocator, p4) = setup();
+    let mut table = PageTable::new(&mut ram, p4);
+
+    let address = 0xffff_8000_1234_5000;
+    assert_eq!(table.translate(address), None);
+
+    table.map_to(address, 0x42000, WRITE | NXE, &mut allocator).unwrap();
+
+    // A new mapping needs a P3, P2 and P1 table
+    assert_eq!(allocator.allocated, 4);
+
+    assert_eq!(table.translate(address), Some(0x42000));
+    assert_eq!(table.translate(address + 0x123), Some(0x42123));
+    assert_eq!(table.translate(address + PAGE_SIZE), None);
+
+    let mapping = table.mapping(address + 8).unwrap();
+    assert_eq!(mapping.virtual_address, address);
+    assert_eq!(mapping.size, PAGE_SIZE);
+    assert!(mapping.flags & WRITE != 0 && mapping.flags & NXE != 0);
+
+    // The next page shares all the tables
+    table.map_to(address + PAGE_SIZE, 0x43000, 0, &mut allocator).unwrap();
+    assert_eq!(allocator.allocated, 4);
+
+    assert_eq!(table.map_to(address, 0x44000, 0, &mut allocator),
+               Err(MapError::AlreadyMapped));
+    assert_eq!(table.map_to(address + 1, 0x44000, 0, &mut allocator),
+               Err(MapError::Unaligned));
+}
+
+#[test]
+fn huge_pages() {
+    let (mut ram, mut allocator, p4) = setup();
+    let mut table = PageTable::new(&mut ram, p4);
+
+    let address = 0x4000_0000;
+    table.map_to_huge(address, 0x20_0000, WRITE, &mut allocator).unwrap();
+
+    assert_eq!(table.translate(address + 0x1_2345), Some(0x21_2345));
+
+    let mapping = table.mapping(address + HUGE_PAGE_SIZE - 1).unwrap();
+    assert_eq!(mapping.size, HUGE_PAGE_SIZE);
+    assert!(mapping.flags & HUGE != 0);
+
+    // We can't map a 4 KiB page inside the huge page
+    assert_eq!(table.map_to(address + PAGE_SIZE, 0x1000, 0, &mut allocator),
+               Err(MapError::HugePage));
+
+    assert_eq!(table.map_to_huge(address + PAGE_SIZE, 0, 0, &mut allocator),
+               Err(MapError::Unaligned));
+
+    let unmapped = table.unmap(address).unwrap();
+    assert_eq!(unmapped.physical_address, 0x20_0000);
+    assert_eq!(table.translate(address), None);
+}
+
+#[test]
+fn unmap_and_flags() {
+    let (mut ram, mut allocator, p4) = setup();
+    let mut table = PageTable::new(&mut ram, p4);
+
+    let address = 0x7000_0000;
+    table.map_to(address, 0x5000, WRITE | USER, &mut allocator).unwrap();
+
+    // The user bit has to be set at every level
+    let p4_entry = table.entry(p4, page_table::table_index(address, 4));
+    assert!(p4_entry & USER != 0);
+
+    table.update_flags(address, |flags| flags & !WRITE).unwrap();
+    let mapping = table.mapping(address).unwrap();
+    assert!(mapping.flags & WRITE == 0 && mapping.flags & USER != 0);
+    assert_eq!(mapping.physical_address, 0x5000);
+
+    assert_eq!(table.unmap(address).map(|x| x.physical_address),
+               Some(0x5000));
+    assert_eq!(table.unmap(address), None);
+    assert_eq!(table.update_flags(address, |flags| flags), None);
+}
+
+#[test]
+fn out_of_memory() {
+    let mut ram = Ram::new();
+
+    // Only room for the P4 and a P3 table
+    let mut allocator = BumpAllocator::new(0, 2 * PAGE_SIZE);
+    let p4 = allocator.allocate_frame().unwrap();
+    let mut table = PageTable::new(&mut ram, p4);
+
+    assert_eq!(table.map_to(0x1000, 0x1000, 0, &mut allocator),
+               Err(MapError::OutOfMemory));
+    assert_eq!(table.translate(0x1000), None);
+}
+
+#[test]
+fn random_mappings() {
+    let (mut ram, mut allocator, p4) = setup();
+    let mut table = PageTable::new(&mut ram, p4);
+    let mut random = Random(0x1234_5678_9abc_def1);
+
+    // The model of what we expect the tables to contain
+    let mut model: HashMap<u64, (u64, u64)> = HashMap::new();
+
+    for _ in 0..20000 {
+        // Keep the addresses inside a small part of the address space so
+        // we get a mix of shared and new tables, and use both halves
+        let mut address = (random.next() % (1 << 16)) * PAGE_SIZE;
+        if random.next() & 1 != 0 {
+            address = page_table::canonical(address | (1 << 47));
+        }
+
+        let physical = (random.next() % (RAM_SIZE / 2 / PAGE_SIZE)) *
+            PAGE_SIZE;
+        let flags = random.next() & (WRITE | USER | NXE);
+
+        match random.next() % 4 {
+            // Map the page and it should only fail if it's mapped
+            0 | 1 => {
+                let result = table.map_to(address, physical, flags,
+                                          &mut allocator);
+
+                match model.entry(address) {
+                    Entry::Occupied(_) => {
+                        assert_eq!(result, Err(MapError::AlreadyMapped));
+                    }
+                    Entry::Vacant(entry) => {
+                        assert_eq!(result, Ok(()));
+                        entry.insert((physical, flags));
+                    }
+                }
+            }
+
+            // Unmap the page
+            2 => {
+                let result = table.unmap(address)
+                    .map(|x| x.physical_address);
+                assert_eq!(result, model.remove(&address).map(|x| x.0));
+            }
+
+            // Translate a random address inside the page
+            _ => {
+                let offset = random.next() % PAGE_SIZE;
+                assert_eq!(table.translate(address + offset),
+                           model.get(&address).map(|x| x.0 + offset));
+            }
+        }
+    }
+
+    // Everything inside the model should be mapped with the right flags
+    for (&address, &(physical, flags)) in model.iter() {
+        let mapping = table.mapping(address).unwrap();
+        assert_eq!(mapping.physical_address, physical);
+        assert_eq!(mapping.flags & (WRITE | USER | NXE), flags);
+    }
+
+    // And the walk should find exactly the pages inside the model
+    let mut count = 0;
+    let mut last = None;
+    table.for_each_mapping(|mapping| {
+        assert!(model.contains_key(&mapping.virtual_address));
+
+        // The walk is in order of the table indices, the lower half comes
+        // before the higher half
+        if let Some(last) = last {
+            assert!(mapping.virtual_address > last);
+        }
+        last = Some(mapping.virtual_address);
+
+        count += 1;
+    });
+    assert_eq!(count, model.len());
+
+    // Every table we allocated is reachable from the P4 table
+    assert_eq!(table.table_count(), allocator.allocated);
+
+    println!("  {} pages mapped with {} table frames",
+             model.len(), allocator.allocated);
+}
+
+#[test]
+fn recursive_entry() {
+    let (mut ram, mut allocator, p4) = setup();
+    let mut table = PageTable::new(&mut ram, p4);
+
+    // The kernel points entry 510 back to the P4 table
+    table.set_entry(p4, 510, p4 | page_table::PRESENT | WRITE);
+    table.map_to(0x1000, 0x2000, 0, &mut allocator).unwrap();
+
+    // The recursive entry should not show up as mappings
+    let mut count = 0;
+    table.for_each_mapping(|_| count += 1);
+    assert_eq!(count, 1);
+    assert_eq!(table.table_count(), 4);
+
+    // But the tables can be reached through it like on the real hardware
+    let p4_address = page_table::canonical(
+        (510 << 39) | (510 << 30) | (510 << 21) | (510 << 12));
+    assert_eq!(table.translate(p4_address), Some(p4));
+}
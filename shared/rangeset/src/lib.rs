@@ -44,6 +44,11 @@ impl RangeSet {
         &self.ranges[..self.in_use as usize]
     }
 
+    /// Get the number of ranges the RangeSet can hold
+    pub fn capacity(&self) -> usize {
+        self.ranges.len()
+    }
+
     /// Delete the Range contained in the RangeSet at `idx`
     fn delete(&mut self, idx: usize) {
         assert!(idx < self.in_use as usize, "Index out of bounds");
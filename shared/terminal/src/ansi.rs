@@ -0,0 +1,387 @@
+//! Parser for the VT100/ANSI escape sequences, the parser turns the bytes
+//! into actions so every console can handle the output the same way a
+//! serial terminal would
+
+/// The maximum number of parameters inside a control sequence, the rest
+/// of the parameters are ignored
+const MAX_PARAMS: usize = 8;
+
+/// The escape character that starts every sequence
+const ESCAPE: u8 = 0x1b;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Color {
+    Black   = 0,
+    Red     = 1,
+    Green   = 2,
+    Yellow  = 3,
+    Blue    = 4,
+    Magenta = 5,
+    Cyan    = 6,
+    White   = 7,
+}
+
+impl Color {
+    fn from_index(index: u16) -> Color {
+        match index {
+            0 => Color::Black,
+            1 => Color::Red,
+            2 => Color::Green,
+            3 => Color::Yellow,
+            4 => Color::Blue,
+            5 => Color::Magenta,
+            6 => Color::Cyan,
+            _ => Color::White,
+        }
+    }
+}
+
+/// The part of the screen an erase sequence clears
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Erase {
+    /// From the cursor to the end
+    ToEnd,
+
+    /// From the start to the cursor
+    ToStart,
+
+    /// Everything
+    All,
+}
+
+impl Erase {
+    fn from_param(param: u16) -> Option<Erase> {
+        match param {
+            0 => Some(Erase::ToEnd),
+            1 => Some(Erase::ToStart),
+            2 | 3 => Some(Erase::All),
+            _ => None,
+        }
+    }
+}
+
+/// What the console should do for the bytes the parser has seen
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Print the byte at the cursor, the characters outside of ASCII are
+    /// given one UTF-8 byte at a time
+    Print(u8),
+
+    /// Move the cursor to the start of the next line
+    Newline,
+
+    /// Move the cursor to the start of the line
+    CarriageReturn,
+
+    /// Move the cursor to the next tab stop
+    Tab,
+
+    /// Move the cursor one column back
+    Backspace,
+
+    /// Move the cursor up, down, right or left by a number of cells, the
+    /// cursor stops at the edge of the screen
+    CursorUp(u16),
+    CursorDown(u16),
+    CursorForward(u16),
+    CursorBack(u16),
+
+    /// Move the cursor to the row and column, these start at 0
+    CursorPosition { row: u16, column: u16 },
+
+    /// Move the cursor to the column on the current line
+    CursorColumn(u16),
+
+    EraseDisplay(Erase),
+    EraseLine(Erase),
+
+    SaveCursor,
+    RestoreCursor,
+
+    /// Show or hide the cursor
+    ShowCursor(bool),
+
+    /// Reset the colors and the attributes to the default
+    ResetAttributes,
+
+    /// Make the foreground color bright
+    Bold(bool),
+
+    /// Swap the foreground and the background color
+    Reverse(bool),
+
+    /// Set the foreground color, `true` for the bright version
+    Foreground(Color, bool),
+    Background(Color, bool),
+
+    DefaultForeground,
+    DefaultBackground,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    /// Normal characters
+    Ground,
+
+    /// We got the escape character
+    Escape,
+
+    /// We are inside a control sequence (ESC [)
+    Csi,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Parser {
+    state: State,
+
+    params: [u16; MAX_PARAMS],
+    param_count: usize,
+
+    /// The sequence had a '?' so it's a private (DEC) sequence
+    private: bool,
+}
+
+impl Default for Parser {
+    fn default() -> Parser {
+        Parser::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            params: [0; MAX_PARAMS],
+            param_count: 0,
+            private: false,
+        }
+    }
+
+    /// Feed the next byte to the parser, `f` is called for every action
+    /// the byte completes
+    pub fn advance<F>(&mut self, byte: u8, mut f: F)
+        where F: FnMut(Action)
+    {
+        // A new escape always starts a new sequence, and CAN and SUB
+        // cancel the sequence
+        if byte == ESCAPE {
+            self.state = State::Escape;
+            return;
+        }
+
+        if byte == 0x18 || byte == 0x1a {
+            self.state = State::Ground;
+            return;
+        }
+
+        match self.state {
+            State::Ground => self.ground(byte, &mut f),
+            State::Escape => self.escape(byte, &mut f),
+            State::Csi => self.csi(byte, &mut f),
+        }
+    }
+
+    fn ground<F>(&mut self, byte: u8, f: &mut F)
+        where F: FnMut(Action)
+    {
+        match byte {
+            b'\n' | 0x0b | 0x0c => f(Action::Newline),
+            b'\r' => f(Action::CarriageReturn),
+            b'\t' => f(Action::Tab),
+            0x08 => f(Action::Backspace),
+
+            // The rest of the control characters are ignored
+            0x00..=0x1f | 0x7f => {}
+
+            _ => f(Action::Print(byte)),
+        }
+    }
+
+    fn escape<F>(&mut self, byte: u8, f: &mut F)
+        where F: FnMut(Action)
+    {
+        self.state = State::Ground;
+
+        match byte {
+            b'[' => {
+                self.params = [0; MAX_PARAMS];
+                self.param_count = 0;
+                self.private = false;
+                self.state = State::Csi;
+            }
+
+            // The DEC versions of save and restore cursor
+            b'7' => f(Action::SaveCursor),
+            b'8' => f(Action::RestoreCursor),
+
+            // Reset to the initial state
+            b'c' => {
+                f(Action::ResetAttributes);
+                f(Action::EraseDisplay(Erase::All));
+                f(Action::CursorPosition { row: 0, column: 0 });
+            }
+
+            _ => {}
+        }
+    }
+
+    fn csi<F>(&mut self, byte: u8, f: &mut F)
+        where F: FnMut(Action)
+    {
+        match byte {
+            b'0'..=b'9' => {
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+
+                if let Some(param) = self.params.get_mut(self.param_count - 1)
+                {
+                    *param = param.saturating_mul(10)
+                        .saturating_add((byte - b'0') as u16);
+                }
+            }
+
+            b';' => {
+                // An empty parameter is a 0
+                if self.param_count == 0 {
+                    self.param_count = 1;
+                }
+
+                self.param_count += 1;
+            }
+
+            b'?' => self.private = true,
+
+            // The intermediate bytes and the sub parameters are not used by
+            // anything we support
+            0x20..=0x2f | b':' | b'<'..=b'>' => {}
+
+            // The final byte, run the sequence
+            0x40..=0x7e => {
+                self.state = State::Ground;
+
+                if self.private {
+                    self.execute_private(byte, f);
+                } else {
+                    self.execute(byte, f);
+                }
+            }
+
+            // A control character inside a sequence is executed as normal
+            _ => self.ground(byte, f),
+        }
+    }
+
+    /// Get parameter `index`, if it's missing or 0 we use `default`
+    fn param(&self, index: usize, default: u16) -> u16 {
+        if index >= self.param_count.min(MAX_PARAMS) {
+            return default;
+        }
+
+        match self.params[index] {
+            0 => default,
+            param => param,
+        }
+    }
+
+    fn execute<F>(&mut self, command: u8, f: &mut F)
+        where F: FnMut(Action)
+    {
+        match command {
+            b'A' => f(Action::CursorUp(self.param(0, 1))),
+            b'B' => f(Action::CursorDown(self.param(0, 1))),
+            b'C' => f(Action::CursorForward(self.param(0, 1))),
+            b'D' => f(Action::CursorBack(self.param(0, 1))),
+            b'G' => f(Action::CursorColumn(self.param(0, 1) - 1)),
+
+            b'H' | b'f' => f(Action::CursorPosition {
+                row: self.param(0, 1) - 1,
+                column: self.param(1, 1) - 1,
+            }),
+
+            b'J' => {
+                if let Some(erase) = Erase::from_param(self.param(0, 0)) {
+                    f(Action::EraseDisplay(erase));
+                }
+            }
+
+            b'K' => {
+                if let Some(erase) = Erase::from_param(self.param(0, 0)) {
+                    f(Action::EraseLine(erase));
+                }
+            }
+
+            b's' => f(Action::SaveCursor),
+            b'u' => f(Action::RestoreCursor),
+
+            b'm' => self.select_graphic_rendition(f),
+
+            _ => {}
+        }
+    }
+
+    /// Run the private sequences, we only support showing and hiding the
+    /// cursor
+    fn execute_private<F>(&mut self, command: u8, f: &mut F)
+        where F: FnMut(Action)
+    {
+        match (command, self.param(0, 0)) {
+            (b'h', 25) => f(Action::ShowCursor(true)),
+            (b'l', 25) => f(Action::ShowCursor(false)),
+            _ => {}
+        }
+    }
+
+    /// Handle the SGR sequence, every parameter changes one attribute
+    fn select_graphic_rendition<F>(&mut self, f: &mut F)
+        where F: FnMut(Action)
+    {
+        // No parameters is the same as a reset
+        if self.param_count == 0 {
+            f(Action::ResetAttributes);
+            return;
+        }
+
+        // TODO(patrik): Support the 256 color and the true color
+        // sequences, for now we skip the color parameters of them
+        let mut index = 0;
+        let count = self.param_count.min(MAX_PARAMS);
+
+        while index < count {
+            let param = self.params[index];
+
+            match param {
+                0 => f(Action::ResetAttributes),
+                1 => f(Action::Bold(true)),
+                22 => f(Action::Bold(false)),
+                7 => f(Action::Reverse(true)),
+                27 => f(Action::Reverse(false)),
+
+                30..=37 => f(Action::Foreground(Color::from_index(param - 30),
+                                                false)),
+                39 => f(Action::DefaultForeground),
+                40..=47 => f(Action::Background(Color::from_index(param - 40),
+                                                false)),
+                49 => f(Action::DefaultBackground),
+
+                90..=97 => f(Action::Foreground(Color::from_index(param - 90),
+                                                true)),
+                100..=107 => f(Action::Background(
+                    Color::from_index(param - 100), true)),
+
+                38 | 48 => {
+                    // 5;n is a 256 color and 2;r;g;b is a true color
+                    index += match self.params.get(index + 1) {
+                        Some(5) => 2,
+                        Some(2) => 4,
+                        _ => 0,
+                    };
+                }
+
+                _ => {}
+            }
+
+            index += 1;
+        }
+    }
+}
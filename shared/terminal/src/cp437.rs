@@ -0,0 +1,150 @@
+//! Translation from Unicode to code page 437, the character set of the VGA
+//! text mode and of the console fonts. The console decodes the UTF-8 it is
+//! given and looks up the glyph of every character here
+
+/// The glyph used for the characters code page 437 doesn't have, this is
+/// the small square
+pub const DEFAULT_REPLACEMENT: u8 = 0xfe;
+
+/// The glyphs 0x01 to 0x1f, the console never prints these as control
+/// characters so the glyphs can be used
+const LOW_GLYPHS: &str = "\
+    ☺☻♥♦♣♠•◘○◙♂♀♪♫☼\
+    ►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+
+/// The glyph 0x7f
+const DELETE_GLYPH: char = '⌂';
+
+/// The glyphs 0x80 to 0xff
+const HIGH_GLYPHS: &str = "\
+    ÇüéâäàåçêëèïîìÄÅ\
+    ÉæÆôöòûùÿÖÜ¢£¥₧ƒ\
+    áíóúñÑªº¿⌐¬½¼¡«»\
+    ░▒▓│┤╡╢╖╕╣║╗╝╜╛┐\
+    └┴┬├─┼╞╟╚╔╩╦╠═╬╧\
+    ╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+    αßΓπΣσµτΦΘΩδ∞φε∩\
+    ≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Characters that look like a glyph of code page 437 close enough, e.g.
+/// the Greek letters that are the same as a symbol and the typographic
+/// quotes and dashes
+const SIMILAR: [(char, u8); 14] = [
+    ('β', 0xe1),
+    ('μ', 0xe6),
+    ('Ω', 0xea),
+    ('∅', 0xed),
+    ('ϕ', 0xed),
+    ('∈', 0xee),
+    ('‘', b'\''),
+    ('’', b'\''),
+    ('“', b'"'),
+    ('”', b'"'),
+    ('–', b'-'),
+    ('—', b'-'),
+    ('−', b'-'),
+    ('×', b'x'),
+];
+
+/// Get the glyph of `character`, returns `None` if code page 437 has no
+/// glyph for it
+pub fn from_char(character: char) -> Option<u8> {
+    // ASCII is the same, except the control characters
+    if (' '..='~').contains(&character) {
+        return Some(character as u8);
+    }
+
+    if character == DELETE_GLYPH {
+        return Some(0x7f);
+    }
+
+    if let Some(index) = LOW_GLYPHS.chars().position(|c| c == character) {
+        return Some(index as u8 + 0x01);
+    }
+
+    if let Some(index) = HIGH_GLYPHS.chars().position(|c| c == character) {
+        return Some(index as u8 + 0x80);
+    }
+
+    SIMILAR.iter()
+        .find(|&&(similar, _)| similar == character)
+        .map(|&(_, glyph)| glyph)
+}
+
+/// A decoder for UTF-8 that is given one byte at a time, the sequences can
+/// be split between the writes to the console
+#[derive(Copy, Clone, Debug)]
+pub struct Utf8Decoder {
+    /// The bits of the code point we have so far
+    code_point: u32,
+
+    /// The number of continuation bytes we are still waiting for
+    remaining: u8,
+
+    /// The smallest code point for the length of the sequence, anything
+    /// less is an overlong encoding
+    minimum: u32,
+}
+
+impl Default for Utf8Decoder {
+    fn default() -> Utf8Decoder {
+        Utf8Decoder::new()
+    }
+}
+
+impl Utf8Decoder {
+    pub const fn new() -> Utf8Decoder {
+        Utf8Decoder {
+            code_point: 0,
+            remaining: 0,
+            minimum: 0,
+        }
+    }
+
+    /// Feed the next byte to the decoder, `f` is called with every
+    /// character the byte completes and with `None` for every broken
+    /// sequence
+    pub fn advance<F>(&mut self, byte: u8, mut f: F)
+        where F: FnMut(Option<char>)
+    {
+        if self.remaining > 0 {
+            if byte & 0xc0 == 0x80 {
+                self.code_point = self.code_point << 6 | (byte & 0x3f) as u32;
+                self.remaining -= 1;
+
+                if self.remaining == 0 {
+                    // The surrogates and the code points above U+10FFFF
+                    // are not characters
+                    let character = if self.code_point >= self.minimum {
+                        core::char::from_u32(self.code_point)
+                    } else {
+                        None
+                    };
+
+                    f(character);
+                }
+
+                return;
+            }
+
+            // The sequence ended early, the byte starts something new
+            self.remaining = 0;
+            f(None);
+        }
+
+        let (code_point, remaining, minimum) = match byte {
+            0x00..=0x7f => return f(Some(byte as char)),
+            0xc0..=0xdf => (byte & 0x1f, 1, 0x80),
+            0xe0..=0xef => (byte & 0x0f, 2, 0x800),
+            0xf0..=0xf7 => (byte & 0x07, 3, 0x10000),
+
+            // A continuation byte without a start or a byte that is never
+            // inside UTF-8
+            _ => return f(None),
+        };
+
+        self.code_point = code_point as u32;
+        self.remaining = remaining;
+        self.minimum = minimum;
+    }
+}
@@ -0,0 +1,10 @@
+//! The text handling of the consoles that doesn't touch any hardware, the
+//! escape sequence parser, the code page 437 translation and the console
+//! font parser. Nothing here allocates so the same code runs inside the
+//! kernel and inside the tests on the host
+
+#![no_std]
+
+pub mod ansi;
+pub mod cp437;
+pub mod psf;
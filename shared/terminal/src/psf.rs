@@ -0,0 +1,138 @@
+//! Parser for the PC Screen Fonts (PSF), the fonts the Linux console uses.
+//! Both version 1 and version 2 of the format are supported, the glyphs
+//! are bitmaps with one bit per pixel and the rows padded to a full byte
+
+/// The magic bytes at the start of a PSF1 font
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+
+/// The PSF1 mode bit for a font with 512 glyphs instead of 256
+const PSF1_MODE512: u8 = 0x01;
+
+/// The size of the PSF1 header
+const PSF1_HEADER_SIZE: usize = 4;
+
+/// The magic at the start of a PSF2 font
+const PSF2_MAGIC: u32 = 0x864ab572;
+
+/// The size of the PSF2 header, the header can be larger in later versions
+/// so we use the size inside the header to find the glyphs
+const PSF2_HEADER_SIZE: usize = 32;
+
+/// The glyph we use for the characters the font doesn't have
+const REPLACEMENT_CHARACTER: usize = b'?' as usize;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Font {
+    /// The bitmaps of all the glyphs
+    glyphs: &'static [u8],
+    glyph_count: usize,
+    bytes_per_glyph: usize,
+
+    /// The size of a glyph in pixels
+    width: usize,
+    height: usize,
+}
+
+impl Font {
+    /// Parse the font inside `data`, returns `None` if it's not a PSF font
+    /// or if the glyphs are outside of the data
+    pub fn parse(data: &'static [u8]) -> Option<Font> {
+        if data.starts_with(&PSF1_MAGIC) {
+            Font::parse_psf1(data)
+        } else if read_u32(data, 0)? == PSF2_MAGIC {
+            Font::parse_psf2(data)
+        } else {
+            None
+        }
+    }
+
+    fn parse_psf1(data: &'static [u8]) -> Option<Font> {
+        let mode = *data.get(2)?;
+        let height = *data.get(3)? as usize;
+
+        let glyph_count = if mode & PSF1_MODE512 != 0 { 512 } else { 256 };
+
+        // The glyphs are always 8 pixels wide so every row is one byte
+        Font::new(data, PSF1_HEADER_SIZE, glyph_count, height, 8, height)
+    }
+
+    fn parse_psf2(data: &'static [u8]) -> Option<Font> {
+        if data.len() < PSF2_HEADER_SIZE {
+            return None;
+        }
+
+        let header_size = read_u32(data, 8)? as usize;
+        let glyph_count = read_u32(data, 16)? as usize;
+        let bytes_per_glyph = read_u32(data, 20)? as usize;
+        let height = read_u32(data, 24)? as usize;
+        let width = read_u32(data, 28)? as usize;
+
+        Font::new(data, header_size, glyph_count, bytes_per_glyph,
+                  width, height)
+    }
+
+    fn new(data: &'static [u8], offset: usize, glyph_count: usize,
+           bytes_per_glyph: usize, width: usize, height: usize)
+        -> Option<Font>
+    {
+        if glyph_count == 0 || width == 0 || height == 0 {
+            return None;
+        }
+
+        // Every glyph needs to have room for all the rows
+        if bytes_per_glyph < row_size(width) * height {
+            return None;
+        }
+
+        let size = glyph_count.checked_mul(bytes_per_glyph)?;
+        let glyphs = data.get(offset..offset.checked_add(size)?)?;
+
+        Some(Font {
+            glyphs,
+            glyph_count,
+            bytes_per_glyph,
+            width,
+            height,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Get the bitmap of glyph `index`, the rows are `(width + 7) / 8`
+    /// bytes with the leftmost pixel inside the highest bit
+    pub fn glyph(&self, index: usize) -> &'static [u8] {
+        let index = if index < self.glyph_count {
+            index
+        } else {
+            REPLACEMENT_CHARACTER.min(self.glyph_count - 1)
+        };
+
+        let start = index * self.bytes_per_glyph;
+        &self.glyphs[start..start + self.bytes_per_glyph]
+    }
+
+    /// Check if the pixel at `x` and `y` inside the glyph is set
+    pub fn pixel(&self, glyph: &[u8], x: usize, y: usize) -> bool {
+        let row = y * row_size(self.width);
+        glyph[row + x / 8] & (0x80 >> (x % 8)) != 0
+    }
+}
+
+/// The number of bytes for a row of a glyph that is `width` pixels wide
+// `div_ceil` is newer than the toolchain the kernel is built with
+#[allow(clippy::manual_div_ceil)]
+fn row_size(width: usize) -> usize {
+    (width + 7) / 8
+}
+
+/// Read the little endian u32 at `offset`
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
@@ -0,0 +1,164 @@
+use terminal::ansi::{Action, Color, Erase, Parser};
+
+/// Run `input` through a new parser and collect the actions
+fn parse(input: &[u8]) -> Vec<Action> {
+    let mut parser = Parser::new();
+    let mut actions = Vec::new();
+
+    for &byte in input {
+        parser.advance(byte, |action| actions.push(action));
+    }
+
+    actions
+}
+
+#[test]
+fn printable_and_control_characters() {
+    assert_eq!(parse(b"a\r\n\tb\x08\x07"), [
+        Action::Print(b'a'),
+        Action::CarriageReturn,
+        Action::Newline,
+        Action::Tab,
+        Action::Print(b'b'),
+        Action::Backspace,
+    ]);
+}
+
+#[test]
+fn cursor_movement() {
+    assert_eq!(parse(b"\x1b[A\x1b[5B\x1b[0C\x1b[12D"), [
+        Action::CursorUp(1),
+        Action::CursorDown(5),
+        Action::CursorForward(1),
+        Action::CursorBack(12),
+    ]);
+
+    // The positions inside the sequences start at 1
+    assert_eq!(parse(b"\x1b[H\x1b[3;7f\x1b[;4H\x1b[9G"), [
+        Action::CursorPosition { row: 0, column: 0 },
+        Action::CursorPosition { row: 2, column: 6 },
+        Action::CursorPosition { row: 0, column: 3 },
+        Action::CursorColumn(8),
+    ]);
+}
+
+#[test]
+fn erase() {
+    assert_eq!(parse(b"\x1b[J\x1b[1J\x1b[2J\x1b[3J\x1b[K\x1b[2K\x1b[4K"), [
+        Action::EraseDisplay(Erase::ToEnd),
+        Action::EraseDisplay(Erase::ToStart),
+        Action::EraseDisplay(Erase::All),
+        Action::EraseDisplay(Erase::All),
+        Action::EraseLine(Erase::ToEnd),
+        Action::EraseLine(Erase::All),
+    ]);
+}
+
+#[test]
+fn select_graphic_rendition() {
+    assert_eq!(parse(b"\x1b[m\x1b[1;31;42m\x1b[0;97;104m\x1b[22;39;49m"), [
+        Action::ResetAttributes,
+        Action::Bold(true),
+        Action::Foreground(Color::Red, false),
+        Action::Background(Color::Green, false),
+        Action::ResetAttributes,
+        Action::Foreground(Color::White, true),
+        Action::Background(Color::Blue, true),
+        Action::Bold(false),
+        Action::DefaultForeground,
+        Action::DefaultBackground,
+    ]);
+
+    assert_eq!(parse(b"\x1b[7m\x1b[27m"), [
+        Action::Reverse(true),
+        Action::Reverse(false),
+    ]);
+}
+
+#[test]
+fn extended_colors_are_skipped() {
+    // The parameters of the 256 color and true color sequences should not
+    // be read as attributes of their own
+    assert_eq!(parse(b"\x1b[38;5;1;4m\x1b[48;2;1;2;3;1m"), [
+        Action::Bold(true),
+    ]);
+}
+
+#[test]
+fn private_sequences() {
+    assert_eq!(parse(b"\x1b[?25l\x1b[?25h\x1b[?1h"), [
+        Action::ShowCursor(false),
+        Action::ShowCursor(true),
+    ]);
+}
+
+#[test]
+fn save_and_restore_cursor() {
+    assert_eq!(parse(b"\x1b7\x1b8\x1b[s\x1b[u"), [
+        Action::SaveCursor,
+        Action::RestoreCursor,
+        Action::SaveCursor,
+        Action::RestoreCursor,
+    ]);
+}
+
+#[test]
+fn reset() {
+    assert_eq!(parse(b"\x1bc"), [
+        Action::ResetAttributes,
+        Action::EraseDisplay(Erase::All),
+        Action::CursorPosition { row: 0, column: 0 },
+    ]);
+}
+
+#[test]
+fn cancelled_and_interrupted_sequences() {
+    // CAN and SUB cancel the sequence and a new escape starts over
+    assert_eq!(parse(b"\x1b[3\x18A\x1b[1\x1aB\x1b[5\x1b[2C"), [
+        Action::Print(b'A'),
+        Action::Print(b'B'),
+        Action::CursorForward(2),
+    ]);
+
+    // A control character inside a sequence is run right away
+    assert_eq!(parse(b"\x1b[2\n;3H"), [
+        Action::Newline,
+        Action::CursorPosition { row: 1, column: 2 },
+    ]);
+}
+
+#[test]
+fn large_and_many_parameters() {
+    // The parameters saturate instead of overflowing
+    assert_eq!(parse(b"\x1b[99999999A"), [Action::CursorUp(u16::MAX)]);
+
+    // The parameters after the first 8 are ignored
+    assert_eq!(parse(b"\x1b[0;0;0;0;0;0;0;1;31m"), [
+        Action::ResetAttributes,
+        Action::ResetAttributes,
+        Action::ResetAttributes,
+        Action::ResetAttributes,
+        Action::ResetAttributes,
+        Action::ResetAttributes,
+        Action::ResetAttributes,
+        Action::Bold(true),
+    ]);
+}
+
+#[test]
+fn split_sequences() {
+    // The parser keeps its state between the calls, so a sequence can be
+    // split between two writes
+    let mut parser = Parser::new();
+    let mut actions = Vec::new();
+
+    for &byte in b"\x1b[1" {
+        parser.advance(byte, |action| actions.push(action));
+    }
+    assert!(actions.is_empty());
+
+    for &byte in b"2;3H" {
+        parser.advance(byte, |action| actions.push(action));
+    }
+    assert_eq!(actions, [Action::CursorPosition { row: 11, column: 2 }]);
+}
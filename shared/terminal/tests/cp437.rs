@@ -0,0 +1,106 @@
+use terminal::cp437::{self, Utf8Decoder};
+
+/// Run `input` through a new decoder and collect the characters
+fn decode(input: &[u8]) -> Vec<Option<char>> {
+    let mut decoder = Utf8Decoder::new();
+    let mut characters = Vec::new();
+
+    for &byte in input {
+        decoder.advance(byte, |character| characters.push(character));
+    }
+
+    characters
+}
+
+#[test]
+fn ascii_is_unchanged() {
+    for byte in b' '..=b'~' {
+        assert_eq!(cp437::from_char(byte as char), Some(byte));
+    }
+}
+
+#[test]
+fn glyphs() {
+    assert_eq!(cp437::from_char('☺'), Some(0x01));
+    assert_eq!(cp437::from_char('▼'), Some(0x1f));
+    assert_eq!(cp437::from_char('⌂'), Some(0x7f));
+    assert_eq!(cp437::from_char('Ç'), Some(0x80));
+    assert_eq!(cp437::from_char('░'), Some(0xb0));
+    assert_eq!(cp437::from_char('█'), Some(0xdb));
+    assert_eq!(cp437::from_char('■'), Some(0xfe));
+    assert_eq!(cp437::from_char('\u{a0}'), Some(0xff));
+}
+
+#[test]
+fn every_glyph_is_unique() {
+    // Every glyph from 0x01 should be found from the character that is
+    // drawn with it, so no glyph is listed twice or missing
+    let mut found = [false; 256];
+
+    for code_point in 0..0x30000 {
+        let character = match char::from_u32(code_point) {
+            Some(character) => character,
+            None => continue,
+        };
+
+        // The similar characters share the glyph of another character
+        if let Some(glyph) = cp437::from_char(character) {
+            found[glyph as usize] = true;
+        }
+    }
+
+    assert!(found[1..].iter().all(|&x| x));
+    assert!(!found[0]);
+}
+
+#[test]
+fn similar_characters() {
+    assert_eq!(cp437::from_char('β'), cp437::from_char('ß'));
+    assert_eq!(cp437::from_char('“'), Some(b'"'));
+    assert_eq!(cp437::from_char('—'), Some(b'-'));
+}
+
+#[test]
+fn missing_characters() {
+    assert_eq!(cp437::from_char('\0'), None);
+    assert_eq!(cp437::from_char('\n'), None);
+    assert_eq!(cp437::from_char('€'), None);
+    assert_eq!(cp437::from_char('😀'), None);
+}
+
+#[test]
+fn decode_valid_utf8() {
+    let text = "a ÅΩ€😀";
+    let expected: Vec<_> = text.chars().map(Some).collect();
+
+    assert_eq!(decode(text.as_bytes()), expected);
+}
+
+#[test]
+fn decode_split_sequence() {
+    let mut decoder = Utf8Decoder::new();
+    let mut characters = Vec::new();
+
+    let bytes = "€".as_bytes();
+    decoder.advance(bytes[0], |x| characters.push(x));
+    decoder.advance(bytes[1], |x| characters.push(x));
+    assert!(characters.is_empty());
+
+    decoder.advance(bytes[2], |x| characters.push(x));
+    assert_eq!(characters, [Some('€')]);
+}
+
+#[test]
+fn decode_invalid_utf8() {
+    // A lone continuation byte and bytes that are never inside UTF-8
+    assert_eq!(decode(b"\x80\xf8\xff"), [None, None, None]);
+
+    // A sequence that ends early, the byte after it is still decoded
+    assert_eq!(decode(b"\xe2\x82a"), [None, Some('a')]);
+
+    // Overlong encodings of '/' and of U+0000
+    assert_eq!(decode(b"\xc0\xaf\xe0\x80\x80"), [None, None]);
+
+    // A surrogate and a code point above U+10FFFF
+    assert_eq!(decode(b"\xed\xa0\x80\xf4\x90\x80\x80"), [None, None]);
+}
@@ -0,0 +1,167 @@
+use terminal::psf::Font;
+
+/// Make a PSF1 font with 8x`height` glyphs, the first row of every glyph
+/// is the index of the glyph so the glyphs can be told apart
+fn psf1(mode: u8, height: u8) -> Vec<u8> {
+    let glyph_count = if mode & 1 != 0 { 512 } else { 256 };
+
+    let mut data = vec![0x36, 0x04, mode, height];
+    for index in 0..glyph_count {
+        let mut glyph = vec![0; height as usize];
+        if let Some(first) = glyph.first_mut() {
+            *first = index as u8;
+        }
+        data.extend(glyph);
+    }
+
+    data
+}
+
+/// Make a PSF2 font with `glyph_count` glyphs of `width` x `height`, the
+/// header is `header_size` bytes
+fn psf2(header_size: u32, glyph_count: u32, width: u32, height: u32)
+    -> Vec<u8>
+{
+    let bytes_per_glyph = width.div_ceil(8) * height;
+
+    let mut data = Vec::new();
+    for value in [0x864ab572, 0, header_size, 0, glyph_count,
+                  bytes_per_glyph, height, width]
+    {
+        data.extend(u32::to_le_bytes(value));
+    }
+
+    data.resize(header_size as usize, 0);
+
+    for index in 0..glyph_count {
+        let mut glyph = vec![0; bytes_per_glyph as usize];
+        if let Some(first) = glyph.first_mut() {
+            *first = index as u8;
+        }
+        data.extend(glyph);
+    }
+
+    data
+}
+
+/// The fonts need to live forever like the module the kernel loads them
+/// from
+fn leak(data: Vec<u8>) -> &'static [u8] {
+    Box::leak(data.into_boxed_slice())
+}
+
+#[test]
+fn parse_psf1() {
+    let font = Font::parse(leak(psf1(0, 16))).expect("Failed to parse");
+
+    assert_eq!(font.width(), 8);
+    assert_eq!(font.height(), 16);
+    assert_eq!(font.glyph(b'A' as usize).len(), 16);
+    assert_eq!(font.glyph(b'A' as usize)[0], b'A');
+}
+
+#[test]
+fn parse_psf1_512() {
+    let font = Font::parse(leak(psf1(1, 8))).expect("Failed to parse");
+
+    // Glyph 300 is inside the font so it's not replaced
+    assert_eq!(font.glyph(300)[0], 300u16 as u8);
+    assert_eq!(font.glyph(512)[0], b'?');
+}
+
+#[test]
+fn parse_psf2() {
+    let font = Font::parse(leak(psf2(32, 256, 12, 20)))
+        .expect("Failed to parse");
+
+    assert_eq!(font.width(), 12);
+    assert_eq!(font.height(), 20);
+
+    // Every row is 2 bytes for a 12 pixel wide glyph
+    assert_eq!(font.glyph(7).len(), 40);
+    assert_eq!(font.glyph(7)[0], 7);
+}
+
+#[test]
+fn parse_psf2_larger_header() {
+    // A later version can have a larger header, the glyphs start at the
+    // size inside the header
+    let font = Font::parse(leak(psf2(48, 128, 8, 8)))
+        .expect("Failed to parse");
+
+    assert_eq!(font.glyph(100)[0], 100);
+}
+
+#[test]
+fn missing_glyphs_are_replaced() {
+    let font = Font::parse(leak(psf2(32, 128, 8, 8)))
+        .expect("Failed to parse");
+    assert_eq!(font.glyph(200)[0], b'?');
+
+    // A font without the replacement character uses the last glyph
+    let font = Font::parse(leak(psf2(32, 16, 8, 8)))
+        .expect("Failed to parse");
+    assert_eq!(font.glyph(200)[0], 15);
+}
+
+#[test]
+fn pixels() {
+    let mut data = psf2(32, 1, 10, 2);
+
+    // Row 0 is 0b10000000 0b01000000 and row 1 is 0b00000001 0b00000000
+    data[32..36].copy_from_slice(&[0x80, 0x40, 0x01, 0x00]);
+
+    let font = Font::parse(leak(data)).expect("Failed to parse");
+    let glyph = font.glyph(0);
+
+    let set: Vec<_> = (0..2)
+        .flat_map(|y| (0..10).map(move |x| (x, y)))
+        .filter(|&(x, y)| font.pixel(glyph, x, y))
+        .collect();
+
+    assert_eq!(set, [(0, 0), (9, 0), (7, 1)]);
+}
+
+#[test]
+fn invalid_fonts() {
+    assert!(Font::parse(leak(Vec::new())).is_none());
+    assert!(Font::parse(leak(vec![0x36])).is_none());
+    assert!(Font::parse(leak(b"not a font at all".to_vec())).is_none());
+
+    // The glyphs go past the end of the data
+    let mut data = psf1(0, 16);
+    data.pop();
+    assert!(Font::parse(leak(data)).is_none());
+
+    let mut data = psf2(32, 256, 8, 16);
+    data.truncate(100);
+    assert!(Font::parse(leak(data)).is_none());
+
+    // The header is shorter than the PSF2 header
+    let data = psf2(32, 1, 8, 8);
+    assert!(Font::parse(leak(data[..20].to_vec())).is_none());
+
+    // No glyphs or glyphs without pixels
+    assert!(Font::parse(leak(psf2(32, 0, 8, 8))).is_none());
+    assert!(Font::parse(leak(psf2(32, 1, 0, 8))).is_none());
+    assert!(Font::parse(leak(psf1(0, 0))).is_none());
+}
+
+#[test]
+fn glyph_too_small_for_the_size() {
+    // The glyphs need room for every row
+    let mut data = psf2(32, 4, 16, 16);
+    data[20..24].copy_from_slice(&u32::to_le_bytes(16));
+
+    assert!(Font::parse(leak(data)).is_none());
+}
+
+#[test]
+fn overflowing_header() {
+    // The glyph count times the glyph size overflows
+    let mut data = psf2(32, 1, 8, 8);
+    data[16..20].copy_from_slice(&u32::to_le_bytes(u32::MAX));
+    data[20..24].copy_from_slice(&u32::to_le_bytes(u32::MAX));
+
+    assert!(Font::parse(leak(data)).is_none());
+}
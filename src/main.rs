@@ -3,6 +3,10 @@ use std::path::Path;
 use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    // Build a relocatable kernel the boot code can move to a random 
+    // address (KASLR), it can be disabled with "nokaslr" on the command line
+    let kaslr = std::env::args().any(|x| x == "--kaslr");
+
     // Create the build directories we need
     std::fs::create_dir_all("build")?;
     std::fs::create_dir_all("build/kernel")?;
@@ -13,6 +17,21 @@ fn main() -> Result<(), Box<dyn Error>> {
 
     std::fs::copy("config/grub.cfg", "build/isofiles/boot/grub/grub.cfg")?;
 
+    // The framebuffer console draws with a PSF font GRUB loads as a module,
+    // any console font from the kbd package works e.g. "default8x16.psf"
+    if Path::new("config/font.psf").exists() {
+        std::fs::copy("config/font.psf", "build/isofiles/boot/font.psf")?;
+    }
+
+    // The boot logo can be a BMP or a TGA image, the kernel finds out which
+    // one from the data
+    for logo in &["config/logo.bmp", "config/logo.tga"] {
+        if Path::new(logo).exists() {
+            std::fs::copy(logo, "build/isofiles/boot/logo")?;
+            break;
+        }
+    }
+
     // Construct the path to the build directory
     let build_path = Path::new("build").canonicalize()?;
 
@@ -60,8 +79,17 @@ fn main() -> Result<(), Box<dyn Error>> {
         .join("kernel")
         .canonicalize()?;
 
+    // The panic handler walks the stack with the frame pointers so they
+    // are always kept. The relocatable kernel needs position independent
+    // code so all the absolute addresses end up as relocations
+    let mut rust_flags = String::from("-C force-frame-pointers=yes");
+    if kaslr {
+        rust_flags.push_str(" -C relocation-model=pie");
+    }
+
     Command::new("cargo")
         .current_dir(&kernel_path)
+        .env("RUSTFLAGS", &rust_flags)
         .args(&[
             "build",
             "--target-dir", kernel_build_path.to_str().unwrap()])
@@ -82,9 +110,19 @@ fn main() -> Result<(), Box<dyn Error>> {
         .join("libkernel.a")
         .canonicalize()?;
     
+    // The relocatable kernel is linked with lld because the boot code 
+    // uses absolute symbols from the linker script in 32 bit relocations
+    // and GNU ld doesn't allow that inside a PIE
+    let (linker, link_flags): (&str, &[&str]) = if kaslr {
+        ("ld.lld", &["-pie", "--no-dynamic-linker", "-z", "notext"])
+    } else {
+        ("ld", &[])
+    };
+
     println!("Linking the final binary");
-    Command::new("ld")
+    Command::new(linker)
         .current_dir(&build_path)
+        .args(link_flags)
         .args(&[
             "-n", 
             "-T", linker_path.to_str().unwrap(),
@@ -97,6 +135,20 @@ fn main() -> Result<(), Box<dyn Error>> {
     println!("Copying the final binary");
     std::fs::copy("build/kernel.bin", "build/isofiles/boot/kernel.bin")?;
 
+    println!("Extracting the symbols");
+    let symbols = Command::new("nm")
+        .current_dir(&build_path)
+        .args(&[
+            "--defined-only",
+            "--numeric-sort",
+            "--print-size",
+            "--demangle",
+            "kernel.bin"])
+        .output()?;
+
+    let symbols = symbol_table(&String::from_utf8_lossy(&symbols.stdout));
+    std::fs::write("build/isofiles/boot/symbols", symbols)?;
+
     println!("Creating the iso");
     Command::new("grub-mkrescue")
         .current_dir(&build_path)
@@ -107,3 +159,41 @@ fn main() -> Result<(), Box<dyn Error>> {
                     
     Ok(())
 }
+
+/// Turn the output of nm into the symbol table the kernel loads, the table
+/// has one function per line as "start size name" with the numbers in hex
+fn symbol_table(nm_output: &str) -> String {
+    let mut table = String::new();
+
+    for line in nm_output.lines() {
+        // The size is missing for the symbols without one, e.g. the labels
+        // inside the assembly code
+        let mut fields = line.splitn(3, ' ');
+        let (start, second, rest) =
+            match (fields.next(), fields.next(), fields.next()) {
+                (Some(start), Some(second), Some(rest)) =>
+                    (start, second, rest),
+                _ => continue,
+            };
+
+        let (size, kind, name) = if second.len() == 1 {
+            ("0", second, rest)
+        } else {
+            match rest.split_once(' ') {
+                Some((kind, name)) => (second, kind, name),
+                None => continue,
+            }
+        };
+
+        // Only the functions are interesting for the backtraces
+        if !matches!(kind, "T" | "t" | "W" | "w") {
+            continue;
+        }
+
+        let start = u64::from_str_radix(start, 16).unwrap_or(0);
+        let size = u64::from_str_radix(size, 16).unwrap_or(0);
+        table.push_str(&format!("{:x} {:x} {}\n", start, size, name));
+    }
+
+    table
+}